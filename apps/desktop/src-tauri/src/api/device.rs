@@ -1,15 +1,20 @@
 // 设备相关 API 命令 - 对应前端 src/api/device.ts
 
-use crate::discovery::start_file_receiver_task;
+use crate::discovery::{start_device_event_task, start_transfer_server};
 use crate::state::AppState;
-use stationuli_core::p2p::mdns::{DeviceInfo, MdnsDiscovery};
-use stationuli_core::p2p::tcp::TcpConnection;
+use stationuli_core::crypto::TrustedPeer;
+use stationuli_core::file::server::DEFAULT_DRAIN_TIMEOUT;
+use stationuli_core::p2p::addr;
+use stationuli_core::p2p::mdns::{DeviceInfo, DiscoveryMode, MdnsDiscovery};
+use stationuli_core::p2p::negotiate::NegotiatedTransport;
 use tauri::{AppHandle, State};
 
 /// 启动设备发现服务
 #[tauri::command]
 pub async fn start_discovery(
   port: u16,
+  discovery_mode: Option<DiscoveryMode>,
+  broadcast_port: Option<u16>,
   state: State<'_, AppState>,
   app: AppHandle,
 ) -> Result<String, String> {
@@ -18,7 +23,7 @@ pub async fn start_discovery(
   info!("🚀 启动服务 (端口: {})", port);
   println!("[DESKTOP] 🚀 启动服务 (端口: {})", port);
 
-  // 先停止旧的服务和 TCP listener（如果存在）
+  // 先停止旧的服务和传输服务（如果存在）
   if let Some(mut discovery) = state.inner().discovery.write().await.take() {
     info!("🔄 检测到旧服务，正在停止...");
     println!("[DESKTOP] 🔄 检测到旧服务，正在停止...");
@@ -45,52 +50,42 @@ pub async fn start_discovery(
     }
   }
 
-  // 清理 TCP listener（这会触发文件接收任务检测到 listener 不存在并退出循环）
-  info!("🔹 清理旧的 TCP Listener...");
-  println!("[DESKTOP] 🔹 清理旧的 TCP Listener...");
+  // 停止旧的传输服务：不再接受新连接，等待进行中的传输结束后释放端口
+  let old_server = state.inner().transfer_server.write().await.take();
+  if let Some(server) = old_server {
+    info!("🔹 停止旧的传输服务...");
+    println!("[DESKTOP] 🔹 停止旧的传输服务...");
+    server.shutdown(DEFAULT_DRAIN_TIMEOUT).await;
+    info!("✅ 旧的传输服务已停止");
+    println!("[DESKTOP] ✅ 旧的传输服务已停止");
+  }
 
-  // 尝试获取写锁清理 TCP Listener（带超时）
-  let cleanup_result = tokio::time::timeout(
-    tokio::time::Duration::from_secs(5),
-    state.inner().tcp_listener.write(),
-  )
-  .await;
+  // 先在同一端口上监听 TCP 和 QUIC，设备发现据此公布本机支持的传输方式
+  let transport = NegotiatedTransport::bind(port, state.inner().identity.clone())
+    .await
+    .map_err(|e| {
+      let err_msg = format!("Failed to start TCP listener: {}", e);
+      info!("❌ {}", err_msg);
+      eprintln!("[DESKTOP] ❌ {}", err_msg);
+      err_msg
+    })?;
 
-  match cleanup_result {
-    Ok(mut guard) => {
-      let old_listener = guard.take();
-      drop(guard);
-      if old_listener.is_some() {
-        info!("✅ 旧的 TCP Listener 已清理");
-        println!("[DESKTOP] ✅ 旧的 TCP Listener 已清理");
-      } else {
-        info!("ℹ️  没有旧的 TCP Listener 需要清理");
-        println!("[DESKTOP] ℹ️  没有旧的 TCP Listener 需要清理");
-      }
-    }
-    Err(_) => {
-      let timeout_msg = "⚠️  清理旧的 TCP Listener 超时（5秒）";
-      let detailed_msg = format!(
-        "{} - 可能原因：文件接收任务正在阻塞等待连接（accept()），持有读锁无法释放",
-        timeout_msg
-      );
-      info!("{}", detailed_msg);
-      eprintln!("[DESKTOP] {}", detailed_msg);
-      println!("[DESKTOP] {}", detailed_msg);
-      info!("⚠️  强制继续：尝试直接绑定新端口（如果端口被占用会失败）");
-      println!("[DESKTOP] ⚠️  强制继续：尝试直接绑定新端口（如果端口被占用会失败）");
-      // 注意：这里无法获取写锁，但我们可以尝试继续启动，如果端口被占用会失败
-    }
+  // 启动新的服务（获取本地IP，注册 mDNS 服务并自动发现局域网设备）
+  let mut discovery = MdnsDiscovery::new(port, state.inner().identity.device_id().to_string());
+  discovery.set_device_type("desktop".to_string());
+  discovery.set_transports(transport.transports());
+  // 未指定时同时使用 mDNS 和 UDP 广播，兼容屏蔽组播的网络
+  if let Some(mode) = discovery_mode {
+    discovery.set_discovery_mode(mode);
+  }
+  if let Some(broadcast_port) = broadcast_port {
+    discovery.set_broadcast_port(broadcast_port);
   }
 
-  // 等待更长时间，确保文件接收任务检测到 listener 不存在并退出循环
-  info!("⏳ 等待文件接收任务退出（500ms）...");
-  println!("[DESKTOP] ⏳ 等待文件接收任务退出（500ms）...");
-  tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+  // 把设备事件转发给前端，前端无需轮询设备列表（先订阅再启动，避免错过启动后立即发现的设备）
+  let outbound = state.inner().file_transfer.read().await.transport();
+  start_device_event_task(discovery.subscribe(), outbound, app.clone());
 
-  // 启动新的服务（只获取本地IP，不启动自动发现）
-  let mut discovery = MdnsDiscovery::new(port);
-  discovery.set_device_type("desktop".to_string());
   discovery.start().await.map_err(|e| {
     let err_msg = format!("Failed to start service: {}", e);
     info!("❌ {}", err_msg);
@@ -98,80 +93,32 @@ pub async fn start_discovery(
     err_msg
   })?;
 
+  // 握手时使用与设备发现一致的设备信息
+  state.inner().file_transfer.write().await.set_device_info(
+    discovery.device_id().to_string(),
+    discovery.device_name().to_string(),
+    discovery.device_type().to_string(),
+  );
+
   *state.inner().discovery.write().await = Some(discovery);
   info!("✅ 设备发现服务已启动");
   println!("[DESKTOP] ✅ 设备发现服务已启动");
 
-  // 启动 TCP 服务器监听文件接收
-  info!("📡 启动 TCP 监听器 (端口: {})...", port);
-  println!("[DESKTOP] 📡 启动 TCP 监听器 (端口: {})...", port);
-
-  // 如果之前的清理失败，端口可能还被占用，这里会失败
-  // 如果失败，尝试再次强制清理并重试
-  let listener_result = TcpConnection::listen(port).await;
-
-  let listener = match listener_result {
-    Ok(listener) => listener,
-    Err(e) => {
-      let err_msg = format!("Failed to start TCP listener: {}", e);
-      info!("❌ {}", err_msg);
-      eprintln!("[DESKTOP] ❌ {}", err_msg);
-
-      // 如果端口被占用，尝试再次强制清理
-      if err_msg.contains("address already in use")
-        || err_msg.contains("端口")
-        || err_msg.contains("Address already in use")
-        || err_msg.contains("already bound")
-      {
-        info!("⚠️  端口可能仍被占用，尝试强制清理...");
-        println!("[DESKTOP] ⚠️  端口可能仍被占用，尝试强制清理...");
-
-        // 再次尝试清理（不等待超时，直接尝试）
-        let force_cleanup = tokio::time::timeout(
-          tokio::time::Duration::from_secs(1),
-          state.inner().tcp_listener.write(),
-        )
-        .await;
-
-        if let Ok(mut guard) = force_cleanup {
-          let _ = guard.take();
-          drop(guard);
-          info!("✅ 强制清理完成，等待端口释放（1秒）...");
-          println!("[DESKTOP] ✅ 强制清理完成，等待端口释放（1秒）...");
-          tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-        }
-
-        // 重试绑定端口
-        info!("🔄 重试绑定端口 {}...", port);
-        println!("[DESKTOP] 🔄 重试绑定端口 {}...", port);
-        TcpConnection::listen(port).await.map_err(|e2| {
-          let err_msg2 = format!("重试绑定端口失败: {} (原始错误: {})", e2, e);
-          info!("❌ {}", err_msg2);
-          eprintln!("[DESKTOP] ❌ {}", err_msg2);
-          println!("[DESKTOP] ❌ {}", err_msg2);
-          err_msg2
-        })?
-      } else {
-        return Err(err_msg);
-      }
-    }
-  };
-
-  *state.inner().tcp_listener.write().await = Some(listener);
-  info!("✅ TCP 监听器已启动 (端口: {})", port);
-  println!("[DESKTOP] ✅ TCP 监听器已启动 (端口: {})", port);
-
-  // 启动文件接收任务
-  start_file_receiver_task(
-    state.inner().tcp_listener.clone(),
-    state.inner().file_transfer.clone(),
+  // 启动传输服务，在独立任务中处理每个入站连接
+  info!("📡 启动传输服务 (端口: {})...", port);
+  println!("[DESKTOP] 📡 启动传输服务 (端口: {})...", port);
+  let server = start_transfer_server(
+    transport,
+    state.inner().transfer_manager.clone(),
     app.clone(),
-  );
-  info!("✅ 文件接收任务已启动");
-  println!("[DESKTOP] ✅ 文件接收任务已启动");
+  )?;
+  *state.inner().transfer_server.write().await = Some(server);
+  info!("✅ 传输服务已启动 (端口: {})", port);
+  println!("[DESKTOP] ✅ 传输服务已启动 (端口: {})", port);
 
   info!("🎉 服务启动完成 (端口: {})", port);
   println!("[DESKTOP] 🎉 服务启动完成 (端口: {})", port);
+
   Ok("Service started".to_string())
 }
 
@@ -193,9 +140,8 @@ pub async fn stop_discovery(state: State<'_, AppState>) -> Result<(), String> {
   info!("     - 设备列表");
   info!("     - mDNS 响应器");
   info!("     - 本地 IP 缓存");
-  info!("  2. TCP Listener (TCP 监听器)");
-  info!("  3. 文件接收任务 (通过清理 TCP Listener 触发退出)");
-  println!("[DESKTOP] 📋 开始停止服务，需要清理的资源：MdnsDiscovery, TCP Listener, 文件接收任务");
+  info!("  2. 传输服务（停止接受连接，等待进行中的传输结束）");
+  println!("[DESKTOP] 📋 开始停止服务，需要清理的资源：MdnsDiscovery, 传输服务");
 
   let stop_result = tokio::time::timeout(tokio::time::Duration::from_secs(60), async {
     // 步骤1: 停止 MdnsDiscovery
@@ -252,82 +198,22 @@ pub async fn stop_discovery(state: State<'_, AppState>) -> Result<(), String> {
     }
   }
 
-  // 步骤2: 清理 TCP listener（这会触发文件接收任务检测到 listener 不存在并退出循环）
-  info!("🔹 Step 2: 清理 TCP Listener...");
-  println!("[DESKTOP] 🔹 Step 2: 清理 TCP Listener...");
-
-  // 先尝试获取读锁检查状态（带超时）
-  info!("  → 检查 TCP Listener 状态...");
-  println!("[DESKTOP]   → 检查 TCP Listener 状态...");
-  let check_result = tokio::time::timeout(
-    tokio::time::Duration::from_secs(2),
-    state.inner().tcp_listener.read(),
-  )
-  .await;
-
-  match check_result {
-    Ok(guard) => {
-      let has_listener = guard.is_some();
-      drop(guard);
-      if has_listener {
-        info!("  → TCP Listener 存在，尝试获取写锁清理...");
-        println!("[DESKTOP]   → TCP Listener 存在，尝试获取写锁清理...");
-      } else {
-        info!("  → TCP Listener 不存在，无需清理");
-        println!("[DESKTOP]   → TCP Listener 不存在，无需清理");
-      }
-    }
-    Err(_) => {
-      info!("  ⚠️  检查 TCP Listener 状态超时（2秒），可能被文件接收任务持有读锁");
-      println!("[DESKTOP]   ⚠️  检查 TCP Listener 状态超时（2秒），可能被文件接收任务持有读锁");
-      info!("  → 继续尝试获取写锁...");
-      println!("[DESKTOP]   → 继续尝试获取写锁...");
-    }
-  }
-
-  // 尝试获取写锁（带超时），如果文件接收任务正在阻塞等待连接，这里可能会超时
-  info!("  → 尝试获取写锁（超时时间：5秒）...");
-  println!("[DESKTOP]   → 尝试获取写锁（超时时间：5秒）...");
-  let write_result = tokio::time::timeout(
-    tokio::time::Duration::from_secs(5),
-    state.inner().tcp_listener.write(),
-  )
-  .await;
-
-  match write_result {
-    Ok(mut guard) => {
-      let tcp_listener_result = guard.take();
-      drop(guard);
-      if tcp_listener_result.is_some() {
-        info!("✅ TCP Listener 已清理（文件接收任务将检测到并退出）");
-        println!("[DESKTOP] ✅ TCP Listener 已清理（文件接收任务将检测到并退出）");
-      } else {
-        info!("ℹ️  没有运行中的 TCP Listener 需要清理");
-        println!("[DESKTOP] ℹ️  没有运行中的 TCP Listener 需要清理");
-      }
+  // 步骤2: 停止传输服务，最多等待进行中的传输结束 DEFAULT_DRAIN_TIMEOUT，之后中止剩余连接
+  info!("🔹 Step 2: 停止传输服务...");
+  println!("[DESKTOP] 🔹 Step 2: 停止传输服务...");
+  let server = state.inner().transfer_server.write().await.take();
+  match server {
+    Some(server) => {
+      server.shutdown(DEFAULT_DRAIN_TIMEOUT).await;
+      info!("✅ 传输服务已停止");
+      println!("[DESKTOP] ✅ 传输服务已停止");
     }
-    Err(_) => {
-      let timeout_msg = "⚠️  获取 TCP Listener 写锁超时（5秒）";
-      let detailed_msg = format!(
-        "{} - 原因分析：文件接收任务可能正在阻塞等待连接（accept()），持有读锁无法释放",
-        timeout_msg
-      );
-      info!("{}", detailed_msg);
-      eprintln!("[DESKTOP] {}", detailed_msg);
-      println!("[DESKTOP] {}", detailed_msg);
-      info!("⚠️  强制继续：文件接收任务会在下次循环时检测到 listener 为 None 并退出");
-      println!("[DESKTOP] ⚠️  强制继续：文件接收任务会在下次循环时检测到 listener 为 None 并退出");
-      // 注意：这里无法获取写锁，但我们可以继续，因为文件接收任务会在下次循环时检测到 listener 为 None
+    None => {
+      info!("ℹ️  没有运行中的传输服务需要停止");
+      println!("[DESKTOP] ℹ️  没有运行中的传输服务需要停止");
     }
   }
 
-  // 步骤3: 等待文件接收任务退出
-  info!("🔹 Step 3: 等待文件接收任务退出（最多等待500ms）...");
-  println!("[DESKTOP] 🔹 Step 3: 等待文件接收任务退出（最多等待500ms）...");
-  tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-  info!("✅ 已等待文件接收任务退出");
-  println!("[DESKTOP] ✅ 已等待文件接收任务退出");
-
   info!("========== ✅ 服务已完全停止 ==========");
   println!("[DESKTOP] ========== ✅ 服务已完全停止 ==========");
   Ok(())
@@ -350,12 +236,21 @@ pub async fn add_device(device: DeviceInfo, state: State<'_, AppState>) -> Resul
   let mut discovery_guard = state.inner().discovery.write().await;
   if discovery_guard.is_none() {
     // 创建一个临时的 discovery 实例（使用默认端口，但不启动服务）
-    let mut temp_discovery = MdnsDiscovery::new(8080);
+    let mut temp_discovery =
+      MdnsDiscovery::new(8080, state.inner().identity.device_id().to_string());
     temp_discovery.set_device_type("desktop".to_string());
     *discovery_guard = Some(temp_discovery);
   }
 
   if let Some(ref discovery) = *discovery_guard {
+    // 服务未启动时没有设备事件，直接把设备公布的传输方式交给传输层
+    state
+      .inner()
+      .file_transfer
+      .read()
+      .await
+      .transport()
+      .set_peer_transports(&device.address, device.port, device.transports.clone());
     discovery.add_device(device).await;
     Ok(())
   } else {
@@ -394,42 +289,25 @@ pub async fn update_device(device: DeviceInfo, state: State<'_, AppState>) -> Re
 /// 获取设备 ID
 #[tauri::command]
 pub async fn get_device_id(state: State<'_, AppState>) -> Result<String, String> {
-  if let Some(ref discovery) = *state.inner().discovery.read().await {
-    Ok(discovery.device_id().to_string())
-  } else {
-    Err("Service not started".to_string())
-  }
+  // 设备 ID 来自持久化的设备身份，服务未启动时同样可用
+  Ok(state.inner().identity.device_id().to_string())
 }
 
 /// 获取本地 IP 地址
 #[tauri::command]
 pub async fn get_local_ip(state: State<'_, AppState>) -> Result<String, String> {
-  use std::net::UdpSocket;
-
   // 首先尝试从设备发现中获取实际使用的 IP 地址
-  // 如果设备发现已启动，可以从 socket 获取实际可连接的 IP
-  if let Some(ref discovery) = *state.inner().discovery.read().await {
-    if let Some(ip) = discovery.get_local_ip().await {
-      if ip != "0.0.0.0" {
-        return Ok(ip);
-      }
-    }
+  if let Some(ref discovery) = *state.inner().discovery.read().await
+    && let Some(ip) = discovery.get_local_ip().await
+  {
+    return Ok(ip);
   }
 
-  // 默认方法：通过连接到一个远程地址来获取本地 IP
-  let socket =
-    UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("Failed to create socket: {}", e))?;
-
-  // 连接到 Google DNS（不会实际连接，只是用来确定路由）
-  socket
-    .connect("8.8.8.8:80")
-    .map_err(|e| format!("Failed to connect: {}", e))?;
-
-  let local_addr = socket
-    .local_addr()
-    .map_err(|e| format!("Failed to get local address: {}", e))?;
-
-  Ok(local_addr.ip().to_string())
+  // 设备发现未启动时直接枚举本机网卡，离线的局域网中同样可用
+  addr::local_addresses()
+    .first()
+    .map(|(_, ip)| ip.to_string())
+    .ok_or_else(|| "No usable network interface found".to_string())
 }
 
 /// 测试与目标设备的连接
@@ -481,3 +359,72 @@ pub async fn test_connection(
     }
   }
 }
+
+/// 与目标设备配对（双方确认短认证串后互相信任）
+#[tauri::command]
+pub async fn pair_device(
+  target_address: String,
+  target_port: u16,
+  state: State<'_, AppState>,
+) -> Result<TrustedPeer, String> {
+  use tracing::info;
+
+  info!("[DESKTOP] Pairing with {}:{}", target_address, target_port);
+
+  let peer = state
+    .inner()
+    .file_transfer
+    .read()
+    .await
+    .pair(&target_address, target_port)
+    .await
+    .map_err(|e| format!("配对失败: {}", e))?;
+
+  state
+    .inner()
+    .trust_store
+    .list()
+    .await
+    .into_iter()
+    .find(|trusted| trusted.device_id == peer.device_id)
+    .ok_or_else(|| "配对失败: 受信任设备未保存".to_string())
+}
+
+/// 应答配对请求（前端确认 pairing-request 事件中的短认证串后调用）
+#[tauri::command]
+pub async fn respond_pairing(
+  request_id: String,
+  accept: bool,
+  state: State<'_, AppState>,
+) -> Result<(), String> {
+  let sender = state
+    .inner()
+    .pending_pairings
+    .lock()
+    .await
+    .remove(&request_id)
+    .ok_or_else(|| format!("Pairing request not found: {}", request_id))?;
+  sender
+    .send(accept)
+    .map_err(|_| "Pairing request expired".to_string())
+}
+
+/// 获取受信任设备列表
+#[tauri::command]
+pub async fn get_trusted_peers(state: State<'_, AppState>) -> Result<Vec<TrustedPeer>, String> {
+  Ok(state.inner().trust_store.list().await)
+}
+
+/// 取消信任设备
+#[tauri::command]
+pub async fn remove_trusted_peer(
+  device_id: String,
+  state: State<'_, AppState>,
+) -> Result<(), String> {
+  state
+    .inner()
+    .trust_store
+    .remove(&device_id)
+    .await
+    .map_err(|e| e.to_string())
+}
@@ -12,35 +12,11 @@ pub async fn send_file(
   state: State<'_, AppState>,
   app: AppHandle,
 ) -> Result<String, String> {
-  let transfer = state.inner().file_transfer.read().await;
-  let app_clone = app.clone();
-  let file_path_clone = file_path.clone();
-
-  // 使用进度回调发送进度更新
-  let result = transfer
-    .send_file_with_progress(
-      &file_path,
-      &target_address,
-      target_port,
-      Some(Box::new(move |sent_bytes, total_bytes| {
-        let progress = if total_bytes > 0 {
-          (sent_bytes * 100 / total_bytes) as u32
-        } else {
-          0
-        };
-        app_clone
-          .emit(
-            "transfer-progress",
-            serde_json::json!({
-              "file": file_path_clone.clone(),
-              "progress": progress,
-              "sent": sent_bytes,
-              "total": total_bytes
-            }),
-          )
-          .ok();
-      })),
-    )
+  // 进度事件由传输管理器统一发出
+  let result = state
+    .inner()
+    .transfer_manager
+    .send_file(&file_path, &target_address, target_port)
     .await;
 
   result.map_err(|e| format!("Failed to send file: {}", e))?;
@@ -57,6 +33,35 @@ pub async fn send_file(
   Ok("File sent successfully".to_string())
 }
 
+/// 在一个会话中发送多个文件或整个目录
+#[tauri::command]
+pub async fn send_files(
+  file_paths: Vec<String>,
+  target_address: String,
+  target_port: u16,
+  state: State<'_, AppState>,
+  app: AppHandle,
+) -> Result<String, String> {
+  // 进度事件由传输管理器统一发出，同时携带当前文件
+  state
+    .inner()
+    .transfer_manager
+    .send_paths(&file_paths, &target_address, target_port)
+    .await
+    .map_err(|e| format!("Failed to send files: {}", e))?;
+
+  app
+    .emit(
+      "transfer-complete",
+      serde_json::json!({
+        "files": file_paths
+      }),
+    )
+    .map_err(|e| format!("Failed to emit event: {}", e))?;
+
+  Ok("Files sent successfully".to_string())
+}
+
 /// 获取文件大小
 #[tauri::command]
 pub async fn get_file_size(file_path: String) -> Result<u64, String> {
@@ -133,3 +138,22 @@ pub async fn save_received_file(
   );
   Ok(format!("文件已保存到: {}", save_path.display()))
 }
+
+/// 应答入站文件请求（前端处理 incoming-transfer 事件后调用）
+#[tauri::command]
+pub async fn respond_transfer(
+  request_id: String,
+  accept: bool,
+  state: State<'_, AppState>,
+) -> Result<(), String> {
+  let sender = state
+    .inner()
+    .pending_transfers
+    .lock()
+    .await
+    .remove(&request_id)
+    .ok_or_else(|| format!("Transfer request not found: {}", request_id))?;
+  sender
+    .send(accept)
+    .map_err(|_| "Transfer request expired".to_string())
+}
@@ -0,0 +1,80 @@
+// 传输历史相关 API 命令 - 对应前端 src/api/history.ts
+
+use crate::state::AppState;
+use stationuli_core::file::{HistoryQuery, HistoryRecord};
+use tauri::{AppHandle, State};
+use tauri_plugin_opener::OpenerExt;
+
+/// 按条件查询传输历史（最新的在前）
+#[tauri::command]
+pub async fn query_history(
+  query: Option<HistoryQuery>,
+  state: State<'_, AppState>,
+) -> Result<Vec<HistoryRecord>, String> {
+  Ok(
+    state
+      .inner()
+      .history
+      .query(&query.unwrap_or_default())
+      .await,
+  )
+}
+
+/// 获取一条传输历史
+#[tauri::command]
+pub async fn get_history_record(
+  record_id: String,
+  state: State<'_, AppState>,
+) -> Result<HistoryRecord, String> {
+  state
+    .inner()
+    .history
+    .get(&record_id)
+    .await
+    .ok_or_else(|| format!("History record not found: {}", record_id))
+}
+
+/// 删除一条传输历史（不删除文件本身）
+#[tauri::command]
+pub async fn delete_history_record(
+  record_id: String,
+  state: State<'_, AppState>,
+) -> Result<(), String> {
+  state
+    .inner()
+    .history
+    .remove(&record_id)
+    .await
+    .map_err(|e| format!("Failed to delete history record: {}", e))
+}
+
+/// 清空传输历史
+#[tauri::command]
+pub async fn clear_history(state: State<'_, AppState>) -> Result<(), String> {
+  state
+    .inner()
+    .history
+    .clear()
+    .await
+    .map_err(|e| format!("Failed to clear history: {}", e))
+}
+
+/// 在文件管理器中显示历史记录中的文件
+#[tauri::command]
+pub async fn reveal_history_file(
+  record_id: String,
+  file_index: usize,
+  state: State<'_, AppState>,
+  app: AppHandle,
+) -> Result<(), String> {
+  let path = state
+    .inner()
+    .history
+    .file_path(&record_id, file_index)
+    .await
+    .map_err(|e| format!("Failed to locate file: {}", e))?;
+  app
+    .opener()
+    .reveal_item_in_dir(path)
+    .map_err(|e| format!("Failed to reveal file: {}", e))
+}
@@ -1,94 +1,190 @@
 // 设备发现服务实现（内部模块）
 
+use stationuli_core::file::transfer::ReceiveOutcome;
+use stationuli_core::file::{TransferInfo, TransferManager, TransferProgress, TransferServer};
+use stationuli_core::p2p::mdns::DeviceEvent;
+use stationuli_core::p2p::negotiate::NegotiatedTransport;
+use std::sync::Arc;
 use tauri::Emitter;
 use tauri::{AppHandle, Manager};
 
-/// 启动文件接收任务
-pub fn start_file_receiver_task(
-  listener: std::sync::Arc<tokio::sync::RwLock<Option<tokio::net::TcpListener>>>,
-  transfer: std::sync::Arc<tokio::sync::RwLock<stationuli_core::file::transfer::FileTransfer>>,
+/// 启动传输服务：每个入站连接在独立任务中交给传输任务管理器处理
+///
+/// 连接的处理可能需要等待用户确认配对或接收，不会阻塞后续连接
+pub fn start_transfer_server(
+  transport: NegotiatedTransport,
+  transfer_manager: TransferManager<NegotiatedTransport>,
+  app: AppHandle,
+) -> Result<TransferServer, String> {
+  // 桌面端使用应用数据目录保存接收的文件
+  let save_dir = app
+    .path()
+    .app_data_dir()
+    .map_err(|e| format!("Failed to resolve app data dir: {}", e))?
+    .join("received_files");
+  std::fs::create_dir_all(&save_dir)
+    .map_err(|e| format!("Failed to create save directory: {}", e))?;
+  let save_path = save_dir
+    .to_str()
+    .unwrap_or("/tmp/stationuli_received")
+    .to_string();
+
+  TransferServer::start(transport, move |connection| {
+    let transfer_manager = transfer_manager.clone();
+    let save_path = save_path.clone();
+    let app = app.clone();
+    async move {
+      let result = transfer_manager.receive(&save_path, connection).await;
+      emit_receive_outcome(&app, result)
+    }
+  })
+  .map_err(|e| format!("Failed to start transfer server: {}", e))
+}
+
+/// 把一次入站连接的处理结果通知前端，错误原样返回给传输服务（违反协议的对端会被暂时封禁）
+fn emit_receive_outcome(
+  app: &AppHandle,
+  result: stationuli_core::Result<ReceiveOutcome>,
+) -> stationuli_core::Result<()> {
+  match result {
+    Ok(ReceiveOutcome::File(file_path)) => {
+      let file_name = std::path::Path::new(&file_path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown")
+        .to_string();
+      let _ = app.emit(
+        "file-received",
+        serde_json::json!({
+          "file_path": file_path,
+          "file_name": file_name
+        }),
+      );
+    }
+    Ok(ReceiveOutcome::Files(file_paths)) => {
+      for file_path in file_paths {
+        let file_name = std::path::Path::new(&file_path)
+          .file_name()
+          .and_then(|n| n.to_str())
+          .unwrap_or("unknown")
+          .to_string();
+        let _ = app.emit(
+          "file-received",
+          serde_json::json!({
+            "file_path": file_path,
+            "file_name": file_name
+          }),
+        );
+      }
+    }
+    Ok(ReceiveOutcome::Paired(peer)) => {
+      let _ = app.emit(
+        "device-paired",
+        serde_json::json!({
+          "device_id": peer.device_id,
+          "device_name": peer.device_name,
+          "device_type": peer.device_type
+        }),
+      );
+    }
+    Ok(ReceiveOutcome::Declined(offer)) => {
+      // 用户拒绝或未及时应答，前端据此关闭对应的确认提示
+      let _ = app.emit(
+        "transfer-declined",
+        serde_json::json!({
+          "file_name": offer.file_name,
+          "sender": offer.sender
+        }),
+      );
+    }
+    Err(e) => {
+      // 接收失败（包括拒绝未配对的发送方）
+      eprintln!("File receive error: {}", e);
+      return Err(e);
+    }
+  }
+  Ok(())
+}
+
+/// 启动设备事件转发任务：把设备出现、更新、离线事件转发给前端
+///
+/// 同时把设备公布的传输方式和设备 ID 交给传输层，连接时据此决定是否尝试 QUIC 并校验对端证书
+pub fn start_device_event_task(
+  mut receiver: tokio::sync::broadcast::Receiver<DeviceEvent>,
+  transport: Arc<NegotiatedTransport>,
   app: AppHandle,
 ) {
   tokio::spawn(async move {
     loop {
-      // 检查 listener 是否存在
-      // 使用超时获取读锁，避免长时间阻塞
-      let listener_check =
-        tokio::time::timeout(tokio::time::Duration::from_millis(100), listener.read()).await;
-
-      let listener_guard = match listener_check {
-        Ok(guard) => guard,
-        Err(_) => {
-          // 获取读锁超时，可能正在被清理，等待后重试
-          tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-          continue;
+      match receiver.recv().await {
+        Ok(event) => {
+          if let DeviceEvent::Appeared(ref device) | DeviceEvent::Updated(ref device) = event {
+            transport.set_peer_transports(&device.address, device.port, device.transports.clone());
+            transport.set_peer_device_id(&device.address, device.port, &device.id);
+          }
+          let (name, device) = match event {
+            DeviceEvent::Appeared(device) => ("device-appeared", device),
+            DeviceEvent::Updated(device) => ("device-updated", device),
+            DeviceEvent::Lost(device) => ("device-lost", device),
+          };
+          let _ = app.emit(name, device);
         }
-      };
-
-      if let Some(listener_ref) = listener_guard.as_ref() {
-        let transfer_guard = transfer.read().await;
-        // 桌面端使用应用数据目录保存接收的文件
-        let save_dir = app.path().app_data_dir().unwrap().join("received_files");
-
-        // 确保目录存在
-        if let Err(e) = std::fs::create_dir_all(&save_dir) {
-          eprintln!("Failed to create save directory: {}", e);
-          drop(listener_guard);
-          tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-          continue;
+        Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+          // 落后太多时丢失了部分事件，前端收到后重新拉取完整列表即可
+          eprintln!("Device events lagged, skipped {} events", skipped);
         }
+        // 设备发现服务已停止
+        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+      }
+    }
+  });
+}
 
-        // 关键改进：使用超时的 receive_file 调用，这样即使没有连接，也会定期返回
-        // 超时时间设置为 1 秒，这样可以在停止服务时快速响应
-        // 在 receive_file 内部调用 accept() 期间，我们持有读锁，但超时后会释放，让停止服务可以获取写锁
-        let receive_timeout = tokio::time::timeout(
-          tokio::time::Duration::from_secs(1),
-          transfer_guard.receive_file(
-            save_dir.to_str().unwrap_or("/tmp/stationuli_received"),
-            listener_ref,
-          ),
-        )
-        .await;
+/// 启动传输任务事件转发任务：把任务状态变化转发给前端
+pub fn start_transfer_event_task(
+  mut receiver: tokio::sync::broadcast::Receiver<TransferInfo>,
+  app: AppHandle,
+) {
+  // 在应用初始化时调用，此时不在 tokio 运行时上下文中，使用 Tauri 的运行时
+  tauri::async_runtime::spawn(async move {
+    loop {
+      match receiver.recv().await {
+        Ok(info) => {
+          let _ = app.emit("transfer-updated", info);
+        }
+        Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+          // 落后太多时丢失了部分事件，前端收到后重新拉取完整列表即可
+          eprintln!("Transfer events lagged, skipped {} events", skipped);
+        }
+        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+      }
+    }
+  });
+}
 
-        match receive_timeout {
-          Ok(Ok(file_path)) => {
-            // 文件接收成功，先释放读锁，然后发送事件
-            drop(listener_guard);
-            let file_name = std::path::Path::new(&file_path)
-              .file_name()
-              .and_then(|n| n.to_str())
-              .unwrap_or("unknown")
-              .to_string();
-            let _ = app.emit(
-              "file-received",
-              serde_json::json!({
-                "file_path": file_path,
-                "file_name": file_name
-              }),
-            );
-          }
-          Ok(Err(e)) => {
-            // receive_file 失败
-            drop(listener_guard);
-            eprintln!("File receive error: {}", e);
-            // 如果连接被关闭或出错，等待一小段时间后继续
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-          }
-          Err(_) => {
-            // receive_file 超时（通常是 accept() 超时），这是正常的
-            // 释放读锁后重新检查 listener 是否存在
-            drop(listener_guard);
-            // 短暂等待后继续循环，检查 listener 是否还存在
-            // 这样可以让停止服务时有机会获取写锁
-            tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
-            continue;
-          }
+/// 把传输进度转发给前端（"transfer-progress" 事件）
+///
+/// 除了结构化的进度字段外，额外附带百分比 `progress`，兼容已有的前端进度条
+pub fn start_transfer_progress_task(
+  mut receiver: tokio::sync::broadcast::Receiver<TransferProgress>,
+  app: AppHandle,
+) {
+  tauri::async_runtime::spawn(async move {
+    loop {
+      match receiver.recv().await {
+        Ok(progress) => {
+          let percent = (progress.transferred * 100)
+            .checked_div(progress.total)
+            .unwrap_or(0) as u32;
+          let mut payload = serde_json::to_value(&progress).unwrap_or_default();
+          payload["progress"] = percent.into();
+          let _ = app.emit("transfer-progress", payload);
+        }
+        Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+          // 进度事件只需要最新值，丢失中间的几条不影响显示
+          eprintln!("Transfer progress lagged, skipped {} events", skipped);
         }
-      } else {
-        drop(listener_guard);
-        // 如果没有 listener，说明服务已停止，退出循环
-        // 这样可以避免任务无限运行，并在下次启动时创建新任务
-        break;
+        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
       }
     }
   });
@@ -0,0 +1,55 @@
+// 入站文件确认实现（内部模块）
+
+use stationuli_core::file::{TransferDecision, TransferOffer, TransferOfferHandler};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{Mutex, oneshot};
+
+/// 等待用户决定的入站文件请求（request_id -> 应答通道）
+pub type PendingTransfers = Arc<Mutex<HashMap<String, oneshot::Sender<bool>>>>;
+
+/// 等待用户决定是否接收文件的超时时间
+const TRANSFER_CONFIRM_TIMEOUT_SECS: u64 = 60;
+
+/// 创建入站文件确认回调：向前端发送 incoming-transfer 事件，并等待 respond_transfer 应答
+pub fn create_offer_handler(app: AppHandle, pending: PendingTransfers) -> TransferOfferHandler {
+  let next_id = Arc::new(AtomicU64::new(1));
+  Arc::new(move |offer: TransferOffer| {
+    let app = app.clone();
+    let pending = pending.clone();
+    let request_id = format!("transfer-{}", next_id.fetch_add(1, Ordering::Relaxed));
+    Box::pin(async move {
+      let (tx, rx) = oneshot::channel();
+      pending.lock().await.insert(request_id.clone(), tx);
+
+      let _ = app.emit(
+        "incoming-transfer",
+        serde_json::json!({
+          "request_id": request_id,
+          "file_name": offer.file_name,
+          "file_size": offer.file_size,
+          "file_count": offer.file_count,
+          "sender": offer.sender,
+        }),
+      );
+
+      let decision = match tokio::time::timeout(
+        tokio::time::Duration::from_secs(TRANSFER_CONFIRM_TIMEOUT_SECS),
+        rx,
+      )
+      .await
+      {
+        Ok(Ok(true)) => TransferDecision::Accept,
+        Ok(Ok(false)) => TransferDecision::Decline("Declined by user".to_string()),
+        Ok(Err(_)) => TransferDecision::Decline("Receiver unavailable".to_string()),
+        Err(_) => TransferDecision::Decline("No response from user".to_string()),
+      };
+
+      // 超时或应答通道关闭时清理
+      pending.lock().await.remove(&request_id);
+      decision
+    })
+  })
+}
@@ -2,7 +2,9 @@
 
 mod api;
 mod discovery;
+mod incoming;
 mod logging;
+mod pairing;
 mod state;
 
 use state::AppState;
@@ -10,10 +12,19 @@ use tauri::Manager;
 
 // 导入 API 命令
 use api::device::{
-  add_device, get_device_id, get_devices, get_local_ip, remove_device, start_discovery,
-  stop_discovery, test_connection, update_device,
+  add_device, get_device_id, get_devices, get_local_ip, get_trusted_peers, pair_device,
+  remove_device, remove_trusted_peer, respond_pairing, start_discovery, stop_discovery,
+  test_connection, update_device,
 };
-use api::file::{get_file_size, save_received_file, send_file};
+use api::file::{get_file_size, respond_transfer, save_received_file, send_file, send_files};
+use api::history::{
+  clear_history, delete_history_record, get_history_record, query_history, reveal_history_file,
+};
+use api::transfer::{
+  cancel_transfer, clear_finished_transfers, list_transfers, pause_transfer, resume_transfer,
+  set_max_concurrent_transfers,
+};
+use discovery::{start_transfer_event_task, start_transfer_progress_task};
 use logging::init_logging_to_ui;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -23,7 +34,14 @@ pub fn run() {
     .plugin(tauri_plugin_dialog::init())
     .setup(|app| {
       // 初始化状态
-      let app_state = AppState::new();
+      let app_state = AppState::new(app.handle())?;
+
+      // 把传输任务状态变化和进度转发给前端
+      start_transfer_event_task(app_state.transfer_manager.subscribe(), app.handle().clone());
+      start_transfer_progress_task(
+        app_state.transfer_manager.subscribe_progress(),
+        app.handle().clone(),
+      );
 
       app.manage(app_state);
 
@@ -46,10 +64,30 @@ pub fn run() {
       get_device_id,
       get_local_ip,
       test_connection,
+      // 配对相关 API
+      pair_device,
+      respond_pairing,
+      get_trusted_peers,
+      remove_trusted_peer,
       // 文件相关 API（对应前端 src/api/file.ts）
       send_file,
+      send_files,
       get_file_size,
       save_received_file,
+      respond_transfer,
+      // 传输任务相关 API（对应前端 src/api/transfer.ts）
+      list_transfers,
+      cancel_transfer,
+      pause_transfer,
+      resume_transfer,
+      clear_finished_transfers,
+      set_max_concurrent_transfers,
+      // 传输历史相关 API（对应前端 src/api/history.ts）
+      query_history,
+      get_history_record,
+      delete_history_record,
+      clear_history,
+      reveal_history_file,
     ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
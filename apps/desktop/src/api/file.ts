@@ -51,6 +51,21 @@ export async function sendFile(
   });
 }
 
+/**
+ * 在一个会话中发送多个文件或整个目录
+ */
+export async function sendFiles(
+  filePaths: string[],
+  targetAddress: string,
+  targetPort: number
+): Promise<string> {
+  return await invoke<string>("send_files", {
+    filePaths,
+    targetAddress,
+    targetPort,
+  });
+}
+
 /**
  * 保存接收的文件
  */
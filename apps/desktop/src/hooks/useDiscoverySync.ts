@@ -1,17 +1,18 @@
 // Hook 用于同步设备发现状态到 store
+import { listen } from "@tauri-apps/api/event";
 import { useEffect } from "react";
 import { deviceApiAdapter } from "../api/deviceAdapter";
 import { useDiscoveryStore } from "../stores/discoveryStore";
 
 /**
  * 同步设备发现状态到 store
- * 这个 hook 会定期刷新设备列表，并在服务启动/停止时更新状态
+ * 这个 hook 会在收到设备事件时刷新设备列表，并在服务启动/停止时更新状态
  */
 export function useDiscoverySync() {
   const { isDiscovering, refreshDevices, setDeviceId, setLocalIp } =
     useDiscoveryStore();
 
-  // 定期刷新设备列表（当服务运行时）
+  // 收到设备出现、更新、离线事件时刷新设备列表（当服务运行时）
   useEffect(() => {
     if (!isDiscovering) {
       return;
@@ -20,12 +21,15 @@ export function useDiscoverySync() {
     // 立即刷新一次
     refreshDevices();
 
-    // 设置定时刷新（每 3 秒）
-    const interval = setInterval(() => {
-      refreshDevices();
-    }, 3000);
+    const unlisteners = [
+      "device-appeared",
+      "device-updated",
+      "device-lost",
+    ].map((event) => listen(event, () => refreshDevices()));
 
-    return () => clearInterval(interval);
+    return () => {
+      unlisteners.forEach((unlisten) => unlisten.then((fn) => fn()));
+    };
   }, [isDiscovering, refreshDevices]);
 
   // 当服务启动时，获取设备 ID 和本地 IP
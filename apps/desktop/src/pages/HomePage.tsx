@@ -4,6 +4,7 @@ import { useEffect, useState } from "react";
 import { ReceivedFilesCard } from "stationuli-common/components";
 import type { ReceivedFile } from "stationuli-common/types";
 import { fileApiAdapter } from "../api/fileAdapter";
+import type { TransferProgress } from "../api/transfer";
 import { FileDetailsDialog } from "../components/FileDetailsDialog";
 import { useDiscoveryStore } from "../stores/discoveryStore";
 import { useFileTransferStore } from "../stores/fileTransferStore";
@@ -37,10 +38,15 @@ export function HomePage() {
 
   // 监听文件传输相关事件
   useEffect(() => {
-    const progressUnlisten = listen("transfer-progress", (event) => {
-      const data = event.payload as { progress: number };
-      setTransferProgress(data.progress);
-    });
+    const progressUnlisten = listen<TransferProgress>(
+      "transfer-progress",
+      (event) => {
+        // 进度条只显示发送进度
+        if (event.payload.direction === "outbound") {
+          setTransferProgress(event.payload.progress);
+        }
+      }
+    );
 
     const completeUnlisten = listen("transfer-complete", (event) => {
       const data = event.payload as { file: string };
@@ -4,8 +4,8 @@
 use crate::file_ops::get_file_name_from_uri;
 use crate::state::AppState;
 use serde::{Deserialize, Serialize};
-use stationuli_core::file::transfer::TransferMessage;
-use stationuli_core::p2p::tcp::TcpConnection;
+use stationuli_core::file::TransferDirection;
+use stationuli_core::p2p::addr::format_host_port;
 use tauri::{AppHandle, Emitter, State};
 
 /// 文件信息结构（增强版）
@@ -53,15 +53,14 @@ pub async fn send_file(
   file_path: String,
   target_address: String,
   target_port: u16,
-  _state: State<'_, AppState>,
+  state: State<'_, AppState>,
   app: AppHandle,
 ) -> Result<String, String> {
   // 在 Android 上，如果文件路径是 content:// URI，需要特殊处理
-  // 同时获取文件数据和文件名
-  let (file_data, file_name) = if file_path.starts_with("content://") {
+  // 打开文件并获取文件名和大小，文件内容由 core 按分片流式读取
+  let (file, file_name, file_size) = if file_path.starts_with("content://") {
     #[cfg(target_os = "android")]
     {
-      use std::io::Read;
       use tauri_plugin_android_fs::{AndroidFsExt, FileUri};
       use tracing::info;
 
@@ -88,8 +87,13 @@ pub async fn send_file(
         .and_then(|n| if !n.is_empty() { Some(n) } else { None })
         .unwrap_or_else(|| get_file_name_from_uri(&file_path));
 
-      // 然后读取文件内容
-      let mut file = api
+      let size = api
+        .get_len(&file_uri)
+        .await
+        .map_err(|e| format!("Failed to get file size: {} (URI: {})", e, file_path))?;
+
+      // 然后打开文件
+      let file = api
         .open_file_readable(&file_uri)
         .await
         .map_err(|e| {
@@ -98,120 +102,47 @@ pub async fn send_file(
             e, file_path
           )
         })?;
-      let mut contents = Vec::new();
-      file
-        .read_to_end(&mut contents)
-        .map_err(|e| format!("Failed to read file contents: {}", e))?;
 
       info!(
-        "[MOBILE] Successfully read file: {} (size: {} bytes)",
-        name,
-        contents.len()
+        "[MOBILE] Successfully opened file: {} (size: {} bytes)",
+        name, size
       );
 
-      (contents, name)
+      (tokio::fs::File::from_std(file), name, size)
     }
     #[cfg(not(target_os = "android"))]
     {
       return Err("Content URI is only supported on Android".to_string());
     }
   } else {
-    // 普通文件路径，直接读取
-    let data = tokio::fs::read(&file_path)
+    // 普通文件路径，直接打开
+    let file = tokio::fs::File::open(&file_path)
       .await
-      .map_err(|e| format!("Failed to read file: {}", e))?;
+      .map_err(|e| format!("Failed to open file: {}", e))?;
+    let size = file
+      .metadata()
+      .await
+      .map_err(|e| format!("Failed to get file metadata: {}", e))?
+      .len();
     let name = std::path::Path::new(&file_path)
       .file_name()
       .and_then(|n| n.to_str())
       .unwrap_or("file")
       .to_string();
-    (data, name)
+    (file, name, size)
   };
 
-  let file_size = file_data.len() as u64;
-  let chunk_size = 1024 * 1024; // 1MB per chunk
-  let total_chunks = (file_size as usize + chunk_size - 1) / chunk_size;
-
-  // 建立连接
-  let mut connection = TcpConnection::connect(&target_address, target_port)
-    .await
-    .map_err(|e| format!("Failed to connect: {}", e))?;
-
-  // 发送开始传输消息
-  let start_msg = TransferMessage::StartTransfer {
-    file_name: file_name.clone(),
+  send_stream(
+    file,
+    file_name,
     file_size,
-    total_chunks: total_chunks as u64,
-  };
-  let start_data =
-    serde_json::to_vec(&start_msg).map_err(|e| format!("Serialize failed: {}", e))?;
-  connection
-    .send(&start_data)
-    .await
-    .map_err(|e| format!("Failed to send start message: {}", e))?;
-
-  // 分片并发送文件
-  let app_clone = app.clone();
-  let file_path_clone = file_path.clone();
-  let mut sent_bytes = 0u64;
-
-  for (i, chunk_data) in file_data.chunks(chunk_size).enumerate() {
-    let chunk_size_actual = chunk_data.len();
-    let chunk_msg = TransferMessage::Chunk {
-      chunk_id: i as u64,
-      data: chunk_data.to_vec(),
-    };
-    let chunk_data_serialized =
-      serde_json::to_vec(&chunk_msg).map_err(|e| format!("Serialize failed: {}", e))?;
-    connection
-      .send(&chunk_data_serialized)
-      .await
-      .map_err(|e| format!("Failed to send chunk: {}", e))?;
-
-    sent_bytes += chunk_size_actual as u64;
-
-    // 发送进度更新
-    let progress = if file_size > 0 {
-      (sent_bytes * 100 / file_size) as u32
-    } else {
-      0
-    };
-    app_clone
-      .emit(
-        "transfer-progress",
-        serde_json::json!({
-          "file": file_path_clone.clone(),
-          "progress": progress,
-          "sent": sent_bytes,
-          "total": file_size
-        }),
-      )
-      .ok();
-  }
-
-  // 发送完成消息
-  let complete_msg = TransferMessage::Complete;
-  let complete_data =
-    serde_json::to_vec(&complete_msg).map_err(|e| format!("Serialize failed: {}", e))?;
-  connection
-    .send(&complete_data)
-    .await
-    .map_err(|e| format!("Failed to send complete message: {}", e))?;
-
-  connection
-    .close()
-    .map_err(|e| format!("Failed to close connection: {}", e))?;
-
-  app
-    .emit(
-      "transfer-complete",
-      serde_json::json!({
-        "file": file_path
-      }),
-    )
-    .map_err(|e| format!("Failed to emit event: {}", e))?;
-
-  Ok("File sent successfully".to_string())
+    file_path,
+    target_address,
+    target_port,
+    state,
+    app,
+  )
+  .await
 }
 
 /// 流式发送文件（避免大文件内存溢出）
@@ -221,7 +152,7 @@ pub async fn send_file_streaming(
   file_info: FileInfo,
   target_address: String,
   target_port: u16,
-  _state: State<'_, AppState>,
+  state: State<'_, AppState>,
   app: AppHandle,
 ) -> Result<String, String> {
   // 验证权限状态
@@ -233,7 +164,6 @@ pub async fn send_file_streaming(
   if file_info.uri.starts_with("content://") {
     #[cfg(target_os = "android")]
     {
-      use std::io::{Read, Seek, SeekFrom};
       use tauri_plugin_android_fs::{AndroidFsExt, FileUri};
       use tracing::info;
       let api = app.android_fs_async();
@@ -255,139 +185,207 @@ pub async fn send_file_streaming(
         }
       }
 
-      // 建立 TCP 连接
-      let mut connection = TcpConnection::connect(&target_address, target_port)
+      let file = api
+        .open_file_readable(&file_uri)
         .await
-        .map_err(|e| format!("Failed to connect: {}", e))?;
-
-      // 发送开始传输消息
-      let chunk_size = 1024 * 1024; // 1MB per chunk
-      let total_chunks = if file_info.size > 0 {
-        (file_info.size as usize + chunk_size - 1) / chunk_size
-      } else {
-        1
-      };
+        .map_err(|e| {
+          format!(
+            "Failed to read file from URI: {} (URI: {}). This may be due to missing permissions. Please try selecting the file again.",
+            e, file_info.uri
+          )
+        })?;
 
-      let start_msg = TransferMessage::StartTransfer {
-        file_name: file_info.name.clone(),
-        file_size: file_info.size,
-        total_chunks: total_chunks as u64,
-      };
-      let start_data =
-        serde_json::to_vec(&start_msg).map_err(|e| format!("Serialize failed: {}", e))?;
-      connection
-        .send(&start_data)
-        .await
-        .map_err(|e| format!("Failed to send start message: {}", e))?;
-
-      // 流式读取并发送文件
-      let app_clone = app.clone();
-      let file_uri_clone = file_info.uri.clone();
-      let mut sent_bytes = 0u64;
-      let total_size = file_info.size;
-      let mut chunk_id = 0u64;
-
-      // 使用固定大小的缓冲区进行流式读取
-      let mut buffer = vec![0u8; chunk_size];
-
-      loop {
-        // 每次读取时重新打开文件（避免长时间持有文件句柄）
-        let mut file = api
-          .open_file_readable(&file_uri)
-          .await
-          .map_err(|e| {
-            format!(
-              "Failed to read file from URI: {} (URI: {}). This may be due to missing permissions. Please try selecting the file again.",
-              e, file_info.uri
-            )
-          })?;
-
-        // 定位到当前读取位置
-        file
-          .seek(SeekFrom::Start(sent_bytes))
-          .map_err(|e| format!("Failed to seek file: {}", e))?;
-
-        // 读取一个块
-        match file.read(&mut buffer) {
-          Ok(0) => break, // EOF
-          Ok(n) => {
-            let chunk_data = buffer[..n].to_vec();
-            let chunk_msg = TransferMessage::Chunk {
-              chunk_id,
-              data: chunk_data,
-            };
-            let chunk_data_serialized =
-              serde_json::to_vec(&chunk_msg).map_err(|e| format!("Serialize failed: {}", e))?;
-            connection
-              .send(&chunk_data_serialized)
-              .await
-              .map_err(|e| format!("Failed to send chunk: {}", e))?;
+      // 由 core 按分片流式读取并发送，内存占用与文件大小无关
+      let result = send_stream(
+        tokio::fs::File::from_std(file),
+        file_info.name.clone(),
+        file_info.size,
+        file_info.uri.clone(),
+        target_address,
+        target_port,
+        state,
+        app,
+      )
+      .await?;
 
-            sent_bytes += n as u64;
-            chunk_id += 1;
+      info!(
+        "[MOBILE] File sent successfully via streaming: {} (size: {} bytes)",
+        file_info.name, file_info.size
+      );
 
-            // 发送进度更新
-            let progress = if total_size > 0 {
-              (sent_bytes * 100 / total_size) as u32
-            } else {
-              0
-            };
-            app_clone
-              .emit(
-                "transfer-progress",
-                serde_json::json!({
-                  "file": file_uri_clone.clone(),
-                  "progress": progress,
-                  "sent": sent_bytes,
-                  "total": total_size
-                }),
-              )
-              .ok();
-          }
-          Err(e) => {
-            return Err(format!("Failed to read file chunk: {}", e));
-          }
-        }
-      }
+      return Ok(result);
+    }
+    #[cfg(not(target_os = "android"))]
+    {
+      Err("Content URI is only supported on Android".to_string())
+    }
+  } else {
+    // 普通文件路径，同样由 send_file 流式发送
+    send_file(file_info.uri, target_address, target_port, state, app).await
+  }
+}
 
-      // 发送完成消息
-      let complete_msg = TransferMessage::Complete;
-      let complete_data =
-        serde_json::to_vec(&complete_msg).map_err(|e| format!("Serialize failed: {}", e))?;
-      connection
-        .send(&complete_data)
-        .await
-        .map_err(|e| format!("Failed to send complete message: {}", e))?;
+/// 在一个会话中发送多个文件或整个目录
+///
+/// 选择器多选得到的 content:// URI 按文件逐个打开；普通路径的目录会被递归展开
+#[tauri::command]
+pub async fn send_files(
+  file_paths: Vec<String>,
+  target_address: String,
+  target_port: u16,
+  state: State<'_, AppState>,
+  app: AppHandle,
+) -> Result<String, String> {
+  // 进度事件由传输管理器统一发出，同时携带当前文件
+  let transfer_manager = &state.inner().transfer_manager;
 
-      connection
-        .close()
-        .map_err(|e| format!("Failed to close connection: {}", e))?;
+  if file_paths.iter().any(|path| path.starts_with("content://")) {
+    #[cfg(target_os = "android")]
+    {
+      use stationuli_core::file::{Manifest, ManifestEntry};
+      use tauri_plugin_android_fs::{AndroidFsExt, FileUri};
 
-      info!(
-        "[MOBILE] File sent successfully via streaming: {} (size: {} bytes)",
-        file_info.name, sent_bytes
-      );
+      let api = app.android_fs_async();
+      let mut file_uris = Vec::new();
+      let mut entries = Vec::new();
+      for file_path in &file_paths {
+        let json_str = format!(r#"{{"uri": "{}", "documentTopTreeUri": null}}"#, file_path);
+        let file_uri = FileUri::from_json_str(&json_str)
+          .map_err(|e| format!("Failed to parse URI: {} (URI: {})", e, file_path))?;
+        let name = api
+          .get_name(&file_uri)
+          .await
+          .ok()
+          .filter(|n| !n.is_empty())
+          .unwrap_or_else(|| get_file_name_from_uri(file_path));
+        let size = api
+          .get_len(&file_uri)
+          .await
+          .map_err(|e| format!("Failed to get file size: {} (URI: {})", e, file_path))?;
+        entries.push(ManifestEntry {
+          path: name,
+          is_dir: false,
+          size,
+          mode: None,
+          modified: None,
+        });
+        file_uris.push(file_uri);
+      }
 
-      app
-        .emit(
-          "transfer-complete",
-          serde_json::json!({
-            "file": file_info.uri
-          }),
+      let manifest = Manifest {
+        name: match entries.as_slice() {
+          [entry] => entry.path.clone(),
+          _ => format!("{} items", entries.len()),
+        },
+        entries,
+      };
+      let peer = format_host_port(&target_address, target_port);
+      transfer_manager
+        .run(
+          TransferDirection::Outbound,
+          manifest.name.clone(),
+          peer,
+          |transfer, handle| async move {
+            transfer
+              .send_entries(
+                &manifest,
+                |index| {
+                  let file_uri = file_uris[index].clone();
+                  async move {
+                    api
+                      .open_file_readable(&file_uri)
+                      .await
+                      .map(tokio::fs::File::from_std)
+                      .map_err(|e| {
+                        stationuli_core::Error::File(format!("Failed to read file from URI: {}", e))
+                      })
+                  }
+                },
+                &target_address,
+                target_port,
+                Some(Box::new(move |progress| {
+                  handle.set_session_progress(progress)
+                })),
+              )
+              .await
+          },
         )
-        .map_err(|e| format!("Failed to emit event: {}", e))?;
-
-      return Ok("File sent successfully".to_string());
+        .await
+        .map_err(|e| format!("Failed to send files: {}", e))?;
     }
     #[cfg(not(target_os = "android"))]
     {
       return Err("Content URI is only supported on Android".to_string());
     }
   } else {
-    // 普通文件路径，使用现有的非流式传输（小文件）
-    // 对于普通文件路径，可以使用现有的 send_file 函数
-    send_file(file_info.uri, target_address, target_port, _state, app).await
+    transfer_manager
+      .send_paths(&file_paths, &target_address, target_port)
+      .await
+      .map_err(|e| format!("Failed to send files: {}", e))?;
   }
+
+  app
+    .emit(
+      "transfer-complete",
+      serde_json::json!({
+        "files": file_paths
+      }),
+    )
+    .map_err(|e| format!("Failed to emit event: {}", e))?;
+
+  Ok("Files sent successfully".to_string())
+}
+
+/// 通过 core 流式发送数据源，并向前端发送进度和完成事件
+#[allow(clippy::too_many_arguments)]
+async fn send_stream(
+  reader: tokio::fs::File,
+  file_name: String,
+  file_size: u64,
+  file_label: String,
+  target_address: String,
+  target_port: u16,
+  state: State<'_, AppState>,
+  app: AppHandle,
+) -> Result<String, String> {
+  let peer = format_host_port(&target_address, target_port);
+
+  // 作为传输任务执行，进度事件由传输管理器统一发出
+  state
+    .inner()
+    .transfer_manager
+    .run(
+      TransferDirection::Outbound,
+      file_name.clone(),
+      peer,
+      |transfer, handle| async move {
+        transfer
+          .send_stream(
+            reader,
+            &file_name,
+            file_size,
+            &target_address,
+            target_port,
+            Some(Box::new(move |sent_bytes, total_bytes| {
+              handle.set_progress(sent_bytes, total_bytes)
+            })),
+          )
+          .await
+      },
+    )
+    .await
+    .map_err(|e| format!("Failed to send file: {}", e))?;
+
+  app
+    .emit(
+      "transfer-complete",
+      serde_json::json!({
+        "file": file_label
+      }),
+    )
+    .map_err(|e| format!("Failed to emit event: {}", e))?;
+
+  Ok("File sent successfully".to_string())
 }
 
 /// 获取文件大小
@@ -466,7 +464,7 @@ pub async fn get_file_name(
       let uri_str = file_path.as_str();
 
       // 如果 URI 包含文件名（通常在路径的最后部分）
-      if let Some(last_part) = uri_str.split('/').last() {
+      if let Some(last_part) = uri_str.split('/').next_back() {
         // 检查是否包含文件扩展名
         if last_part.contains('.') && !last_part.starts_with("msf:") {
           // 可能是文件名，尝试解码
@@ -615,8 +613,8 @@ pub async fn save_received_file(
         )
       } else {
         save_uri_string
-          .split('/')
-          .last()
+          .rsplit('/')
+          .next()
           .map(|s| s.to_string())
           .unwrap_or_else(|| save_uri_string.clone())
       }
@@ -941,7 +939,7 @@ pub async fn select_file_android(
             let debug_str = format!("{:?}", file_uri);
             if let Some(start) = debug_str.find("content://") {
               let end = debug_str[start..]
-                .find(|c: char| c == '"' || c == '\'' || c == ' ' || c == '}')
+                .find(['"', '\'', ' ', '}'])
                 .map(|i| start + i)
                 .unwrap_or(debug_str.len());
               debug_str[start..end].to_string()
@@ -973,3 +971,22 @@ pub async fn select_file_android(
     Ok(Some(result))
   }
 }
+
+/// 应答入站文件请求（前端处理 incoming-transfer 事件后调用）
+#[tauri::command]
+pub async fn respond_transfer(
+  request_id: String,
+  accept: bool,
+  state: State<'_, AppState>,
+) -> Result<(), String> {
+  let sender = state
+    .inner()
+    .pending_transfers
+    .lock()
+    .await
+    .remove(&request_id)
+    .ok_or_else(|| format!("Transfer request not found: {}", request_id))?;
+  sender
+    .send(accept)
+    .map_err(|_| "Transfer request expired".to_string())
+}
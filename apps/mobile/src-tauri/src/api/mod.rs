@@ -2,3 +2,5 @@
 
 pub mod device;
 pub mod file;
+pub mod history;
+pub mod transfer;
@@ -0,0 +1,67 @@
+// 传输任务相关 API 命令 - 对应前端 src/api/transfer.ts
+
+use crate::state::AppState;
+use stationuli_core::file::TransferInfo;
+use tauri::State;
+
+/// 获取全部传输任务
+#[tauri::command]
+pub async fn list_transfers(state: State<'_, AppState>) -> Result<Vec<TransferInfo>, String> {
+  Ok(state.inner().transfer_manager.list())
+}
+
+/// 取消传输任务
+#[tauri::command]
+pub async fn cancel_transfer(
+  transfer_id: String,
+  state: State<'_, AppState>,
+) -> Result<(), String> {
+  state
+    .inner()
+    .transfer_manager
+    .cancel(&transfer_id)
+    .map_err(|e| format!("Failed to cancel transfer: {}", e))
+}
+
+/// 暂停出站传输（接收方会收到暂停通知）
+#[tauri::command]
+pub async fn pause_transfer(transfer_id: String, state: State<'_, AppState>) -> Result<(), String> {
+  state
+    .inner()
+    .transfer_manager
+    .pause(&transfer_id)
+    .map_err(|e| format!("Failed to pause transfer: {}", e))
+}
+
+/// 恢复已暂停的出站传输
+#[tauri::command]
+pub async fn resume_transfer(
+  transfer_id: String,
+  state: State<'_, AppState>,
+) -> Result<(), String> {
+  state
+    .inner()
+    .transfer_manager
+    .resume(&transfer_id)
+    .map_err(|e| format!("Failed to resume transfer: {}", e))
+}
+
+/// 清除已结束的传输任务
+#[tauri::command]
+pub async fn clear_finished_transfers(state: State<'_, AppState>) -> Result<(), String> {
+  state.inner().transfer_manager.clear_finished();
+  Ok(())
+}
+
+/// 设置同时进行的传输数量上限
+#[tauri::command]
+pub async fn set_max_concurrent_transfers(
+  max_concurrent: usize,
+  state: State<'_, AppState>,
+) -> Result<(), String> {
+  state
+    .inner()
+    .transfer_manager
+    .set_max_concurrent(max_concurrent);
+  Ok(())
+}
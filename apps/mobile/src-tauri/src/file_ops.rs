@@ -6,7 +6,7 @@ pub fn get_file_name_from_uri(uri: &str) -> String {
   let uri_str = uri;
 
   // 如果 URI 包含文件名（通常在路径的最后部分）
-  if let Some(last_part) = uri_str.split('/').last() {
+  if let Some(last_part) = uri_str.split('/').next_back() {
     // 检查是否包含文件扩展名
     if last_part.contains('.') && !last_part.starts_with("msf:") {
       // 可能是文件名，尝试解码
@@ -3,7 +3,9 @@
 mod api;
 mod discovery;
 mod file_ops;
+mod incoming;
 mod logging;
+mod pairing;
 mod state;
 
 use state::AppState;
@@ -11,13 +13,22 @@ use tauri::Manager;
 
 // 导入 API 命令
 use api::device::{
-  add_device, get_device_id, get_devices, get_local_ip, remove_device, start_discovery,
-  stop_discovery, test_connection, update_device,
+  add_device, get_device_id, get_devices, get_local_ip, get_trusted_peers, pair_device,
+  remove_device, remove_trusted_peer, respond_pairing, start_discovery, stop_discovery,
+  test_connection, update_device,
 };
 use api::file::{
-  get_file_name, get_file_size, save_received_file, select_file_android, select_file_android_v2,
-  send_file, send_file_streaming,
+  get_file_name, get_file_size, respond_transfer, save_received_file, select_file_android,
+  select_file_android_v2, send_file, send_file_streaming, send_files,
 };
+use api::history::{
+  clear_history, delete_history_record, get_history_record, query_history, reveal_history_file,
+};
+use api::transfer::{
+  cancel_transfer, clear_finished_transfers, list_transfers, pause_transfer, resume_transfer,
+  set_max_concurrent_transfers,
+};
+use discovery::{start_transfer_event_task, start_transfer_progress_task};
 use logging::init_logging_to_ui;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -30,7 +41,16 @@ pub fn run() {
   builder
     .setup(|app| {
       // 初始化状态
-      app.manage(AppState::new());
+      let app_state = AppState::new(app.handle())?;
+
+      // 把传输任务状态变化和进度转发给前端
+      start_transfer_event_task(app_state.transfer_manager.subscribe(), app.handle().clone());
+      start_transfer_progress_task(
+        app_state.transfer_manager.subscribe_progress(),
+        app.handle().clone(),
+      );
+
+      app.manage(app_state);
 
       // 初始化自定义日志层，将日志发送到前端
       init_logging_to_ui(app.handle(), "mobile")?;
@@ -51,14 +71,34 @@ pub fn run() {
       get_device_id,
       get_local_ip,
       test_connection,
+      // 配对相关 API
+      pair_device,
+      respond_pairing,
+      get_trusted_peers,
+      remove_trusted_peer,
       // 文件相关 API（对应前端 src/api/file.ts）
       send_file,
       send_file_streaming,
+      send_files,
       get_file_size,
       get_file_name,
       save_received_file,
+      respond_transfer,
       select_file_android,
       select_file_android_v2,
+      // 传输任务相关 API（对应前端 src/api/transfer.ts）
+      list_transfers,
+      cancel_transfer,
+      pause_transfer,
+      resume_transfer,
+      clear_finished_transfers,
+      set_max_concurrent_transfers,
+      // 传输历史相关 API（对应前端 src/api/history.ts）
+      query_history,
+      get_history_record,
+      delete_history_record,
+      clear_history,
+      reveal_history_file,
     ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
@@ -0,0 +1,52 @@
+// 设备配对实现（内部模块）
+
+use stationuli_core::crypto::{PairingHandler, PairingRequest};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{Mutex, oneshot};
+
+/// 等待用户确认的配对请求（request_id -> 应答通道）
+pub type PendingPairings = Arc<Mutex<HashMap<String, oneshot::Sender<bool>>>>;
+
+/// 等待用户确认配对的超时时间
+const PAIRING_CONFIRM_TIMEOUT_SECS: u64 = 60;
+
+/// 创建配对确认回调：向前端发送 pairing-request 事件，并等待 respond_pairing 应答
+pub fn create_pairing_handler(app: AppHandle, pending: PendingPairings) -> PairingHandler {
+  let next_id = Arc::new(AtomicU64::new(1));
+  Arc::new(move |request: PairingRequest| {
+    let app = app.clone();
+    let pending = pending.clone();
+    let request_id = format!("pair-{}", next_id.fetch_add(1, Ordering::Relaxed));
+    Box::pin(async move {
+      let (tx, rx) = oneshot::channel();
+      pending.lock().await.insert(request_id.clone(), tx);
+
+      let _ = app.emit(
+        "pairing-request",
+        serde_json::json!({
+          "request_id": request_id,
+          "device_id": request.peer.device_id,
+          "device_name": request.peer.device_name,
+          "device_type": request.peer.device_type,
+          "code": request.code,
+        }),
+      );
+
+      let accepted = matches!(
+        tokio::time::timeout(
+          tokio::time::Duration::from_secs(PAIRING_CONFIRM_TIMEOUT_SECS),
+          rx
+        )
+        .await,
+        Ok(Ok(true))
+      );
+
+      // 超时或应答通道关闭时清理
+      pending.lock().await.remove(&request_id);
+      accepted
+    })
+  })
+}
@@ -1,30 +1,108 @@
 // 应用状态管理
 
+use crate::incoming::{PendingTransfers, create_offer_handler};
+use crate::pairing::{PendingPairings, create_pairing_handler};
+use stationuli_core::crypto::TrustStore;
 use stationuli_core::file::transfer::FileTransfer;
+use stationuli_core::file::{ResumeTransfer, TransferHistory, TransferManager, TransferServer};
+use stationuli_core::identity::DeviceIdentity;
 use stationuli_core::p2p::mdns::MdnsDiscovery;
+use stationuli_core::p2p::negotiate::NegotiatedTransport;
 use std::sync::Arc;
+use tauri::{AppHandle, Manager};
 use tokio::sync::RwLock;
 
 /// 全局应用状态
 pub struct AppState {
+  pub identity: Arc<DeviceIdentity>,
   pub discovery: Arc<RwLock<Option<MdnsDiscovery>>>,
-  pub file_transfer: Arc<RwLock<FileTransfer>>,
-  pub tcp_listener: Arc<RwLock<Option<tokio::net::TcpListener>>>,
+  pub file_transfer: Arc<RwLock<FileTransfer<NegotiatedTransport>>>,
+  pub transfer_manager: TransferManager<NegotiatedTransport>,
+  pub transfer_server: Arc<RwLock<Option<TransferServer>>>,
+  pub trust_store: Arc<TrustStore>,
+  pub history: Arc<TransferHistory>,
+  pub pending_pairings: PendingPairings,
+  pub pending_transfers: PendingTransfers,
 }
 
 impl AppState {
   /// 创建新的应用状态
-  pub fn new() -> Self {
-    Self {
-      discovery: Arc::new(RwLock::new(None)),
-      file_transfer: Arc::new(RwLock::new(FileTransfer::new())),
-      tcp_listener: Arc::new(RwLock::new(None)),
-    }
-  }
-}
+  pub fn new(app: &AppHandle) -> Result<Self, String> {
+    let data_dir = app
+      .path()
+      .app_data_dir()
+      .map_err(|e| format!("Failed to resolve app data dir: {}", e))?;
+
+    // 设备身份保存在应用数据目录，设备 ID 由公钥指纹派生，重启后保持不变
+    let identity = Arc::new(
+      DeviceIdentity::load_or_create(data_dir.join("identity.json"))
+        .map_err(|e| format!("Failed to load device identity: {}", e))?,
+    );
+
+    // 受信任设备列表同样保存在应用数据目录
+    let trust_store = TrustStore::load(data_dir.join("trusted_peers.json")).unwrap_or_else(|e| {
+      tracing::warn!(
+        "Failed to load trust store, pairings will not persist: {}",
+        e
+      );
+      TrustStore::in_memory()
+    });
+    let trust_store = Arc::new(trust_store);
+    let pending_pairings = PendingPairings::default();
+    let pending_transfers = PendingTransfers::default();
 
-impl Default for AppState {
-  fn default() -> Self {
-    Self::new()
+    // 未完成传输的检查点，重启后仍可续传
+    let resume_store = ResumeTransfer::load(data_dir.join("transfers.json")).unwrap_or_else(|e| {
+      tracing::warn!(
+        "Failed to load transfer checkpoints, transfers will not resume after restart: {}",
+        e
+      );
+      ResumeTransfer::new()
+    });
+
+    // 传输历史（收发双方的每次传输都会记录）
+    let history = TransferHistory::load(data_dir.join("history.json")).unwrap_or_else(|e| {
+      tracing::warn!(
+        "Failed to load transfer history, history will not persist: {}",
+        e
+      );
+      TransferHistory::new()
+    });
+    let history = Arc::new(history);
+
+    // 出站连接优先使用 QUIC，对端不支持或无法连通时回退到 TCP
+    // 已配对设备的 QUIC 证书与设备发现记录不一致时拒绝连接
+    let mut transport = NegotiatedTransport::new(identity.clone());
+    transport.set_trust_store(trust_store.clone());
+    let mut file_transfer = FileTransfer::with_transport(transport);
+    let local_peer = identity.local_peer(
+      file_transfer.local_peer().device_name.clone(),
+      "mobile".to_string(),
+    );
+    file_transfer.set_local_peer(local_peer);
+    file_transfer.set_trust_store(trust_store.clone());
+    file_transfer.set_resume_store(Arc::new(resume_store));
+    file_transfer.set_history(history.clone());
+    file_transfer.set_pairing_handler(create_pairing_handler(
+      app.clone(),
+      pending_pairings.clone(),
+    ));
+    file_transfer.set_offer_handler(create_offer_handler(app.clone(), pending_transfers.clone()));
+
+    // 所有收发都经由传输任务管理器，支持并发、排队和取消
+    let file_transfer = Arc::new(RwLock::new(file_transfer));
+    let transfer_manager = TransferManager::new(file_transfer.clone());
+
+    Ok(Self {
+      identity,
+      discovery: Arc::new(RwLock::new(None)),
+      file_transfer,
+      transfer_manager,
+      transfer_server: Arc::new(RwLock::new(None)),
+      trust_store,
+      history,
+      pending_pairings,
+      pending_transfers,
+    })
   }
 }
@@ -0,0 +1,95 @@
+// 传输历史相关 API 调用
+import { invoke } from "@tauri-apps/api/core";
+import type { TransferDirection } from "./transfer";
+
+export type TransferOutcome = "completed" | "failed" | "declined" | "cancelled";
+
+/**
+ * 历史记录中的一个文件
+ */
+export interface HistoryFile {
+  /** 文件名（多文件会话中为相对路径） */
+  name: string;
+  /** 本地路径（数据源不是本地文件时为空） */
+  path: string | null;
+  size: number;
+  /** SHA-256（十六进制） */
+  hash: string;
+}
+
+/**
+ * 一条传输历史
+ */
+export interface HistoryRecord {
+  id: string;
+  direction: TransferDirection;
+  peer_id: string | null;
+  peer_name: string | null;
+  peer_address: string;
+  name: string;
+  files: HistoryFile[];
+  total_size: number;
+  /** Unix 秒 */
+  started_at: number;
+  finished_at: number;
+  outcome: TransferOutcome;
+  error: string | null;
+}
+
+/**
+ * 历史查询条件（未设置的条件不参与过滤）
+ */
+export interface HistoryQuery {
+  peer_id?: string;
+  direction?: TransferDirection;
+  outcome?: TransferOutcome;
+  /** 开始时间范围（Unix 秒，左闭右开） */
+  since?: number;
+  until?: number;
+  /** 匹配名称、文件名和对端名称 */
+  keyword?: string;
+  offset?: number;
+  limit?: number;
+}
+
+/**
+ * 查询传输历史（最新的在前）
+ */
+export async function queryHistory(
+  query?: HistoryQuery
+): Promise<HistoryRecord[]> {
+  return await invoke<HistoryRecord[]>("query_history", { query });
+}
+
+/**
+ * 获取一条传输历史
+ */
+export async function getHistoryRecord(
+  recordId: string
+): Promise<HistoryRecord> {
+  return await invoke<HistoryRecord>("get_history_record", { recordId });
+}
+
+/**
+ * 删除一条传输历史（不删除文件本身）
+ */
+export async function deleteHistoryRecord(recordId: string): Promise<void> {
+  return await invoke("delete_history_record", { recordId });
+}
+
+/**
+ * 清空传输历史
+ */
+export async function clearHistory(): Promise<void> {
+  return await invoke("clear_history");
+}
+
+/**
+ * 在文件管理器中显示历史记录中的文件（移动端直接打开文件）
+ */
+export async function revealHistoryFile(
+  recordId: string,
+  fileIndex: number
+): Promise<void> {
+  return await invoke("reveal_history_file", { recordId, fileIndex });
+}
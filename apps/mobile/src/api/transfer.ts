@@ -0,0 +1,95 @@
+// 传输任务相关 API 调用
+import { invoke } from "@tauri-apps/api/core";
+
+export type TransferDirection = "outbound" | "inbound";
+
+export type TransferState =
+  | "queued"
+  | "running"
+  | "paused"
+  | "done"
+  | "failed"
+  | "cancelled";
+
+/**
+ * 传输任务信息（transfer-updated 事件携带同样的结构）
+ */
+export interface TransferInfo {
+  id: string;
+  direction: TransferDirection;
+  name: string;
+  peer: string;
+  state: TransferState;
+  transferred: number;
+  total: number;
+  error: string | null;
+  /** 入站传输被发送方暂停 */
+  paused_by_peer: boolean;
+  created_at: number;
+}
+
+/**
+ * 传输进度（transfer-progress 事件携带的结构，已限频）
+ */
+export interface TransferProgress {
+  transfer_id: string;
+  direction: TransferDirection;
+  transferred: number;
+  total: number;
+  /** 瞬时速度（字节/秒） */
+  speed: number;
+  /** 平均速度（字节/秒） */
+  average_speed: number;
+  /** 预计剩余时间（秒） */
+  eta_secs: number | null;
+  /** 当前文件（多文件会话中的相对路径） */
+  current_file: string | null;
+  file_index: number;
+  file_count: number;
+  /** 百分比（0-100） */
+  progress: number;
+}
+
+/**
+ * 获取全部传输任务
+ */
+export async function listTransfers(): Promise<TransferInfo[]> {
+  return await invoke<TransferInfo[]>("list_transfers");
+}
+
+/**
+ * 取消传输任务
+ */
+export async function cancelTransfer(transferId: string): Promise<void> {
+  return await invoke("cancel_transfer", { transferId });
+}
+
+/**
+ * 暂停出站传输
+ */
+export async function pauseTransfer(transferId: string): Promise<void> {
+  return await invoke("pause_transfer", { transferId });
+}
+
+/**
+ * 恢复已暂停的出站传输
+ */
+export async function resumeTransfer(transferId: string): Promise<void> {
+  return await invoke("resume_transfer", { transferId });
+}
+
+/**
+ * 清除已结束的传输任务
+ */
+export async function clearFinishedTransfers(): Promise<void> {
+  return await invoke("clear_finished_transfers");
+}
+
+/**
+ * 设置同时进行的传输数量上限
+ */
+export async function setMaxConcurrentTransfers(
+  maxConcurrent: number
+): Promise<void> {
+  return await invoke("set_max_concurrent_transfers", { maxConcurrent });
+}
@@ -10,6 +10,7 @@ import {
   selectFileAndroidV2,
   sendFileStreaming,
 } from "../api/fileAdapter";
+import type { TransferProgress } from "../api/transfer";
 import { FILE_FILTERS } from "../constants";
 import { sendFile } from "../api/file";
 
@@ -21,15 +22,14 @@ export function useFileTransfer() {
 
   // 监听传输进度事件
   useEffect(() => {
-    const unlistenProgress = listen("transfer-progress", (event) => {
-      const data = event.payload as {
-        file: string;
-        progress: number;
-        sent: number;
-        total: number;
-      };
-      setTransferProgress(data.progress);
-    });
+    const unlistenProgress = listen<TransferProgress>(
+      "transfer-progress",
+      (event) => {
+        if (event.payload.direction === "outbound") {
+          setTransferProgress(event.payload.progress);
+        }
+      }
+    );
 
     const unlistenComplete = listen("transfer-complete", () => {
       setTransferProgress(100);
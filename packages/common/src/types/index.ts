@@ -6,8 +6,17 @@ export interface DeviceInfo {
   address: string;
   port: number;
   device_type: string;
+  transports?: TransportKind[]; // 支持的传输方式（缺省表示未知，连接时先尝试 QUIC 再回退到 TCP）
+  addresses?: InterfaceAddress[]; // 所有可用网卡上的地址（address 为其中的首选地址，不含 IPv6 链路本地地址）
 }
 
+export interface InterfaceAddress {
+  interface: string; // 网卡名称（为空表示未知）
+  address: string;
+}
+
+export type TransportKind = "quic" | "tcp";
+
 export type TabType = "transfer" | "control";
 
 export interface ReceivedFile {
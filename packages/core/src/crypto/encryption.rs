@@ -1,26 +1,158 @@
-//! 加密模块（AES-256）
+//! 加密模块（AES-256-GCM）
 
 use crate::Result;
+use crate::crypto::handshake::SessionKeys;
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+
+/// 认证标签长度（字节）
+pub const TAG_LEN: usize = 16;
 
 /// 加密器
+///
+/// 收发两个方向各用一把密钥，nonce 由各自的帧计数器派生且不随帧传输，
+/// 因此被重放、乱序或篡改的帧都会在解密时校验失败
 pub struct Encryption {
-  // TODO: 实现 AES-256 加密
+  sealing_key: LessSafeKey,
+  opening_key: LessSafeKey,
+  send_counter: u64,
+  recv_counter: u64,
 }
 
 impl Encryption {
-  pub fn new() -> Self {
-    Self {}
+  /// 使用发送/接收密钥创建加密器
+  pub fn new(send_key: &[u8], recv_key: &[u8]) -> Result<Self> {
+    Ok(Self {
+      sealing_key: Self::make_key(send_key)?,
+      opening_key: Self::make_key(recv_key)?,
+      send_counter: 0,
+      recv_counter: 0,
+    })
+  }
+
+  /// 使用握手得到的会话密钥创建加密器
+  pub fn from_session(keys: &SessionKeys) -> Result<Self> {
+    Self::new(&keys.send_key, &keys.recv_key)
   }
 
-  /// 加密数据
-  pub fn encrypt(&self) -> Result<Vec<u8>> {
-    // TODO: 实现加密
-    Ok(vec![])
+  /// 加密数据（输出 = 密文 || 认证标签）
+  pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let nonce = Self::next_nonce(&mut self.send_counter)?;
+    let mut buffer = Vec::with_capacity(plaintext.len() + TAG_LEN);
+    buffer.extend_from_slice(plaintext);
+    self
+      .sealing_key
+      .seal_in_place_append_tag(nonce, Aad::empty(), &mut buffer)
+      .map_err(|_| crate::Error::Crypto("Encrypt failed".to_string()))?;
+    Ok(buffer)
   }
 
   /// 解密数据
-  pub fn decrypt(&self) -> Result<Vec<u8>> {
-    // TODO: 实现解密
-    Ok(vec![])
+  ///
+  /// 帧必须按发送顺序逐一解密，篡改、重放或乱序的帧会返回 `Error::Crypto`
+  pub fn decrypt(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    if ciphertext.len() < TAG_LEN {
+      return Err(crate::Error::Crypto(format!(
+        "Ciphertext too short: {} bytes",
+        ciphertext.len()
+      )));
+    }
+
+    // 只有认证成功才推进计数器，失败的帧不会影响后续帧的 nonce
+    let mut counter = self.recv_counter;
+    let nonce = Self::next_nonce(&mut counter)?;
+    let mut buffer = ciphertext.to_vec();
+    let plaintext_len = self
+      .opening_key
+      .open_in_place(nonce, Aad::empty(), &mut buffer)
+      .map_err(|_| {
+        crate::Error::Crypto(format!(
+          "Frame {} authentication failed (tampered, replayed or reordered)",
+          self.recv_counter
+        ))
+      })?
+      .len();
+    self.recv_counter = counter;
+
+    buffer.truncate(plaintext_len);
+    Ok(buffer)
+  }
+
+  fn make_key(key: &[u8]) -> Result<LessSafeKey> {
+    let key = UnboundKey::new(&AES_256_GCM, key)
+      .map_err(|_| crate::Error::Crypto(format!("Invalid key length: {}", key.len())))?;
+    Ok(LessSafeKey::new(key))
+  }
+
+  /// 由帧计数器生成 nonce：4 字节 0 || 8 字节大端计数
+  fn next_nonce(counter: &mut u64) -> Result<Nonce> {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[NONCE_LEN - 8..].copy_from_slice(&counter.to_be_bytes());
+    *counter = counter
+      .checked_add(1)
+      .ok_or_else(|| crate::Error::Crypto("Frame counter exhausted".to_string()))?;
+    Ok(Nonce::assume_unique_for_key(nonce))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pair() -> (Encryption, Encryption) {
+    let (a, b) = ([1u8; 32], [2u8; 32]);
+    (
+      Encryption::new(&a, &b).unwrap(),
+      Encryption::new(&b, &a).unwrap(),
+    )
+  }
+
+  #[test]
+  fn round_trip() {
+    let (mut alice, mut bob) = pair();
+    for message in [&b"first"[..], b"", b"third"] {
+      let ciphertext = alice.encrypt(message).unwrap();
+      assert_eq!(ciphertext.len(), message.len() + TAG_LEN);
+      assert_eq!(bob.decrypt(&ciphertext).unwrap(), message);
+    }
+    let reply = bob.encrypt(b"reply").unwrap();
+    assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+  }
+
+  #[test]
+  fn rejects_tampered_frame() {
+    let (mut alice, mut bob) = pair();
+    let mut ciphertext = alice.encrypt(b"hello").unwrap();
+    ciphertext[0] ^= 1;
+    assert!(matches!(
+      bob.decrypt(&ciphertext),
+      Err(crate::Error::Crypto(_))
+    ));
+    assert!(matches!(
+      bob.decrypt(&ciphertext[..TAG_LEN - 1]),
+      Err(crate::Error::Crypto(_))
+    ));
+
+    // 失败的帧不推进计数器，下一帧仍能解密
+    ciphertext[0] ^= 1;
+    assert_eq!(bob.decrypt(&ciphertext).unwrap(), b"hello");
+  }
+
+  #[test]
+  fn rejects_replayed_and_reordered_frames() {
+    let (mut alice, mut bob) = pair();
+    let first = alice.encrypt(b"first").unwrap();
+    let second = alice.encrypt(b"second").unwrap();
+
+    assert!(matches!(bob.decrypt(&second), Err(crate::Error::Crypto(_))));
+    assert_eq!(bob.decrypt(&first).unwrap(), b"first");
+    assert!(matches!(bob.decrypt(&first), Err(crate::Error::Crypto(_))));
+    assert_eq!(bob.decrypt(&second).unwrap(), b"second");
+  }
+
+  #[test]
+  fn directions_use_separate_keys() {
+    let (mut alice, _) = pair();
+    let ciphertext = alice.encrypt(b"hello").unwrap();
+    assert!(alice.decrypt(&ciphertext).is_err());
   }
 }
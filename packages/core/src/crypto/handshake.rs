@@ -0,0 +1,628 @@
+//! 会话握手模块
+//!
+//! 在文件传输开始前双方交换临时 X25519 公钥和长期公钥，通过 HKDF 派生
+//! 收发方向各自独立的会话密钥，并以握手记录的 HMAC 做密钥确认。
+//! 会话密钥同时依赖双方的长期私钥，因此握手成功即证明对端持有其声明的长期公钥
+
+use crate::Result;
+use crate::crypto::key_exchange::{KeyExchange, PUBLIC_KEY_LEN};
+use crate::p2p::transport::FramedConnection;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hkdf, hmac};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// 握手协议版本
+pub const HANDSHAKE_VERSION: u8 = 2;
+
+/// 握手记录哈希的域分隔前缀
+const TRANSCRIPT_LABEL: &[u8] = b"stationuli/handshake/v2";
+
+/// 会话密钥长度（字节）
+pub const SESSION_KEY_LEN: usize = 32;
+
+/// 握手消息类型
+#[derive(Debug, Serialize, Deserialize)]
+pub enum HandshakeMessage {
+  /// 承诺：发起方先发送自己 Hello 的哈希，防止中间人针对短认证串穷举密钥
+  Commit { hello_hash: [u8; 32] },
+  /// 问候：携带设备信息、临时公钥、长期公钥和随机数
+  Hello {
+    version: u8,
+    device_id: String,
+    device_name: String,
+    device_type: String,
+    static_public_key: [u8; PUBLIC_KEY_LEN],
+    ephemeral_public_key: [u8; PUBLIC_KEY_LEN],
+    nonce: [u8; 32],
+  },
+  /// 完成：对握手记录的密钥确认
+  Finished { verify_data: Vec<u8> },
+  /// 握手错误
+  Error(String),
+}
+
+/// 握手角色
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeRole {
+  /// 发起方（发送文件的一端）
+  Initiator,
+  /// 响应方（接收文件的一端）
+  Responder,
+}
+
+/// 本端身份（设备信息 + 长期私钥）
+#[derive(Clone)]
+pub struct LocalPeer {
+  pub device_id: String,
+  pub device_name: String,
+  pub device_type: String,
+  static_secret: StaticSecret,
+}
+
+impl LocalPeer {
+  /// 使用已有的长期私钥创建本端身份
+  pub fn new(
+    device_id: String,
+    device_name: String,
+    device_type: String,
+    static_secret: StaticSecret,
+  ) -> Self {
+    Self {
+      device_id,
+      device_name,
+      device_type,
+      static_secret,
+    }
+  }
+
+  /// 生成一个新的随机长期私钥作为本端身份
+  pub fn generate(device_id: String, device_name: String, device_type: String) -> Self {
+    Self::new(device_id, device_name, device_type, StaticSecret::random())
+  }
+
+  /// 获取长期公钥
+  pub fn public_key(&self) -> [u8; PUBLIC_KEY_LEN] {
+    PublicKey::from(&self.static_secret).to_bytes()
+  }
+}
+
+/// 对端身份（握手成功后得到，长期公钥已被验证）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerIdentity {
+  pub device_id: String,
+  pub device_name: String,
+  pub device_type: String,
+  pub public_key: [u8; PUBLIC_KEY_LEN],
+}
+
+/// 握手得到的会话密钥
+pub struct SessionKeys {
+  /// 本端发送方向的密钥
+  pub send_key: [u8; SESSION_KEY_LEN],
+  /// 本端接收方向的密钥
+  pub recv_key: [u8; SESSION_KEY_LEN],
+  /// 握手记录哈希，用于派生短认证串
+  pub transcript_hash: [u8; 32],
+  /// 对端身份
+  pub peer: PeerIdentity,
+}
+
+/// 会话握手
+pub struct Handshake<'a> {
+  local: &'a LocalPeer,
+  rng: SystemRandom,
+}
+
+impl<'a> Handshake<'a> {
+  pub fn new(local: &'a LocalPeer) -> Self {
+    Self {
+      local,
+      rng: SystemRandom::new(),
+    }
+  }
+
+  /// 作为发起方执行握手
+  pub async fn initiate<C: FramedConnection>(&self, connection: &mut C) -> Result<SessionKeys> {
+    self.run(connection, HandshakeRole::Initiator).await
+  }
+
+  /// 作为响应方执行握手
+  pub async fn respond<C: FramedConnection>(&self, connection: &mut C) -> Result<SessionKeys> {
+    self.run(connection, HandshakeRole::Responder).await
+  }
+
+  async fn run<C: FramedConnection>(
+    &self,
+    connection: &mut C,
+    role: HandshakeRole,
+  ) -> Result<SessionKeys> {
+    let mut key_exchange = KeyExchange::new();
+    let ephemeral_public_key = key_exchange.generate_keypair()?;
+
+    let mut nonce = [0u8; 32];
+    self
+      .rng
+      .fill(&mut nonce)
+      .map_err(|_| crate::Error::Crypto("Generate nonce failed".to_string()))?;
+
+    let local_hello = Self::encode(&HandshakeMessage::Hello {
+      version: HANDSHAKE_VERSION,
+      device_id: self.local.device_id.clone(),
+      device_name: self.local.device_name.clone(),
+      device_type: self.local.device_type.clone(),
+      static_public_key: self.local.public_key(),
+      ephemeral_public_key,
+      nonce,
+    })?;
+
+    // 发起方：Commit → 收到对端 Hello → 发送 Hello
+    // 响应方：收到 Commit → 发送 Hello → 收到对端 Hello 并校验承诺
+    let peer_hello = match role {
+      HandshakeRole::Initiator => {
+        let commit = HandshakeMessage::Commit {
+          hello_hash: Self::hash(&local_hello),
+        };
+        connection.send_frame(&Self::encode(&commit)?).await?;
+        let peer_hello = connection.recv_frame().await?;
+        connection.send_frame(&local_hello).await?;
+        peer_hello
+      }
+      HandshakeRole::Responder => {
+        let hello_hash = match Self::decode(&connection.recv_frame().await?)? {
+          HandshakeMessage::Commit { hello_hash } => hello_hash,
+          _ => {
+            return Err(crate::Error::ProtocolViolation(
+              "Expected handshake Commit message".to_string(),
+            ));
+          }
+        };
+        connection.send_frame(&local_hello).await?;
+        let peer_hello = connection.recv_frame().await?;
+        if Self::hash(&peer_hello) != hello_hash {
+          return Self::abort(
+            connection,
+            crate::Error::ProtocolViolation("Handshake commitment mismatch".to_string()),
+          )
+          .await;
+        }
+        peer_hello
+      }
+    };
+
+    let (peer, peer_ephemeral_public_key) = match Self::decode(&peer_hello)? {
+      HandshakeMessage::Hello {
+        version,
+        device_id,
+        device_name,
+        device_type,
+        static_public_key,
+        ephemeral_public_key,
+        ..
+      } => {
+        if version != HANDSHAKE_VERSION {
+          let err = format!(
+            "Unsupported handshake version: expected {}, got {}",
+            HANDSHAKE_VERSION, version
+          );
+          return Self::abort(connection, crate::Error::Protocol(err)).await;
+        }
+        let peer = PeerIdentity {
+          device_id,
+          device_name,
+          device_type,
+          public_key: static_public_key,
+        };
+        // 传输层已认证对端时（QUIC 证书），握手中声明的身份必须与之一致
+        if let Err(e) = connection.verify_peer(&peer) {
+          return Self::abort(connection, e).await;
+        }
+        (peer, ephemeral_public_key)
+      }
+      HandshakeMessage::Error(err) => {
+        return Err(crate::Error::Crypto(format!("Handshake rejected: {}", err)));
+      }
+      _ => {
+        return Err(crate::Error::ProtocolViolation(
+          "Expected handshake Hello message".to_string(),
+        ));
+      }
+    };
+
+    // 握手记录始终按 发起方 Hello || 响应方 Hello 的顺序计算
+    let (initiator_hello, responder_hello) = match role {
+      HandshakeRole::Initiator => (&local_hello, &peer_hello),
+      HandshakeRole::Responder => (&peer_hello, &local_hello),
+    };
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(TRANSCRIPT_LABEL);
+    ctx.update(initiator_hello);
+    ctx.update(responder_hello);
+    let mut transcript_hash = [0u8; 32];
+    transcript_hash.copy_from_slice(ctx.finish().as_ref());
+
+    // 输入密钥材料：DH(临时_发起, 临时_响应) || DH(长期_发起, 临时_响应) || DH(临时_发起, 长期_响应)
+    let ephemeral_dh = key_exchange.exchange(&peer_ephemeral_public_key)?;
+    let (initiator_static_dh, responder_static_dh) = match role {
+      HandshakeRole::Initiator => (
+        KeyExchange::exchange_static(&self.local.static_secret, &peer_ephemeral_public_key)?,
+        key_exchange.exchange(&peer.public_key)?,
+      ),
+      HandshakeRole::Responder => (
+        key_exchange.exchange(&peer.public_key)?,
+        KeyExchange::exchange_static(&self.local.static_secret, &peer_ephemeral_public_key)?,
+      ),
+    };
+    let mut input_key_material = Vec::with_capacity(96);
+    input_key_material.extend_from_slice(&ephemeral_dh);
+    input_key_material.extend_from_slice(&initiator_static_dh);
+    input_key_material.extend_from_slice(&responder_static_dh);
+
+    let schedule = KeySchedule::derive(&input_key_material, &transcript_hash)?;
+
+    // 密钥确认：发起方先发 Finished，响应方验证后回 Finished
+    let (local_confirm, peer_confirm) = match role {
+      HandshakeRole::Initiator => (&schedule.initiator_confirm, &schedule.responder_confirm),
+      HandshakeRole::Responder => (&schedule.responder_confirm, &schedule.initiator_confirm),
+    };
+    let local_finished = Self::encode(&HandshakeMessage::Finished {
+      verify_data: hmac::sign(local_confirm, &transcript_hash)
+        .as_ref()
+        .to_vec(),
+    })?;
+
+    match role {
+      HandshakeRole::Initiator => {
+        connection.send_frame(&local_finished).await?;
+        let peer_finished = connection.recv_frame().await?;
+        Self::verify_finished(&peer_finished, peer_confirm, &transcript_hash)?;
+      }
+      HandshakeRole::Responder => {
+        let peer_finished = connection.recv_frame().await?;
+        if let Err(e) = Self::verify_finished(&peer_finished, peer_confirm, &transcript_hash) {
+          return Self::abort(connection, e).await;
+        }
+        connection.send_frame(&local_finished).await?;
+      }
+    }
+
+    info!(
+      "Secure session established with {} ({}, {})",
+      connection.peer_addr(),
+      peer.device_name,
+      peer.device_id
+    );
+
+    let (send_key, recv_key) = match role {
+      HandshakeRole::Initiator => (
+        schedule.initiator_to_responder,
+        schedule.responder_to_initiator,
+      ),
+      HandshakeRole::Responder => (
+        schedule.responder_to_initiator,
+        schedule.initiator_to_responder,
+      ),
+    };
+
+    Ok(SessionKeys {
+      send_key,
+      recv_key,
+      transcript_hash,
+      peer,
+    })
+  }
+
+  /// 通知对端握手失败并返回错误
+  async fn abort<C: FramedConnection, T>(connection: &mut C, error: crate::Error) -> Result<T> {
+    if let Ok(data) = Self::encode(&HandshakeMessage::Error(error.to_string())) {
+      let _ = connection.send_frame(&data).await;
+    }
+    Err(error)
+  }
+
+  /// 校验对端的 Finished 消息
+  fn verify_finished(data: &[u8], key: &hmac::Key, transcript_hash: &[u8]) -> Result<()> {
+    match Self::decode(data)? {
+      HandshakeMessage::Finished { verify_data } => {
+        hmac::verify(key, transcript_hash, &verify_data)
+          .map_err(|_| crate::Error::ProtocolViolation("Handshake verification failed".to_string()))
+      }
+      HandshakeMessage::Error(err) => {
+        Err(crate::Error::Crypto(format!("Handshake rejected: {}", err)))
+      }
+      _ => Err(crate::Error::ProtocolViolation(
+        "Expected handshake Finished message".to_string(),
+      )),
+    }
+  }
+
+  fn hash(data: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out.copy_from_slice(digest::digest(&digest::SHA256, data).as_ref());
+    out
+  }
+
+  fn encode(message: &HandshakeMessage) -> Result<Vec<u8>> {
+    serde_json::to_vec(message)
+      .map_err(|e| crate::Error::Protocol(format!("Serialize failed: {}", e)))
+  }
+
+  fn decode(data: &[u8]) -> Result<HandshakeMessage> {
+    serde_json::from_slice(data)
+      .map_err(|e| crate::Error::ProtocolViolation(format!("Deserialize failed: {}", e)))
+  }
+}
+
+/// 由共享密钥派生出的全部密钥
+struct KeySchedule {
+  initiator_to_responder: [u8; SESSION_KEY_LEN],
+  responder_to_initiator: [u8; SESSION_KEY_LEN],
+  initiator_confirm: hmac::Key,
+  responder_confirm: hmac::Key,
+}
+
+impl KeySchedule {
+  /// HKDF-SHA256：以握手记录哈希为 salt，按用途标签展开各个密钥
+  fn derive(input_key_material: &[u8], transcript_hash: &[u8]) -> Result<Self> {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, transcript_hash).extract(input_key_material);
+
+    let expand = |label: &[u8]| -> Result<[u8; SESSION_KEY_LEN]> {
+      let mut out = [0u8; SESSION_KEY_LEN];
+      prk
+        .expand(&[label], hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut out))
+        .map_err(|_| crate::Error::Crypto("Key derivation failed".to_string()))?;
+      Ok(out)
+    };
+
+    Ok(Self {
+      initiator_to_responder: expand(b"stationuli/v2 key i2r")?,
+      responder_to_initiator: expand(b"stationuli/v2 key r2i")?,
+      initiator_confirm: hmac::Key::new(
+        hmac::HMAC_SHA256,
+        &expand(b"stationuli/v2 confirm initiator")?,
+      ),
+      responder_confirm: hmac::Key::new(
+        hmac::HMAC_SHA256,
+        &expand(b"stationuli/v2 confirm responder")?,
+      ),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crypto::Encryption;
+  use crate::p2p::memory::MemoryConnection;
+  use std::net::SocketAddr;
+
+  fn peer(name: &str) -> LocalPeer {
+    LocalPeer::generate(
+      format!("device-{}", name),
+      name.to_string(),
+      "desktop".to_string(),
+    )
+  }
+
+  fn connection_pair() -> (MemoryConnection, MemoryConnection) {
+    MemoryConnection::pair(
+      SocketAddr::from(([127, 0, 0, 1], 1)),
+      SocketAddr::from(([127, 0, 0, 1], 2)),
+    )
+  }
+
+  /// 在两端之间转发帧，`tamper` 可以修改发起方（`true`）或响应方发出的第 n 帧
+  fn relay(
+    mut initiator: MemoryConnection,
+    mut responder: MemoryConnection,
+    tamper: fn(bool, usize, Vec<u8>) -> Vec<u8>,
+  ) {
+    tokio::spawn(async move {
+      let (mut from_initiator, mut from_responder) = (0, 0);
+      loop {
+        tokio::select! {
+          frame = initiator.recv_frame() => {
+            let Ok(frame) = frame else { break };
+            let frame = tamper(true, from_initiator, frame);
+            from_initiator += 1;
+            if responder.send_frame(&frame).await.is_err() {
+              break;
+            }
+          }
+          frame = responder.recv_frame() => {
+            let Ok(frame) = frame else { break };
+            let frame = tamper(false, from_responder, frame);
+            from_responder += 1;
+            if initiator.send_frame(&frame).await.is_err() {
+              break;
+            }
+          }
+        }
+      }
+    });
+  }
+
+  /// 经由 `relay` 执行一次握手，返回双方的结果
+  async fn handshake_through(
+    tamper: fn(bool, usize, Vec<u8>) -> Vec<u8>,
+  ) -> (Result<SessionKeys>, Result<SessionKeys>) {
+    let (alice, bob) = (peer("alice"), peer("bob"));
+    let (mut initiator, initiator_end) = connection_pair();
+    let (responder_end, mut responder) = connection_pair();
+    relay(initiator_end, responder_end, tamper);
+    tokio::join!(
+      async move { Handshake::new(&alice).initiate(&mut initiator).await },
+      async move { Handshake::new(&bob).respond(&mut responder).await },
+    )
+  }
+
+  /// 修改 Hello 中的设备名称
+  fn rename_hello(frame: Vec<u8>) -> Vec<u8> {
+    match Handshake::decode(&frame).unwrap() {
+      HandshakeMessage::Hello {
+        version,
+        device_id,
+        device_type,
+        static_public_key,
+        ephemeral_public_key,
+        nonce,
+        ..
+      } => Handshake::encode(&HandshakeMessage::Hello {
+        version,
+        device_id,
+        device_name: "mallory".to_string(),
+        device_type,
+        static_public_key,
+        ephemeral_public_key,
+        nonce,
+      })
+      .unwrap(),
+      _ => panic!("expected Hello"),
+    }
+  }
+
+  #[tokio::test]
+  async fn handshake_derives_matching_keys() {
+    let (alice, bob) = (peer("alice"), peer("bob"));
+    let (initiator, responder) = (Handshake::new(&alice), Handshake::new(&bob));
+    let (mut a, mut b) = connection_pair();
+    let (initiator, responder) =
+      tokio::join!(initiator.initiate(&mut a), responder.respond(&mut b));
+    let (initiator, responder) = (initiator.unwrap(), responder.unwrap());
+
+    assert_eq!(initiator.send_key, responder.recv_key);
+    assert_eq!(initiator.recv_key, responder.send_key);
+    assert_ne!(initiator.send_key, initiator.recv_key);
+    assert_eq!(initiator.transcript_hash, responder.transcript_hash);
+    assert_eq!(initiator.peer.device_id, "device-bob");
+    assert_eq!(initiator.peer.public_key, bob.public_key());
+    assert_eq!(responder.peer.device_id, "device-alice");
+    assert_eq!(responder.peer.public_key, alice.public_key());
+
+    // 会话密钥可以直接用于帧加密
+    let mut sender = Encryption::from_session(&initiator).unwrap();
+    let mut receiver = Encryption::from_session(&responder).unwrap();
+    let ciphertext = sender.encrypt(b"hello").unwrap();
+    assert_eq!(receiver.decrypt(&ciphertext).unwrap(), b"hello");
+  }
+
+  #[tokio::test]
+  async fn handshake_sessions_are_unique() {
+    let (alice, bob) = (peer("alice"), peer("bob"));
+    let (initiator, responder) = (Handshake::new(&alice), Handshake::new(&bob));
+    let mut keys = Vec::new();
+    for _ in 0..2 {
+      let (mut a, mut b) = connection_pair();
+      let (initiator, responder) =
+        tokio::join!(initiator.initiate(&mut a), responder.respond(&mut b));
+      responder.unwrap();
+      keys.push(initiator.unwrap());
+    }
+    assert_ne!(keys[0].send_key, keys[1].send_key);
+    assert_ne!(keys[0].transcript_hash, keys[1].transcript_hash);
+  }
+
+  #[tokio::test]
+  async fn handshake_rejects_unexpected_message() {
+    let bob = peer("bob");
+    let (mut a, mut b) = connection_pair();
+    a.send_frame(b"not a handshake message").await.unwrap();
+    let result = Handshake::new(&bob).respond(&mut b).await;
+    assert!(matches!(result, Err(crate::Error::ProtocolViolation(_))));
+
+    let (mut a, mut b) = connection_pair();
+    let finished = Handshake::encode(&HandshakeMessage::Finished {
+      verify_data: vec![0; 32],
+    })
+    .unwrap();
+    a.send_frame(&finished).await.unwrap();
+    let result = Handshake::new(&bob).respond(&mut b).await;
+    assert!(matches!(result, Err(crate::Error::ProtocolViolation(_))));
+  }
+
+  #[tokio::test]
+  async fn handshake_rejects_unsupported_version() {
+    let (alice, bob) = (peer("alice"), peer("bob"));
+    let (mut a, mut b) = connection_pair();
+    let responder = tokio::spawn(async move { Handshake::new(&bob).respond(&mut b).await });
+
+    // 手工扮演发起方，发送版本不支持的 Hello
+    let hello = Handshake::encode(&HandshakeMessage::Hello {
+      version: HANDSHAKE_VERSION + 1,
+      device_id: alice.device_id.clone(),
+      device_name: alice.device_name.clone(),
+      device_type: alice.device_type.clone(),
+      static_public_key: alice.public_key(),
+      ephemeral_public_key: alice.public_key(),
+      nonce: [0; 32],
+    })
+    .unwrap();
+    let commit = HandshakeMessage::Commit {
+      hello_hash: Handshake::hash(&hello),
+    };
+    a.send_frame(&Handshake::encode(&commit).unwrap())
+      .await
+      .unwrap();
+    a.recv_frame().await.unwrap();
+    a.send_frame(&hello).await.unwrap();
+
+    let result = responder.await.unwrap();
+    assert!(matches!(result, Err(crate::Error::Protocol(_))));
+    assert!(matches!(
+      Handshake::decode(&a.recv_frame().await.unwrap()).unwrap(),
+      HandshakeMessage::Error(_)
+    ));
+  }
+
+  #[tokio::test]
+  async fn handshake_detects_tampered_initiator_hello() {
+    // 发起方的 Hello 与其承诺不一致
+    let (initiator, responder) = handshake_through(|from_initiator, index, frame| {
+      if from_initiator && index == 1 {
+        rename_hello(frame)
+      } else {
+        frame
+      }
+    })
+    .await;
+    assert!(initiator.is_err());
+    match responder {
+      Err(crate::Error::ProtocolViolation(e)) => assert!(e.contains("commitment")),
+      other => panic!("unexpected result: {:?}", other.map(|keys| keys.peer)),
+    }
+  }
+
+  #[tokio::test]
+  async fn handshake_detects_tampered_responder_hello() {
+    // 响应方的 Hello 被修改后双方的握手记录不同，密钥确认失败
+    let (initiator, responder) = handshake_through(|from_initiator, index, frame| {
+      if !from_initiator && index == 0 {
+        rename_hello(frame)
+      } else {
+        frame
+      }
+    })
+    .await;
+    assert!(initiator.is_err());
+    assert!(matches!(responder, Err(crate::Error::ProtocolViolation(_))));
+  }
+
+  #[tokio::test]
+  async fn handshake_detects_tampered_finished() {
+    let (initiator, responder) = handshake_through(|from_initiator, index, frame| {
+      if from_initiator && index == 2 {
+        Handshake::encode(&HandshakeMessage::Finished {
+          verify_data: vec![0; 32],
+        })
+        .unwrap()
+      } else {
+        frame
+      }
+    })
+    .await;
+    assert!(initiator.is_err());
+    assert!(matches!(responder, Err(crate::Error::ProtocolViolation(_))));
+  }
+}
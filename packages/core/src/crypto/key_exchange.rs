@@ -1,26 +1,79 @@
 //! 密钥交换模块（X25519）
 
 use crate::Result;
+use x25519_dalek::{PublicKey, ReusableSecret, SharedSecret, StaticSecret};
+
+/// X25519 公钥长度（字节）
+pub const PUBLIC_KEY_LEN: usize = 32;
 
 /// 密钥交换
+///
+/// 每次握手使用一次性的临时密钥对，握手结束后随 `KeyExchange` 一起销毁
 pub struct KeyExchange {
-  // TODO: 实现 X25519 密钥交换
+  secret: Option<ReusableSecret>,
+  public_key: Option<PublicKey>,
 }
 
 impl KeyExchange {
   pub fn new() -> Self {
-    Self {}
+    Self {
+      secret: None,
+      public_key: None,
+    }
+  }
+
+  /// 生成临时密钥对，返回公钥
+  pub fn generate_keypair(&mut self) -> Result<[u8; PUBLIC_KEY_LEN]> {
+    let secret = ReusableSecret::random();
+    let public_key = PublicKey::from(&secret);
+    self.secret = Some(secret);
+    self.public_key = Some(public_key);
+    Ok(public_key.to_bytes())
+  }
+
+  /// 获取本端公钥（需先调用 `generate_keypair`）
+  pub fn public_key(&self) -> Option<[u8; PUBLIC_KEY_LEN]> {
+    self.public_key.map(|key| key.to_bytes())
+  }
+
+  /// 使用临时私钥与对端公钥执行密钥交换，返回共享密钥
+  pub fn exchange(&self, peer_public_key: &[u8]) -> Result<[u8; 32]> {
+    let secret = self
+      .secret
+      .as_ref()
+      .ok_or_else(|| crate::Error::Crypto("Key pair not generated".to_string()))?;
+    Self::check_shared_secret(secret.diffie_hellman(&Self::parse_public_key(peer_public_key)?))
+  }
+
+  /// 使用长期私钥与对端公钥执行密钥交换，返回共享密钥
+  pub fn exchange_static(secret: &StaticSecret, peer_public_key: &[u8]) -> Result<[u8; 32]> {
+    Self::check_shared_secret(secret.diffie_hellman(&Self::parse_public_key(peer_public_key)?))
   }
 
-  /// 生成密钥对
-  pub fn generate_keypair(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
-    // TODO: 实现密钥对生成
-    Ok((vec![], vec![]))
+  fn parse_public_key(public_key: &[u8]) -> Result<PublicKey> {
+    let public_key: [u8; PUBLIC_KEY_LEN] = public_key.try_into().map_err(|_| {
+      crate::Error::Crypto(format!(
+        "Invalid public key length: expected {}, got {}",
+        PUBLIC_KEY_LEN,
+        public_key.len()
+      ))
+    })?;
+    Ok(PublicKey::from(public_key))
   }
 
-  /// 执行密钥交换
-  pub fn exchange(&self) -> Result<Vec<u8>> {
-    // TODO: 实现密钥交换
-    Ok(vec![])
+  /// 拒绝低阶点等非贡献性公钥，否则共享密钥可被对端预测
+  fn check_shared_secret(shared_secret: SharedSecret) -> Result<[u8; 32]> {
+    if !shared_secret.was_contributory() {
+      return Err(crate::Error::Crypto(
+        "Non-contributory peer public key".to_string(),
+      ));
+    }
+    Ok(shared_secret.to_bytes())
+  }
+}
+
+impl Default for KeyExchange {
+  fn default() -> Self {
+    Self::new()
   }
 }
@@ -1,9 +1,13 @@
 //! 加密模块
 //!
-//! 提供密钥交换、加密解密等功能
+//! 提供密钥交换、会话握手、设备配对、加密解密等功能
 
 pub mod encryption;
+pub mod handshake;
 pub mod key_exchange;
+pub mod pairing;
 
 pub use encryption::Encryption;
+pub use handshake::{Handshake, LocalPeer, PeerIdentity, SessionKeys};
 pub use key_exchange::KeyExchange;
+pub use pairing::{PairingHandler, PairingRequest, TrustStore, TrustedPeer};
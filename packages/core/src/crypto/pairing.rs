@@ -0,0 +1,187 @@
+//! 设备配对模块
+//!
+//! 首次连接时双方比对由握手记录派生的 6 位短认证串（SAS），
+//! 用户确认一致后把对端长期公钥写入受信任设备列表
+
+use crate::Result;
+use crate::crypto::handshake::PeerIdentity;
+use crate::persist;
+use base64::{Engine as _, engine::general_purpose};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// 短认证串的域分隔前缀
+const SAS_LABEL: &[u8] = b"stationuli/sas/v1";
+
+/// 由握手记录哈希派生 6 位短认证串
+pub fn short_auth_string(transcript_hash: &[u8]) -> String {
+  let mut ctx = digest::Context::new(&digest::SHA256);
+  ctx.update(SAS_LABEL);
+  ctx.update(transcript_hash);
+  let hash = ctx.finish();
+  let bytes = hash.as_ref();
+  let value = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+  format!("{:06}", value % 1_000_000)
+}
+
+/// 配对请求（交给用户确认）
+#[derive(Debug, Clone)]
+pub struct PairingRequest {
+  pub peer: PeerIdentity,
+  /// 双方屏幕上应显示相同的短认证串
+  pub code: String,
+}
+
+/// 配对确认回调：返回用户是否确认两端的短认证串一致
+pub type PairingHandler =
+  Arc<dyn Fn(PairingRequest) -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync>;
+
+/// 受信任的设备
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TrustedPeer {
+  pub device_id: String,
+  pub device_name: String,
+  pub device_type: String,
+  /// 长期公钥（Base64）
+  pub public_key: String,
+  /// 配对时间（Unix 秒）
+  pub paired_at: u64,
+}
+
+/// 受信任设备存储（按 `DeviceInfo.id` 索引）
+pub struct TrustStore {
+  path: Option<PathBuf>,
+  peers: RwLock<HashMap<String, TrustedPeer>>,
+}
+
+impl TrustStore {
+  /// 创建仅存在于内存中的存储
+  pub fn in_memory() -> Self {
+    Self {
+      path: None,
+      peers: RwLock::new(HashMap::new()),
+    }
+  }
+
+  /// 从文件加载存储（文件不存在时视为空列表）
+  pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    let path = path.as_ref().to_path_buf();
+    let list: Vec<TrustedPeer> = persist::load_json(&path, "trust store")?.unwrap_or_default();
+    let peers: HashMap<String, TrustedPeer> = list
+      .into_iter()
+      .map(|peer| (peer.device_id.clone(), peer))
+      .collect();
+
+    info!(
+      "Trust store loaded: {} ({} peers)",
+      path.display(),
+      peers.len()
+    );
+
+    Ok(Self {
+      path: Some(path),
+      peers: RwLock::new(peers),
+    })
+  }
+
+  /// 对端是否已配对（设备 ID 与长期公钥都必须匹配）
+  pub async fn is_trusted(&self, peer: &PeerIdentity) -> bool {
+    let public_key = general_purpose::STANDARD.encode(peer.public_key);
+    self
+      .peers
+      .read()
+      .await
+      .get(&peer.device_id)
+      .is_some_and(|trusted| trusted.public_key == public_key)
+  }
+
+  /// 设备 ID 是否已配对（不校验公钥）
+  pub async fn contains(&self, device_id: &str) -> bool {
+    self.peers.read().await.contains_key(device_id)
+  }
+
+  /// 对端声称的设备 ID 是否已绑定了另一把长期公钥
+  ///
+  /// 设备 ID 由对端自行声明，同一 ID 出现不同的公钥说明对端已重新生成身份，或有设备在冒充它
+  pub async fn is_key_changed(&self, peer: &PeerIdentity) -> bool {
+    let public_key = general_purpose::STANDARD.encode(peer.public_key);
+    self
+      .peers
+      .read()
+      .await
+      .get(&peer.device_id)
+      .is_some_and(|trusted| trusted.public_key != public_key)
+  }
+
+  /// 信任对端（已存在则更新名称和类型）
+  ///
+  /// 设备 ID 已绑定另一把公钥时拒绝覆盖，需先调用 `remove` 取消原有信任
+  pub async fn trust(&self, peer: &PeerIdentity) -> Result<()> {
+    let public_key = general_purpose::STANDARD.encode(peer.public_key);
+    let paired_at = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|elapsed| elapsed.as_secs())
+      .unwrap_or_default();
+
+    let mut peers = self.peers.write().await;
+    if let Some(existing) = peers.get(&peer.device_id)
+      && existing.public_key != public_key
+    {
+      warn!(
+        "Refusing to re-pin {} ({}): public key changed",
+        peer.device_name, peer.device_id
+      );
+      return Err(crate::Error::Crypto(format!(
+        "Device {} is already paired with a different key",
+        peer.device_id
+      )));
+    }
+
+    let trusted = TrustedPeer {
+      device_id: peer.device_id.clone(),
+      device_name: peer.device_name.clone(),
+      device_type: peer.device_type.clone(),
+      public_key,
+      paired_at,
+    };
+    peers.insert(trusted.device_id.clone(), trusted);
+    self.save(&peers).await?;
+    info!("Peer trusted: {} ({})", peer.device_name, peer.device_id);
+    Ok(())
+  }
+
+  /// 取消信任
+  pub async fn remove(&self, device_id: &str) -> Result<()> {
+    let mut peers = self.peers.write().await;
+    if peers.remove(device_id).is_none() {
+      return Err(crate::Error::NotFound(format!(
+        "Trusted peer not found: {}",
+        device_id
+      )));
+    }
+    self.save(&peers).await?;
+    info!("Peer untrusted: {}", device_id);
+    Ok(())
+  }
+
+  /// 获取全部受信任设备
+  pub async fn list(&self) -> Vec<TrustedPeer> {
+    self.peers.read().await.values().cloned().collect()
+  }
+
+  async fn save(&self, peers: &HashMap<String, TrustedPeer>) -> Result<()> {
+    let Some(ref path) = self.path else {
+      return Ok(());
+    };
+    let list: Vec<&TrustedPeer> = peers.values().collect();
+    persist::save_json_atomic(path, &list, "trust store").await
+  }
+}
@@ -17,7 +17,7 @@ impl FileChunk {
   /// 将文件分片
   pub fn split_file(data: &[u8], chunk_size: usize, file_name: String) -> Vec<Self> {
     let file_size = data.len() as u64;
-    let total_chunks = (data.len() + chunk_size - 1) / chunk_size;
+    let total_chunks = data.len().div_ceil(chunk_size);
     let mut chunks = Vec::with_capacity(total_chunks);
 
     for (i, chunk_data) in data.chunks(chunk_size).enumerate() {
@@ -0,0 +1,287 @@
+//! 传输帧编码模块
+//!
+//! 每条 `TransferMessage` 编码为一个二进制帧：
+//!
+//! ```text
+//! | 版本 (u8) | 消息类型 (u8) | 分片 ID (u64) | 负载长度 (u32) | 负载 |
+//! ```
+//!
+//! 整数均为大端序。分片消息的负载是 32 字节的 SHA-256 摘要加原始数据，
+//! 其余控制消息的负载为 JSON
+
+use crate::Result;
+use crate::file::transfer::{CHUNK_DIGEST_LEN, TransferMessage};
+use std::borrow::Cow;
+
+/// 传输协议版本（JSON 编码的旧协议视为版本 1）
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// 帧头长度（字节）
+pub const HEADER_LEN: usize = 1 + 1 + 8 + 4;
+
+const TYPE_START_TRANSFER: u8 = 1;
+const TYPE_CHUNK: u8 = 2;
+const TYPE_COMPLETE: u8 = 3;
+const TYPE_ERROR: u8 = 4;
+const TYPE_PAIR_REQUEST: u8 = 5;
+const TYPE_PAIR_RESPONSE: u8 = 6;
+const TYPE_RESUME_OFFER: u8 = 7;
+const TYPE_RESUME_ACCEPT: u8 = 8;
+const TYPE_RETRANSMIT: u8 = 9;
+const TYPE_VERIFIED: u8 = 10;
+const TYPE_ACCEPT: u8 = 11;
+const TYPE_DECLINE: u8 = 12;
+const TYPE_MANIFEST: u8 = 13;
+const TYPE_PAUSE: u8 = 14;
+const TYPE_RESUME: u8 = 15;
+const TYPE_QUEUED: u8 = 16;
+
+/// 将消息编码为二进制帧
+pub fn encode(message: &TransferMessage) -> Result<Vec<u8>> {
+  // 分片负载由摘要和数据两段组成，直接写入帧中，避免额外拷贝
+  let (chunk_id, head, body) = match message {
+    TransferMessage::Chunk {
+      chunk_id,
+      digest,
+      data,
+    } => (*chunk_id, &digest[..], Cow::Borrowed(&data[..])),
+    _ => {
+      let json = serde_json::to_vec(message)
+        .map_err(|e| crate::Error::Protocol(format!("Serialize failed: {}", e)))?;
+      (0, &[][..], Cow::Owned(json))
+    }
+  };
+
+  let payload_len = head.len() + body.len();
+  let encoded_len = u32::try_from(payload_len).map_err(|_| {
+    crate::Error::Protocol(format!("Frame payload too large: {} bytes", payload_len))
+  })?;
+
+  let mut frame = Vec::with_capacity(HEADER_LEN + payload_len);
+  frame.push(PROTOCOL_VERSION);
+  frame.push(message_type(message));
+  frame.extend_from_slice(&chunk_id.to_be_bytes());
+  frame.extend_from_slice(&encoded_len.to_be_bytes());
+  frame.extend_from_slice(head);
+  frame.extend_from_slice(&body);
+  Ok(frame)
+}
+
+/// 从二进制帧解码消息
+pub fn decode(frame: &[u8]) -> Result<TransferMessage> {
+  // 旧版本的对端直接发送 JSON 消息
+  if frame.first() == Some(&b'{') {
+    return Err(crate::Error::Protocol(
+      "Peer uses the legacy JSON transfer protocol".to_string(),
+    ));
+  }
+
+  if frame.len() < HEADER_LEN {
+    return Err(crate::Error::ProtocolViolation(format!(
+      "Frame too short: {} bytes",
+      frame.len()
+    )));
+  }
+
+  let version = frame[0];
+  if version != PROTOCOL_VERSION {
+    return Err(crate::Error::Protocol(format!(
+      "Unsupported protocol version: {} (expected {})",
+      version, PROTOCOL_VERSION
+    )));
+  }
+
+  let frame_type = frame[1];
+  let chunk_id = u64::from_be_bytes(frame[2..10].try_into().unwrap());
+  let payload_len = u32::from_be_bytes(frame[10..HEADER_LEN].try_into().unwrap()) as usize;
+  let payload = &frame[HEADER_LEN..];
+  if payload.len() != payload_len {
+    return Err(crate::Error::ProtocolViolation(format!(
+      "Frame length mismatch: header says {}, got {}",
+      payload_len,
+      payload.len()
+    )));
+  }
+
+  if frame_type == TYPE_CHUNK {
+    if payload.len() < CHUNK_DIGEST_LEN {
+      return Err(crate::Error::ProtocolViolation(format!(
+        "Chunk frame too short: {} bytes",
+        payload.len()
+      )));
+    }
+    let (digest, data) = payload.split_at(CHUNK_DIGEST_LEN);
+    return Ok(TransferMessage::Chunk {
+      chunk_id,
+      digest: digest.try_into().unwrap(),
+      data: data.to_vec(),
+    });
+  }
+
+  let message: TransferMessage = serde_json::from_slice(payload)
+    .map_err(|e| crate::Error::ProtocolViolation(format!("Deserialize failed: {}", e)))?;
+  if message_type(&message) != frame_type {
+    return Err(crate::Error::ProtocolViolation(format!(
+      "Frame type mismatch: header says {}, payload is {}",
+      frame_type,
+      message_type(&message)
+    )));
+  }
+  Ok(message)
+}
+
+fn message_type(message: &TransferMessage) -> u8 {
+  match message {
+    TransferMessage::StartTransfer { .. } => TYPE_START_TRANSFER,
+    TransferMessage::Chunk { .. } => TYPE_CHUNK,
+    TransferMessage::Complete => TYPE_COMPLETE,
+    TransferMessage::Error(_) => TYPE_ERROR,
+    TransferMessage::PairRequest => TYPE_PAIR_REQUEST,
+    TransferMessage::PairResponse { .. } => TYPE_PAIR_RESPONSE,
+    TransferMessage::ResumeOffer { .. } => TYPE_RESUME_OFFER,
+    TransferMessage::ResumeAccept { .. } => TYPE_RESUME_ACCEPT,
+    TransferMessage::Retransmit { .. } => TYPE_RETRANSMIT,
+    TransferMessage::Verified => TYPE_VERIFIED,
+    TransferMessage::Accept => TYPE_ACCEPT,
+    TransferMessage::Decline { .. } => TYPE_DECLINE,
+    TransferMessage::Manifest { .. } => TYPE_MANIFEST,
+    TransferMessage::Pause => TYPE_PAUSE,
+    TransferMessage::Resume => TYPE_RESUME,
+    TransferMessage::Queued => TYPE_QUEUED,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::file::manifest::{Manifest, ManifestEntry};
+
+  fn chunk() -> TransferMessage {
+    TransferMessage::Chunk {
+      chunk_id: 7,
+      digest: [0xab; CHUNK_DIGEST_LEN],
+      data: vec![1, 2, 3, 4, 5],
+    }
+  }
+
+  #[test]
+  fn chunk_round_trip() {
+    let frame = encode(&chunk()).unwrap();
+    assert_eq!(frame[0], PROTOCOL_VERSION);
+    assert_eq!(frame[1], TYPE_CHUNK);
+    assert_eq!(frame.len(), HEADER_LEN + CHUNK_DIGEST_LEN + 5);
+
+    match decode(&frame).unwrap() {
+      TransferMessage::Chunk {
+        chunk_id,
+        digest,
+        data,
+      } => {
+        assert_eq!(chunk_id, 7);
+        assert_eq!(digest, [0xab; CHUNK_DIGEST_LEN]);
+        assert_eq!(data, vec![1, 2, 3, 4, 5]);
+      }
+      other => panic!("unexpected message: {:?}", other),
+    }
+  }
+
+  #[test]
+  fn control_message_round_trip() {
+    let message = TransferMessage::StartTransfer {
+      protocol_version: PROTOCOL_VERSION,
+      file_name: "report.pdf".to_string(),
+      file_size: 1024,
+      total_chunks: 2,
+      chunk_size: 512,
+      file_hash: "00ff".to_string(),
+    };
+    match decode(&encode(&message).unwrap()).unwrap() {
+      TransferMessage::StartTransfer {
+        file_name,
+        file_size,
+        total_chunks,
+        chunk_size,
+        file_hash,
+        ..
+      } => {
+        assert_eq!(file_name, "report.pdf");
+        assert_eq!((file_size, total_chunks, chunk_size), (1024, 2, 512));
+        assert_eq!(file_hash, "00ff");
+      }
+      other => panic!("unexpected message: {:?}", other),
+    }
+
+    let manifest = Manifest {
+      name: "photos".to_string(),
+      entries: vec![ManifestEntry {
+        path: "photos/a.jpg".to_string(),
+        is_dir: false,
+        size: 10,
+        mode: Some(0o644),
+        modified: None,
+      }],
+    };
+    let message = TransferMessage::Manifest {
+      protocol_version: PROTOCOL_VERSION,
+      manifest: manifest.clone(),
+    };
+    match decode(&encode(&message).unwrap()).unwrap() {
+      TransferMessage::Manifest { manifest: got, .. } => assert_eq!(got, manifest),
+      other => panic!("unexpected message: {:?}", other),
+    }
+
+    assert!(matches!(
+      decode(&encode(&TransferMessage::Complete).unwrap()).unwrap(),
+      TransferMessage::Complete
+    ));
+  }
+
+  #[test]
+  fn rejects_other_versions() {
+    let mut frame = encode(&TransferMessage::Complete).unwrap();
+    frame[0] = PROTOCOL_VERSION + 1;
+    assert!(matches!(decode(&frame), Err(crate::Error::Protocol(_))));
+
+    let legacy = serde_json::to_vec(&TransferMessage::Error("old peer".to_string())).unwrap();
+    assert!(matches!(decode(&legacy), Err(crate::Error::Protocol(_))));
+  }
+
+  #[test]
+  fn rejects_malformed_frames() {
+    let frame = encode(&chunk()).unwrap();
+
+    // 帧头不完整
+    assert!(matches!(
+      decode(&frame[..HEADER_LEN - 1]),
+      Err(crate::Error::ProtocolViolation(_))
+    ));
+
+    // 负载长度与帧头不一致
+    assert!(matches!(
+      decode(&frame[..frame.len() - 1]),
+      Err(crate::Error::ProtocolViolation(_))
+    ));
+    let mut oversized = frame.clone();
+    oversized[10..HEADER_LEN].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert!(matches!(
+      decode(&oversized),
+      Err(crate::Error::ProtocolViolation(_))
+    ));
+
+    // 分片负载放不下摘要
+    let mut short_chunk = frame[..HEADER_LEN + 4].to_vec();
+    short_chunk[10..HEADER_LEN].copy_from_slice(&4u32.to_be_bytes());
+    assert!(matches!(
+      decode(&short_chunk),
+      Err(crate::Error::ProtocolViolation(_))
+    ));
+
+    // 帧头类型与负载不一致
+    let mut mismatched = encode(&TransferMessage::Complete).unwrap();
+    mismatched[1] = TYPE_VERIFIED;
+    assert!(matches!(
+      decode(&mismatched),
+      Err(crate::Error::ProtocolViolation(_))
+    ));
+  }
+}
@@ -0,0 +1,320 @@
+//! 传输历史模块
+//!
+//! 每次传输结束（完成、失败、被拒绝或取消）后记录一条历史：对端设备、方向、文件及其哈希、
+//! 起止时间和结果，持久化到 JSON 文件中，供界面按条件查询
+
+use crate::Result;
+use crate::crypto::PeerIdentity;
+use crate::file::manager::TransferDirection;
+use crate::persist::{self, to_hex};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tracing::info;
+
+/// 最多保留的历史记录数量，超出后删除最旧的记录
+pub const MAX_HISTORY_RECORDS: usize = 5000;
+
+/// 传输结果
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferOutcome {
+  Completed,
+  Failed,
+  Declined,
+  Cancelled,
+}
+
+/// 历史记录中的一个文件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryFile {
+  /// 文件名（多文件会话中为相对路径）
+  pub name: String,
+  /// 本地路径（接收方为保存路径，发送方为源文件路径，数据源不是本地文件时为空）
+  pub path: Option<String>,
+  pub size: u64,
+  /// 文件内容的 SHA-256（十六进制）
+  pub hash: String,
+}
+
+/// 一条传输历史
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryRecord {
+  pub id: String,
+  pub direction: TransferDirection,
+  /// 对端设备 ID（握手完成前结束的传输为空）
+  pub peer_id: Option<String>,
+  pub peer_name: Option<String>,
+  /// 对端地址（IP:端口）
+  pub peer_address: String,
+  /// 传输名称（单个文件时为文件名，多文件会话时为清单名称）
+  pub name: String,
+  /// 已完整传输并通过校验的文件
+  pub files: Vec<HistoryFile>,
+  /// 计划传输的总字节数
+  pub total_size: u64,
+  /// 开始和结束时间（Unix 秒）
+  pub started_at: u64,
+  pub finished_at: u64,
+  pub outcome: TransferOutcome,
+  pub error: Option<String>,
+}
+
+impl HistoryRecord {
+  /// 创建一条进行中的记录（结果在传输结束后填写）
+  pub fn new(
+    direction: TransferDirection,
+    name: String,
+    peer_address: String,
+    total_size: u64,
+  ) -> Self {
+    Self {
+      id: new_record_id(),
+      direction,
+      peer_id: None,
+      peer_name: None,
+      peer_address,
+      name,
+      files: Vec::new(),
+      total_size,
+      started_at: now_secs(),
+      finished_at: 0,
+      outcome: TransferOutcome::Completed,
+      error: None,
+    }
+  }
+
+  /// 记录对端身份
+  pub fn set_peer(&mut self, peer: &PeerIdentity) {
+    self.peer_id = Some(peer.device_id.clone());
+    self.peer_name = Some(peer.device_name.clone());
+  }
+
+  /// 根据传输结果填写结束时间和结果
+  ///
+  /// 已标记为拒绝或取消的记录保留原结果
+  pub fn finish<T>(&mut self, result: &Result<T>) {
+    self.finished_at = now_secs();
+    if let Err(e) = result {
+      if self.outcome == TransferOutcome::Completed {
+        self.outcome = TransferOutcome::Failed;
+      }
+      self.error = Some(e.to_string());
+    }
+  }
+
+  /// 标记为被接收方拒绝
+  pub fn set_declined(&mut self, reason: &str) {
+    self.outcome = TransferOutcome::Declined;
+    self.error = Some(reason.to_string());
+  }
+
+  fn matches(&self, query: &HistoryQuery) -> bool {
+    if query
+      .peer_id
+      .as_ref()
+      .is_some_and(|id| self.peer_id.as_ref() != Some(id))
+    {
+      return false;
+    }
+    if query
+      .direction
+      .is_some_and(|direction| self.direction != direction)
+    {
+      return false;
+    }
+    if query.outcome.is_some_and(|outcome| self.outcome != outcome) {
+      return false;
+    }
+    if query.since.is_some_and(|since| self.started_at < since) {
+      return false;
+    }
+    if query.until.is_some_and(|until| self.started_at >= until) {
+      return false;
+    }
+    if let Some(ref keyword) = query.keyword {
+      // 按名称、文件名和对端名称做不区分大小写的模糊匹配
+      let keyword = keyword.to_lowercase();
+      let found = self.name.to_lowercase().contains(&keyword)
+        || self
+          .peer_name
+          .as_ref()
+          .is_some_and(|name| name.to_lowercase().contains(&keyword))
+        || self
+          .files
+          .iter()
+          .any(|file| file.name.to_lowercase().contains(&keyword));
+      if !found {
+        return false;
+      }
+    }
+    true
+  }
+}
+
+/// 历史查询条件（未设置的条件不参与过滤）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryQuery {
+  pub peer_id: Option<String>,
+  pub direction: Option<TransferDirection>,
+  pub outcome: Option<TransferOutcome>,
+  /// 开始时间范围（Unix 秒，左闭右开）
+  pub since: Option<u64>,
+  pub until: Option<u64>,
+  /// 关键字（匹配名称、文件名和对端名称）
+  pub keyword: Option<String>,
+  /// 分页：跳过的记录数和最多返回的记录数
+  pub offset: usize,
+  pub limit: Option<usize>,
+}
+
+/// 传输历史存储（按时间顺序保存）
+pub struct TransferHistory {
+  path: Option<PathBuf>,
+  records: RwLock<Vec<HistoryRecord>>,
+}
+
+impl TransferHistory {
+  /// 创建仅存在于内存中的存储
+  pub fn new() -> Self {
+    Self {
+      path: None,
+      records: RwLock::new(Vec::new()),
+    }
+  }
+
+  /// 从文件加载存储（文件不存在时视为空列表）
+  pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    let path = path.as_ref().to_path_buf();
+    let records: Vec<HistoryRecord> =
+      persist::load_json(&path, "transfer history")?.unwrap_or_default();
+
+    info!(
+      "Transfer history loaded: {} ({} records)",
+      path.display(),
+      records.len()
+    );
+
+    Ok(Self {
+      path: Some(path),
+      records: RwLock::new(records),
+    })
+  }
+
+  /// 添加一条记录
+  pub async fn add(&self, record: HistoryRecord) -> Result<()> {
+    let mut records = self.records.write().await;
+    records.push(record);
+    if records.len() > MAX_HISTORY_RECORDS {
+      let excess = records.len() - MAX_HISTORY_RECORDS;
+      records.drain(..excess);
+    }
+    self.save(&records).await
+  }
+
+  /// 按条件查询（最新的记录在前）
+  pub async fn query(&self, query: &HistoryQuery) -> Vec<HistoryRecord> {
+    self
+      .records
+      .read()
+      .await
+      .iter()
+      .rev()
+      .filter(|record| record.matches(query))
+      .skip(query.offset)
+      .take(query.limit.unwrap_or(usize::MAX))
+      .cloned()
+      .collect()
+  }
+
+  /// 获取一条记录
+  pub async fn get(&self, id: &str) -> Option<HistoryRecord> {
+    self
+      .records
+      .read()
+      .await
+      .iter()
+      .find(|record| record.id == id)
+      .cloned()
+  }
+
+  /// 删除一条记录（不会删除已接收的文件）
+  pub async fn remove(&self, id: &str) -> Result<()> {
+    let mut records = self.records.write().await;
+    let index = records
+      .iter()
+      .position(|record| record.id == id)
+      .ok_or_else(|| crate::Error::NotFound(format!("History record not found: {}", id)))?;
+    records.remove(index);
+    self.save(&records).await
+  }
+
+  /// 清空全部记录
+  pub async fn clear(&self) -> Result<()> {
+    let mut records = self.records.write().await;
+    records.clear();
+    self.save(&records).await
+  }
+
+  /// 获取记录中某个文件的本地路径，用于在文件管理器中显示
+  ///
+  /// 文件已被移动或删除时返回 `NotFound`
+  pub async fn file_path(&self, id: &str, file_index: usize) -> Result<PathBuf> {
+    let record = self
+      .get(id)
+      .await
+      .ok_or_else(|| crate::Error::NotFound(format!("History record not found: {}", id)))?;
+    let path = record
+      .files
+      .get(file_index)
+      .and_then(|file| file.path.as_ref())
+      .map(PathBuf::from)
+      .ok_or_else(|| {
+        crate::Error::NotFound(format!("No local file in record {}: #{}", id, file_index))
+      })?;
+    if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+      return Err(crate::Error::NotFound(format!(
+        "File no longer exists: {}",
+        path.display()
+      )));
+    }
+    Ok(path)
+  }
+
+  async fn save(&self, records: &[HistoryRecord]) -> Result<()> {
+    match self.path {
+      Some(ref path) => persist::save_json_atomic(path, records, "transfer history").await,
+      None => Ok(()),
+    }
+  }
+}
+
+impl Default for TransferHistory {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// 生成随机的记录 ID
+fn new_record_id() -> String {
+  let mut bytes = [0u8; 8];
+  // 系统随机数不可用时退化为时间戳，ID 只需在本机唯一
+  if SystemRandom::new().fill(&mut bytes).is_err() {
+    let nanos = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_nanos() as u64;
+    bytes = nanos.to_be_bytes();
+  }
+  to_hex(&bytes)
+}
+
+fn now_secs() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_secs()
+}
@@ -0,0 +1,984 @@
+//! 传输任务管理模块
+//!
+//! 为每个入站和出站传输分配 ID 并跟踪其状态（排队、进行中、暂停、完成、失败、取消），
+//! 按并发上限调度执行，支持暂停、恢复和随时取消。状态变化通过广播通知订阅者
+
+use crate::Result;
+use crate::file::history::{HistoryRecord, TransferOutcome};
+use crate::file::manifest::SessionProgress;
+use crate::file::progress::{ProgressTracker, TransferProgress};
+use crate::file::transfer::{FileTransfer, IncomingConnection, ReceiveOutcome};
+use crate::p2p::addr;
+use crate::p2p::tcp::TcpTransport;
+use crate::p2p::transport::{FramedConnection, Transport};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{Notify, RwLock, broadcast, watch};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// 默认同时进行的传输数量
+pub const DEFAULT_MAX_CONCURRENT: usize = 3;
+
+/// 状态事件通道容量
+const TRANSFER_EVENT_CAPACITY: usize = 64;
+
+/// 进度事件通道容量
+const PROGRESS_EVENT_CAPACITY: usize = 256;
+
+/// 传输方向
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferDirection {
+  Outbound,
+  Inbound,
+}
+
+/// 传输状态
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+  /// 等待空闲的并发名额
+  Queued,
+  Running,
+  Paused,
+  Done,
+  Failed,
+  Cancelled,
+}
+
+impl TransferState {
+  /// 是否已结束（不会再变化）
+  pub fn is_finished(self) -> bool {
+    matches!(
+      self,
+      TransferState::Done | TransferState::Failed | TransferState::Cancelled
+    )
+  }
+}
+
+/// 传输任务信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferInfo {
+  pub id: String,
+  pub direction: TransferDirection,
+  /// 文件或会话名称（入站传输在收到文件信息前为空）
+  pub name: String,
+  /// 对端地址
+  pub peer: String,
+  pub state: TransferState,
+  /// 已传输字节数
+  pub transferred: u64,
+  /// 总字节数
+  pub total: u64,
+  /// 失败原因
+  pub error: Option<String>,
+  /// 是否由对端暂停（入站传输暂停时界面显示"发送方已暂停"）
+  pub paused_by_peer: bool,
+  /// 创建时间（Unix 秒）
+  pub created_at: u64,
+}
+
+struct TransferJob {
+  seq: u64,
+  info: TransferInfo,
+  cancel: CancellationToken,
+  pause: watch::Sender<bool>,
+  tracker: ProgressTracker,
+}
+
+struct Shared {
+  jobs: Mutex<HashMap<String, TransferJob>>,
+  next_seq: AtomicU64,
+  running: Mutex<usize>,
+  max_concurrent: AtomicUsize,
+  slot_released: Notify,
+  events: broadcast::Sender<TransferInfo>,
+  progress: broadcast::Sender<TransferProgress>,
+}
+
+impl Shared {
+  /// 修改任务信息；`notify` 为 true 时广播修改后的信息
+  fn update(&self, id: &str, notify: bool, f: impl FnOnce(&mut TransferInfo)) {
+    let info = {
+      let mut jobs = self.jobs.lock().unwrap();
+      let Some(job) = jobs.get_mut(id) else {
+        return;
+      };
+      f(&mut job.info);
+      job.info.clone()
+    };
+    if notify {
+      // 没有订阅者时发送会失败，忽略即可
+      let _ = self.events.send(info);
+    }
+  }
+
+  /// 更新进度，并按 `PROGRESS_INTERVAL` 限频广播
+  fn report(&self, id: &str, transferred: u64, total: u64, session: Option<&SessionProgress>) {
+    let progress = {
+      let mut jobs = self.jobs.lock().unwrap();
+      let Some(job) = jobs.get_mut(id) else {
+        return;
+      };
+      job.info.transferred = transferred;
+      job.info.total = total;
+      let Some((speed, average_speed, eta_secs)) = job.tracker.record(transferred, total) else {
+        return;
+      };
+
+      let (current_file, file_index, file_count) = match session {
+        Some(session) => (
+          Some(session.file_name.clone()),
+          session.file_index,
+          session.file_count,
+        ),
+        None => (Some(job.info.name.clone()), 0, 1),
+      };
+      TransferProgress {
+        transfer_id: id.to_string(),
+        direction: job.info.direction,
+        transferred,
+        total,
+        speed,
+        average_speed,
+        eta_secs,
+        current_file,
+        file_index,
+        file_count,
+      }
+    };
+    let _ = self.progress.send(progress);
+  }
+
+  /// 等待空闲的并发名额
+  async fn acquire(self: &Arc<Self>) -> SlotGuard {
+    loop {
+      // 先注册等待再检查，避免错过检查之后释放名额的通知
+      let released = self.slot_released.notified();
+      {
+        let mut running = self.running.lock().unwrap();
+        if *running < self.max_concurrent.load(Ordering::SeqCst) {
+          *running += 1;
+          return SlotGuard {
+            shared: self.clone(),
+          };
+        }
+      }
+      released.await;
+    }
+  }
+}
+
+/// 并发名额，释放时唤醒排队的任务
+struct SlotGuard {
+  shared: Arc<Shared>,
+}
+
+impl Drop for SlotGuard {
+  fn drop(&mut self) {
+    *self.shared.running.lock().unwrap() -= 1;
+    self.shared.slot_released.notify_waiters();
+  }
+}
+
+/// 任务未正常结束（执行中的 future 被丢弃，例如服务停止时中止连接）时标记为已取消
+struct FinishGuard {
+  id: String,
+  shared: Arc<Shared>,
+}
+
+impl Drop for FinishGuard {
+  fn drop(&mut self) {
+    let unfinished = self
+      .shared
+      .jobs
+      .lock()
+      .unwrap()
+      .get(&self.id)
+      .is_some_and(|job| !job.info.state.is_finished());
+    if unfinished {
+      info!("Transfer aborted: {}", self.id);
+      self.shared.update(&self.id, true, |info| {
+        info.state = TransferState::Cancelled;
+        info.paused_by_peer = false;
+      });
+    }
+  }
+}
+
+/// 已登记、等待开始的任务
+struct PendingJob<T: Transport> {
+  manager: TransferManager<T>,
+  handle: TransferHandle,
+  direction: TransferDirection,
+  cancel: CancellationToken,
+  pause_signal: watch::Receiver<bool>,
+}
+
+impl<T: Transport> PendingJob<T> {
+  /// 等待并发名额，返回为该任务配置好的 `FileTransfer` 快照和占用的名额
+  async fn start(&self) -> (FileTransfer<T>, SlotGuard) {
+    let slot = self.manager.shared.acquire().await;
+    let id = &self.handle.id;
+    self
+      .manager
+      .shared
+      .update(id, true, |info| info.state = TransferState::Running);
+    let mut transfer = self.manager.transfer.read().await.clone();
+    match self.direction {
+      TransferDirection::Outbound => transfer.set_pause_signal(self.pause_signal.clone()),
+      TransferDirection::Inbound => {
+        let pause_handle = self.handle.clone();
+        transfer
+          .set_peer_pause_handler(Arc::new(move |paused| pause_handle.set_peer_paused(paused)));
+        let progress_handle = self.handle.clone();
+        transfer.set_receive_progress_handler(Arc::new(move |progress| {
+          progress_handle.set_session_progress(progress)
+        }));
+        transfer.set_cancel_signal(self.cancel.clone());
+      }
+    }
+    (transfer, slot)
+  }
+
+  /// 任务被取消时返回的错误
+  fn cancelled_error(&self) -> crate::Error {
+    crate::Error::File(format!("Transfer cancelled: {}", self.handle.id))
+  }
+}
+
+/// 传输任务句柄，任务执行过程中通过它上报进度
+#[derive(Clone)]
+pub struct TransferHandle {
+  id: String,
+  shared: Arc<Shared>,
+}
+
+impl TransferHandle {
+  pub fn id(&self) -> &str {
+    &self.id
+  }
+
+  /// 更新单个文件的进度
+  pub fn set_progress(&self, transferred: u64, total: u64) {
+    self.shared.report(&self.id, transferred, total, None);
+  }
+
+  /// 更新多文件会话的进度（包含当前文件）
+  pub fn set_session_progress(&self, progress: &SessionProgress) {
+    self.shared.report(
+      &self.id,
+      progress.total_sent,
+      progress.total_size,
+      Some(progress),
+    );
+  }
+
+  /// 更新对端暂停状态（入站传输收到发送方的暂停、恢复消息时调用）
+  pub fn set_peer_paused(&self, paused: bool) {
+    self.shared.update(&self.id, true, |info| {
+      info.paused_by_peer = paused;
+      info.state = if paused {
+        TransferState::Paused
+      } else {
+        TransferState::Running
+      };
+    });
+  }
+
+  /// 更新名称（入站传输收到文件信息后调用）
+  pub fn set_name(&self, name: String) {
+    self.shared.update(&self.id, true, |info| info.name = name);
+  }
+}
+
+/// 传输任务管理器
+///
+/// 每个任务执行时使用 `FileTransfer` 的一份快照，不会长时间持有其锁，
+/// 因此传输进行期间仍可以更新设备信息等配置
+pub struct TransferManager<T: Transport = TcpTransport> {
+  transfer: Arc<RwLock<FileTransfer<T>>>,
+  shared: Arc<Shared>,
+}
+
+impl<T: Transport> Clone for TransferManager<T> {
+  fn clone(&self) -> Self {
+    Self {
+      transfer: self.transfer.clone(),
+      shared: self.shared.clone(),
+    }
+  }
+}
+
+impl<T: Transport> TransferManager<T> {
+  pub fn new(transfer: Arc<RwLock<FileTransfer<T>>>) -> Self {
+    let (events, _) = broadcast::channel(TRANSFER_EVENT_CAPACITY);
+    let (progress, _) = broadcast::channel(PROGRESS_EVENT_CAPACITY);
+    Self {
+      transfer,
+      shared: Arc::new(Shared {
+        jobs: Mutex::new(HashMap::new()),
+        next_seq: AtomicU64::new(1),
+        running: Mutex::new(0),
+        max_concurrent: AtomicUsize::new(DEFAULT_MAX_CONCURRENT),
+        slot_released: Notify::new(),
+        events,
+        progress,
+      }),
+    }
+  }
+
+  /// 设置同时进行的传输数量上限（至少为 1）
+  ///
+  /// 调低上限不会中断进行中的传输，只影响之后开始的任务
+  pub fn set_max_concurrent(&self, max_concurrent: usize) {
+    self
+      .shared
+      .max_concurrent
+      .store(max_concurrent.max(1), Ordering::SeqCst);
+    self.shared.slot_released.notify_waiters();
+  }
+
+  /// 获取同时进行的传输数量上限
+  pub fn max_concurrent(&self) -> usize {
+    self.shared.max_concurrent.load(Ordering::SeqCst)
+  }
+
+  /// 订阅任务状态变化
+  pub fn subscribe(&self) -> broadcast::Receiver<TransferInfo> {
+    self.shared.events.subscribe()
+  }
+
+  /// 订阅传输进度（已限频，收发两个方向都有）
+  pub fn subscribe_progress(&self) -> broadcast::Receiver<TransferProgress> {
+    self.shared.progress.subscribe()
+  }
+
+  /// 获取全部任务（按创建顺序）
+  pub fn list(&self) -> Vec<TransferInfo> {
+    let jobs = self.shared.jobs.lock().unwrap();
+    let mut list: Vec<_> = jobs.values().collect();
+    list.sort_by_key(|job| job.seq);
+    list.into_iter().map(|job| job.info.clone()).collect()
+  }
+
+  /// 获取单个任务
+  pub fn get(&self, id: &str) -> Option<TransferInfo> {
+    let jobs = self.shared.jobs.lock().unwrap();
+    jobs.get(id).map(|job| job.info.clone())
+  }
+
+  /// 取消任务（排队中的任务不再开始，进行中的出站任务立即断开连接，
+  /// 入站任务通知发送方后断开并删除未收完的临时文件）
+  pub fn cancel(&self, id: &str) -> Result<()> {
+    let jobs = self.shared.jobs.lock().unwrap();
+    let job = jobs
+      .get(id)
+      .ok_or_else(|| crate::Error::NotFound(format!("Transfer not found: {}", id)))?;
+    if job.info.state.is_finished() {
+      return Err(crate::Error::File(format!(
+        "Transfer already finished: {}",
+        id
+      )));
+    }
+    job.cancel.cancel();
+    Ok(())
+  }
+
+  /// 暂停出站传输：在当前分片发送完后停止读取文件，连接保持，并通知接收方
+  pub fn pause(&self, id: &str) -> Result<()> {
+    self.set_paused(id, true)
+  }
+
+  /// 恢复已暂停的出站传输
+  pub fn resume(&self, id: &str) -> Result<()> {
+    self.set_paused(id, false)
+  }
+
+  fn set_paused(&self, id: &str, paused: bool) -> Result<()> {
+    let info = {
+      let mut jobs = self.shared.jobs.lock().unwrap();
+      let job = jobs
+        .get_mut(id)
+        .ok_or_else(|| crate::Error::NotFound(format!("Transfer not found: {}", id)))?;
+      if job.info.direction != TransferDirection::Outbound {
+        return Err(crate::Error::File(format!(
+          "Only outbound transfers can be paused: {}",
+          id
+        )));
+      }
+      let (from, to) = if paused {
+        (TransferState::Running, TransferState::Paused)
+      } else {
+        (TransferState::Paused, TransferState::Running)
+      };
+      if job.info.state != from {
+        return Err(crate::Error::File(format!(
+          "Transfer is not {:?}: {}",
+          from, id
+        )));
+      }
+      job.pause.send_replace(paused);
+      job.info.state = to;
+      job.info.clone()
+    };
+    let _ = self.shared.events.send(info);
+    Ok(())
+  }
+
+  /// 清除已结束的任务
+  pub fn clear_finished(&self) {
+    let mut jobs = self.shared.jobs.lock().unwrap();
+    jobs.retain(|_, job| !job.info.state.is_finished());
+  }
+
+  /// 以任务的形式执行一次传输
+  ///
+  /// 任务先排队等待并发名额，然后以 `FileTransfer` 的快照执行 `task`。
+  /// 任务被取消时 `task` 的 future 会被直接丢弃（连接随之关闭），返回错误
+  pub async fn run<R, F, Fut>(
+    &self,
+    direction: TransferDirection,
+    name: String,
+    peer: String,
+    task: F,
+  ) -> Result<R>
+  where
+    F: FnOnce(FileTransfer<T>, TransferHandle) -> Fut,
+    Fut: Future<Output = Result<R>>,
+  {
+    self
+      .run_job(direction, name, peer, |job| async move {
+        tokio::select! {
+          result = async {
+            let (transfer, _slot) = job.start().await;
+            task(transfer, job.handle.clone()).await
+          } => result,
+          _ = job.cancel.cancelled() => Err(job.cancelled_error()),
+        }
+      })
+      .await
+  }
+
+  /// 登记任务并执行 `task`，根据结果更新任务状态
+  ///
+  /// `task` 负责通过 `PendingJob::start` 等待并发名额，并自行响应取消信号
+  async fn run_job<R, F, Fut>(
+    &self,
+    direction: TransferDirection,
+    name: String,
+    peer: String,
+    task: F,
+  ) -> Result<R>
+  where
+    F: FnOnce(PendingJob<T>) -> Fut,
+    Fut: Future<Output = Result<R>>,
+  {
+    let (handle, cancel, pause_signal) = self.register(direction, name, peer);
+    let _finish_guard = FinishGuard {
+      id: handle.id.clone(),
+      shared: self.shared.clone(),
+    };
+
+    let result = task(PendingJob {
+      manager: self.clone(),
+      handle: handle.clone(),
+      direction,
+      cancel: cancel.clone(),
+      pause_signal,
+    })
+    .await;
+
+    let cancelled = result.is_err() && cancel.is_cancelled();
+    let (state, error) = match result {
+      Ok(_) => (TransferState::Done, None),
+      Err(_) if cancelled => (TransferState::Cancelled, None),
+      Err(ref e) => (TransferState::Failed, Some(e.to_string())),
+    };
+
+    // 完成、失败和入站任务的取消由 FileTransfer 记录历史；
+    // 已开始的出站任务被取消时 future 被直接丢弃，在这里补记
+    if cancelled
+      && direction == TransferDirection::Outbound
+      && let Some(info) = self.get(&handle.id)
+      && info.state != TransferState::Queued
+    {
+      let mut record = HistoryRecord::new(direction, info.name, info.peer, info.total);
+      record.started_at = info.created_at;
+      record.outcome = TransferOutcome::Cancelled;
+      record.finish(&Ok(()));
+      let history = self.transfer.read().await.history();
+      if let Err(e) = history.add(record).await {
+        warn!("Save transfer history failed: {}", e);
+      }
+    }
+
+    self.shared.update(&handle.id, true, |info| {
+      info.state = state;
+      info.error = error;
+      info.paused_by_peer = false;
+    });
+
+    if cancelled {
+      info!("Transfer cancelled: {}", handle.id);
+      return Err(crate::Error::File(format!(
+        "Transfer cancelled: {}",
+        handle.id
+      )));
+    }
+    result
+  }
+
+  /// 发送单个文件
+  pub async fn send_file(
+    &self,
+    file_path: &str,
+    target_address: &str,
+    target_port: u16,
+  ) -> Result<()> {
+    let name = std::path::Path::new(file_path)
+      .file_name()
+      .and_then(|n| n.to_str())
+      .unwrap_or(file_path)
+      .to_string();
+    let peer = addr::format_host_port(target_address, target_port);
+    self
+      .run(
+        TransferDirection::Outbound,
+        name,
+        peer,
+        |transfer, handle| async move {
+          transfer
+            .send_file_with_progress(
+              file_path,
+              target_address,
+              target_port,
+              Some(Box::new(move |sent, total| {
+                handle.set_progress(sent, total)
+              })),
+            )
+            .await
+        },
+      )
+      .await
+  }
+
+  /// 在一个会话中发送多个文件或整个目录
+  pub async fn send_paths(
+    &self,
+    paths: &[String],
+    target_address: &str,
+    target_port: u16,
+  ) -> Result<()> {
+    let name = match paths {
+      [path] => std::path::Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(path)
+        .to_string(),
+      _ => format!("{} items", paths.len()),
+    };
+    let peer = addr::format_host_port(target_address, target_port);
+    self
+      .run(
+        TransferDirection::Outbound,
+        name,
+        peer,
+        |transfer, handle| async move {
+          transfer
+            .send_paths(
+              paths,
+              target_address,
+              target_port,
+              Some(Box::new(move |progress| {
+                handle.set_session_progress(progress)
+              })),
+            )
+            .await
+        },
+      )
+      .await
+  }
+
+  /// 处理一个入站连接
+  ///
+  /// 握手、配对和用户确认不登记任务也不占用并发名额，
+  /// 用户同意接收后才作为任务排队接收文件内容。排队期间定期通知发送方，避免其等待超时；
+  /// 任务被取消时通知发送方，已开始接收的文件删除临时文件和检查点
+  pub async fn receive<C: FramedConnection>(
+    &self,
+    save_path: &str,
+    connection: C,
+  ) -> Result<ReceiveOutcome> {
+    let transfer = self.transfer.read().await.clone();
+    let mut pending = match transfer.accept_connection(save_path, connection).await? {
+      IncomingConnection::Done(outcome) => return Ok(outcome),
+      IncomingConnection::Accepted(pending) => pending,
+    };
+
+    let name = pending.name().to_string();
+    let peer = pending.peer_addr().to_string();
+    self
+      .run_job(TransferDirection::Inbound, name, peer, |job| async move {
+        let started = tokio::select! {
+          started = transfer.wait_queued(&mut pending, job.start()) => Some(started),
+          _ = job.cancel.cancelled() => None,
+        };
+        let Some(started) = started else {
+          transfer.cancel_accepted(pending).await;
+          return Err(job.cancelled_error());
+        };
+        let (transfer, _slot) = started?;
+        transfer.receive_accepted(pending).await
+      })
+      .await
+  }
+
+  /// 登记新任务（排队状态）
+  fn register(
+    &self,
+    direction: TransferDirection,
+    name: String,
+    peer: String,
+  ) -> (TransferHandle, CancellationToken, watch::Receiver<bool>) {
+    let seq = self.shared.next_seq.fetch_add(1, Ordering::SeqCst);
+    let id = format!("job-{}", seq);
+    let info = TransferInfo {
+      id: id.clone(),
+      direction,
+      name,
+      peer,
+      state: TransferState::Queued,
+      transferred: 0,
+      total: 0,
+      error: None,
+      paused_by_peer: false,
+      created_at: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs(),
+    };
+
+    let cancel = CancellationToken::new();
+    let (pause, pause_signal) = watch::channel(false);
+    self.shared.jobs.lock().unwrap().insert(
+      id.clone(),
+      TransferJob {
+        seq,
+        info: info.clone(),
+        cancel: cancel.clone(),
+        pause,
+        tracker: ProgressTracker::new(),
+      },
+    );
+    let _ = self.shared.events.send(info);
+
+    let handle = TransferHandle {
+      id,
+      shared: self.shared.clone(),
+    };
+    (handle, cancel, pause_signal)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crypto::{Encryption, Handshake, LocalPeer, PeerIdentity};
+  use crate::file::frame::{self, PROTOCOL_VERSION};
+  use crate::file::history::HistoryQuery;
+  use crate::file::transfer::{CHUNK_DIGEST_LEN, MIN_CHUNK_SIZE, TransferMessage};
+  use crate::p2p::memory::{MemoryConnection, MemoryNetwork, MemoryTransport};
+  use std::io::Cursor;
+  use std::net::SocketAddr;
+  use std::path::PathBuf;
+  use std::time::Duration;
+  use tokio::sync::oneshot;
+
+  /// 每个测试使用独立的临时目录
+  fn test_dir(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+      "stationuli-manager-{}-{}-{}",
+      name,
+      std::process::id(),
+      NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn local_peer(name: &str) -> LocalPeer {
+    LocalPeer::generate(
+      format!("device-{}", name),
+      name.to_string(),
+      "desktop".to_string(),
+    )
+  }
+
+  fn identity(peer: &LocalPeer) -> PeerIdentity {
+    PeerIdentity {
+      device_id: peer.device_id.clone(),
+      device_name: peer.device_name.clone(),
+      device_type: peer.device_type.clone(),
+      public_key: peer.public_key(),
+    }
+  }
+
+  /// 只有一个并发名额的接收方任务管理器
+  fn receiver_manager() -> TransferManager<MemoryTransport> {
+    let mut receiver = FileTransfer::with_transport(MemoryNetwork::new().transport());
+    receiver.set_local_peer(local_peer("receiver"));
+    let manager = TransferManager::new(Arc::new(RwLock::new(receiver)));
+    manager.set_max_concurrent(1);
+    manager
+  }
+
+  /// 启动一个占用并发名额的任务，发送信号后结束
+  async fn occupy_slot(manager: &TransferManager<MemoryTransport>) -> oneshot::Sender<()> {
+    let (release, released) = oneshot::channel::<()>();
+    let busy = manager.clone();
+    tokio::spawn(async move {
+      busy
+        .run(
+          TransferDirection::Outbound,
+          "busy".to_string(),
+          "peer".to_string(),
+          |_, _| async move {
+            let _ = released.await;
+            Ok(())
+          },
+        )
+        .await
+    });
+    wait_for(manager, TransferDirection::Outbound, TransferState::Running).await;
+    release
+  }
+
+  /// 等待指定方向的任务进入指定状态
+  async fn wait_for(
+    manager: &TransferManager<MemoryTransport>,
+    direction: TransferDirection,
+    state: TransferState,
+  ) -> TransferInfo {
+    loop {
+      if let Some(info) = manager
+        .list()
+        .into_iter()
+        .find(|info| info.direction == direction && info.state == state)
+      {
+        return info;
+      }
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+  }
+
+  /// 与接收方互相信任的发送方，连接到接收方管理器的入站处理
+  async fn connect_sender(
+    manager: &TransferManager<MemoryTransport>,
+    save_path: &std::path::Path,
+  ) -> (
+    FileTransfer<MemoryTransport>,
+    u16,
+    tokio::task::JoinHandle<Result<ReceiveOutcome>>,
+  ) {
+    let network = MemoryNetwork::new();
+    let listener = network.bind(0).unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let mut sender = FileTransfer::with_transport(network.transport());
+    sender.set_local_peer(local_peer("sender"));
+    let receiver = manager.transfer.read().await;
+    receiver
+      .trust_store()
+      .trust(&identity(sender.local_peer()))
+      .await
+      .unwrap();
+    sender
+      .trust_store()
+      .trust(&identity(receiver.local_peer()))
+      .await
+      .unwrap();
+    drop(receiver);
+
+    let manager = manager.clone();
+    let save_path = save_path.to_string_lossy().to_string();
+    let task = tokio::spawn(async move {
+      let connection = listener.accept().await?;
+      manager.receive(&save_path, connection).await
+    });
+    (sender, port, task)
+  }
+
+  async fn recv_message(connection: &mut MemoryConnection) -> TransferMessage {
+    frame::decode(&connection.recv_frame().await.unwrap()).unwrap()
+  }
+
+  #[tokio::test]
+  async fn queues_jobs_beyond_concurrency_limit() {
+    let manager = receiver_manager();
+    let release = occupy_slot(&manager).await;
+
+    let queued = manager.clone();
+    let task = tokio::spawn(async move {
+      queued
+        .run(
+          TransferDirection::Outbound,
+          "queued".to_string(),
+          "peer".to_string(),
+          |_, _| async move { Ok(()) },
+        )
+        .await
+    });
+    let info = wait_for(&manager, TransferDirection::Outbound, TransferState::Queued).await;
+    assert_eq!(info.name, "queued");
+
+    release.send(()).unwrap();
+    task.await.unwrap().unwrap();
+    assert_eq!(manager.get(&info.id).unwrap().state, TransferState::Done);
+  }
+
+  #[tokio::test]
+  async fn queued_inbound_transfer_completes_after_slot_frees() {
+    let dir = test_dir("queued");
+    let manager = receiver_manager();
+    let release = occupy_slot(&manager).await;
+    let (sender, port, receive) = connect_sender(&manager, &dir).await;
+
+    let data = b"queued transfer".to_vec();
+    let send = tokio::spawn(async move {
+      let size = data.len() as u64;
+      sender
+        .send_stream(
+          Cursor::new(data),
+          "queued.txt",
+          size,
+          "127.0.0.1",
+          port,
+          None,
+        )
+        .await
+    });
+    // 排队期间发送方收到排队通知，继续等待 Accept
+    wait_for(&manager, TransferDirection::Inbound, TransferState::Queued).await;
+
+    release.send(()).unwrap();
+    send.await.unwrap().unwrap();
+    let Ok(ReceiveOutcome::File(path)) = receive.await.unwrap() else {
+      panic!("expected a received file");
+    };
+    assert_eq!(std::fs::read(path).unwrap(), b"queued transfer");
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn cancelling_queued_inbound_transfer_notifies_sender() {
+    let dir = test_dir("cancel-queued");
+    let manager = receiver_manager();
+    let _release = occupy_slot(&manager).await;
+    let (sender, port, receive) = connect_sender(&manager, &dir).await;
+
+    let send = tokio::spawn(async move {
+      sender
+        .send_stream(
+          Cursor::new(vec![1u8; 16]),
+          "a.bin",
+          16,
+          "127.0.0.1",
+          port,
+          None,
+        )
+        .await
+    });
+    let info = wait_for(&manager, TransferDirection::Inbound, TransferState::Queued).await;
+    manager.cancel(&info.id).unwrap();
+
+    assert!(receive.await.unwrap().is_err());
+    let err = send.await.unwrap().unwrap_err();
+    assert!(err.to_string().contains("cancelled"), "{}", err);
+    assert_eq!(
+      manager.get(&info.id).unwrap().state,
+      TransferState::Cancelled
+    );
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn cancelling_running_inbound_transfer_removes_partial_file() {
+    let dir = test_dir("cancel-running");
+    let manager = receiver_manager();
+    let sender = local_peer("raw");
+    let receiver = manager.transfer.read().await.clone();
+    receiver
+      .trust_store()
+      .trust(&identity(&sender))
+      .await
+      .unwrap();
+
+    let (mut client, server) = MemoryConnection::pair(
+      SocketAddr::from(([127, 0, 0, 1], 1)),
+      SocketAddr::from(([127, 0, 0, 1], 2)),
+    );
+    let receive = {
+      let manager = manager.clone();
+      let save_path = dir.to_string_lossy().to_string();
+      tokio::spawn(async move { manager.receive(&save_path, server).await })
+    };
+    let session = Handshake::new(&sender).initiate(&mut client).await.unwrap();
+    client.set_encryption(Encryption::from_session(&session).unwrap());
+
+    let send = |message: TransferMessage| frame::encode(&message).unwrap();
+    let start = TransferMessage::StartTransfer {
+      protocol_version: PROTOCOL_VERSION,
+      file_name: "partial.bin".to_string(),
+      file_size: 2 * MIN_CHUNK_SIZE,
+      total_chunks: 2,
+      chunk_size: MIN_CHUNK_SIZE,
+      file_hash: "00".repeat(CHUNK_DIGEST_LEN),
+    };
+    client.send_frame(&send(start)).await.unwrap();
+    assert!(matches!(
+      recv_message(&mut client).await,
+      TransferMessage::Accept
+    ));
+    assert!(matches!(
+      recv_message(&mut client).await,
+      TransferMessage::ResumeOffer { .. }
+    ));
+    client
+      .send_frame(&send(TransferMessage::ResumeAccept { chunks: 2 }))
+      .await
+      .unwrap();
+
+    // 接收方已创建临时文件，等待分片时被取消
+    let info = wait_for(&manager, TransferDirection::Inbound, TransferState::Running).await;
+    while std::fs::read_dir(&dir).unwrap().count() == 0 {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    manager.cancel(&info.id).unwrap();
+
+    assert!(matches!(
+      recv_message(&mut client).await,
+      TransferMessage::Error(err) if err.contains("cancelled")
+    ));
+    assert!(receive.await.unwrap().is_err());
+    assert_eq!(
+      manager.get(&info.id).unwrap().state,
+      TransferState::Cancelled
+    );
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    assert!(receiver.resume_store().list().await.is_empty());
+
+    let history = receiver.history().query(&HistoryQuery::default()).await;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].outcome, TransferOutcome::Cancelled);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}
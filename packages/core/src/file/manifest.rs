@@ -0,0 +1,359 @@
+//! 传输清单模块
+//!
+//! 一次会话发送多个文件或整个目录时，发送方先发送清单（相对路径、大小、权限、修改时间），
+//! 接收方据此在保存目录下重建目录结构
+
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// 应用对端权限时保留的位：不授予组和其他用户写权限，也不设置 setuid、setgid 等特殊位
+const RECEIVED_MODE_MASK: u32 = 0o755;
+
+/// 清单中的一项（文件或目录）
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ManifestEntry {
+  /// 相对路径（以 `/` 分隔）
+  pub path: String,
+  pub is_dir: bool,
+  /// 文件大小（目录为 0）
+  pub size: u64,
+  /// Unix 权限位（非 Unix 平台为空）
+  #[serde(default)]
+  pub mode: Option<u32>,
+  /// 修改时间（Unix 秒）
+  #[serde(default)]
+  pub modified: Option<u64>,
+}
+
+impl ManifestEntry {
+  /// 从本地文件元数据创建清单项
+  fn from_metadata(path: String, metadata: &std::fs::Metadata) -> Self {
+    #[cfg(unix)]
+    let mode = {
+      use std::os::unix::fs::PermissionsExt;
+      Some(metadata.permissions().mode() & 0o777)
+    };
+    #[cfg(not(unix))]
+    let mode = None;
+
+    let modified = metadata
+      .modified()
+      .ok()
+      .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+      .map(|duration| duration.as_secs());
+
+    Self {
+      path,
+      is_dir: metadata.is_dir(),
+      size: if metadata.is_dir() { 0 } else { metadata.len() },
+      mode,
+      modified,
+    }
+  }
+
+  /// 把权限和修改时间应用到本地文件（在阻塞线程池中执行），权限按 `RECEIVED_MODE_MASK` 屏蔽
+  pub async fn apply_metadata(&self, path: &Path) -> Result<()> {
+    let entry = self.clone();
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || entry.apply_metadata_blocking(&path))
+      .await
+      .map_err(|e| crate::Error::File(format!("Apply metadata failed: {}", e)))?
+  }
+
+  fn apply_metadata_blocking(&self, path: &Path) -> Result<()> {
+    // 先设置修改时间，只读权限会导致之后无法以写方式打开文件
+    if let Some(modified) = self.modified {
+      let file = std::fs::File::options()
+        .write(!self.is_dir)
+        .read(self.is_dir)
+        .open(path)
+        .map_err(|e| crate::Error::File(format!("Open file failed: {}", e)))?;
+      file
+        .set_modified(UNIX_EPOCH + Duration::from_secs(modified))
+        .map_err(|e| crate::Error::File(format!("Set modified time failed: {}", e)))?;
+    }
+
+    #[cfg(unix)]
+    if let Some(mode) = self.mode {
+      use std::os::unix::fs::PermissionsExt;
+      std::fs::set_permissions(
+        path,
+        std::fs::Permissions::from_mode(mode & RECEIVED_MODE_MASK),
+      )
+      .map_err(|e| crate::Error::File(format!("Set permissions failed: {}", e)))?;
+    }
+    Ok(())
+  }
+}
+
+/// 传输清单
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Manifest {
+  /// 会话名称（发送单个目录时为目录名）
+  pub name: String,
+  pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+  /// 扫描本地文件和目录，生成清单及每一项对应的本地路径
+  ///
+  /// 目录会被递归展开，符号链接会被跳过
+  pub fn scan(paths: &[PathBuf]) -> Result<(Self, Vec<PathBuf>)> {
+    let mut entries = Vec::new();
+    let mut sources = Vec::new();
+
+    for path in paths {
+      let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| crate::Error::File(format!("Invalid path: {}", path.display())))?
+        .to_string();
+      Self::scan_path(path, name, &mut entries, &mut sources)?;
+    }
+
+    let name = match paths {
+      [path] => path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+        .to_string(),
+      _ => format!("{} items", paths.len()),
+    };
+
+    let manifest = Self { name, entries };
+    manifest.validate()?;
+    Ok((manifest, sources))
+  }
+
+  fn scan_path(
+    path: &Path,
+    relative: String,
+    entries: &mut Vec<ManifestEntry>,
+    sources: &mut Vec<PathBuf>,
+  ) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(path)
+      .map_err(|e| crate::Error::File(format!("Read file metadata failed: {}", e)))?;
+    if metadata.file_type().is_symlink() {
+      return Ok(());
+    }
+
+    entries.push(ManifestEntry::from_metadata(relative.clone(), &metadata));
+    sources.push(path.to_path_buf());
+
+    if metadata.is_dir() {
+      let mut children: Vec<_> = std::fs::read_dir(path)
+        .map_err(|e| crate::Error::File(format!("Read directory failed: {}", e)))?
+        .collect::<std::io::Result<_>>()
+        .map_err(|e| crate::Error::File(format!("Read directory failed: {}", e)))?;
+      children.sort_by_key(|entry| entry.file_name());
+
+      for child in children {
+        let Some(name) = child.file_name().to_str().map(|n| n.to_string()) else {
+          continue;
+        };
+        Self::scan_path(
+          &child.path(),
+          format!("{}/{}", relative, name),
+          entries,
+          sources,
+        )?;
+      }
+    }
+    Ok(())
+  }
+
+  /// 文件总大小（溢出时为 `u64::MAX`，这样的清单无法通过校验）
+  pub fn total_size(&self) -> u64 {
+    self.checked_total_size().unwrap_or(u64::MAX)
+  }
+
+  /// 文件总大小，溢出时返回 `None`
+  fn checked_total_size(&self) -> Option<u64> {
+    self
+      .entries
+      .iter()
+      .try_fold(0u64, |total, entry| total.checked_add(entry.size))
+  }
+
+  /// 文件数量（不含目录）
+  pub fn file_count(&self) -> usize {
+    self.entries.iter().filter(|entry| !entry.is_dir).count()
+  }
+
+  /// 校验清单：路径必须是不含 `..` 的相对路径且不能重复，文件总大小不能溢出
+  pub fn validate(&self) -> Result<()> {
+    self.check(false)
+  }
+
+  /// 校验对端发来的清单：在 `validate` 的基础上，路径中也不能含有 `\` 和 `:`
+  ///
+  /// 这两个字符在 Windows 上是路径分隔符和盘符，但在发送方的文件系统上可能是合法的文件名字符
+  pub fn validate_received(&self) -> Result<()> {
+    self.check(true)
+  }
+
+  fn check(&self, received: bool) -> Result<()> {
+    if self.checked_total_size().is_none() {
+      return Err(crate::Error::Protocol(
+        "Invalid manifest: total size overflows".to_string(),
+      ));
+    }
+
+    let mut seen = HashSet::new();
+    for entry in &self.entries {
+      let valid = !entry.path.is_empty()
+        && entry.path.split('/').all(|component| {
+          !component.is_empty()
+            && component != "."
+            && component != ".."
+            && !component.contains('\0')
+            && !(received && component.contains(['\\', ':']))
+        });
+      if !valid {
+        return Err(crate::Error::Protocol(format!(
+          "Invalid manifest path: {:?}",
+          entry.path
+        )));
+      }
+      if entry.is_dir && entry.size != 0 {
+        return Err(crate::Error::Protocol(format!(
+          "Invalid directory size in manifest: {:?}",
+          entry.path
+        )));
+      }
+      if !seen.insert(entry.path.as_str()) {
+        return Err(crate::Error::Protocol(format!(
+          "Duplicate manifest path: {:?}",
+          entry.path
+        )));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// 会话传输进度
+#[derive(Debug, Clone)]
+pub struct SessionProgress {
+  /// 当前文件在清单中的序号（仅计文件）
+  pub file_index: usize,
+  pub file_count: usize,
+  /// 当前文件的相对路径
+  pub file_name: String,
+  pub file_sent: u64,
+  pub file_size: u64,
+  pub total_sent: u64,
+  pub total_size: u64,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn file(path: &str, size: u64) -> ManifestEntry {
+    ManifestEntry {
+      path: path.to_string(),
+      is_dir: false,
+      size,
+      mode: None,
+      modified: None,
+    }
+  }
+
+  fn dir(path: &str) -> ManifestEntry {
+    ManifestEntry {
+      is_dir: true,
+      ..file(path, 0)
+    }
+  }
+
+  fn manifest(entries: Vec<ManifestEntry>) -> Manifest {
+    Manifest {
+      name: "test".to_string(),
+      entries,
+    }
+  }
+
+  #[test]
+  fn accepts_relative_paths() {
+    let manifest = manifest(vec![
+      dir("photos"),
+      file("photos/a.jpg", 10),
+      dir("photos/2024"),
+      file("photos/2024/b..jpg", 20),
+    ]);
+    assert!(manifest.validate().is_ok());
+    assert!(manifest.validate_received().is_ok());
+    assert_eq!(manifest.total_size(), 30);
+    assert_eq!(manifest.file_count(), 2);
+  }
+
+  #[test]
+  fn rejects_path_traversal() {
+    for path in [
+      "",
+      "..",
+      ".",
+      "../secret",
+      "photos/../../secret",
+      "photos/./a.jpg",
+      "/etc/passwd",
+      "photos//a.jpg",
+      "photos/",
+      "a\0b",
+    ] {
+      assert!(
+        manifest(vec![file(path, 1)]).validate().is_err(),
+        "{:?} should be rejected",
+        path
+      );
+    }
+  }
+
+  #[test]
+  fn rejects_windows_separators_only_when_received() {
+    for path in ["..\\secret", "photos\\a.jpg", "C:secret", "C:\\Windows"] {
+      let manifest = manifest(vec![file(path, 1)]);
+      assert!(manifest.validate().is_ok(), "{:?}", path);
+      assert!(manifest.validate_received().is_err(), "{:?}", path);
+    }
+  }
+
+  #[test]
+  fn rejects_duplicates_and_invalid_sizes() {
+    let duplicate = manifest(vec![file("a.txt", 1), file("a.txt", 2)]);
+    assert!(duplicate.validate().is_err());
+
+    let sized_dir = manifest(vec![ManifestEntry {
+      size: 1,
+      ..dir("photos")
+    }]);
+    assert!(sized_dir.validate().is_err());
+
+    let overflow = manifest(vec![file("a", u64::MAX), file("b", 1)]);
+    assert_eq!(overflow.total_size(), u64::MAX);
+    assert!(overflow.validate().is_err());
+    assert!(overflow.validate_received().is_err());
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn masks_received_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+    let path =
+      std::env::temp_dir().join(format!("stationuli-manifest-mode-{}", std::process::id()));
+    std::fs::write(&path, b"data").unwrap();
+
+    let entry = ManifestEntry {
+      mode: Some(0o4777),
+      ..file("data.bin", 4)
+    };
+    entry.apply_metadata(&path).await.unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode, 0o755);
+    let _ = std::fs::remove_file(&path);
+  }
+}
@@ -3,9 +3,25 @@
 //! 提供文件传输、分片、断点续传等功能
 
 pub mod chunk;
+pub mod frame;
+pub mod history;
+pub mod manager;
+pub mod manifest;
+pub mod progress;
 pub mod resume;
+pub mod server;
 pub mod transfer;
 
 pub use chunk::FileChunk;
-pub use resume::ResumeTransfer;
-pub use transfer::FileTransfer;
+pub use history::{HistoryFile, HistoryQuery, HistoryRecord, TransferHistory, TransferOutcome};
+pub use manager::{
+  TransferDirection, TransferHandle, TransferInfo, TransferManager, TransferState,
+};
+pub use manifest::{Manifest, ManifestEntry, SessionProgress};
+pub use progress::TransferProgress;
+pub use resume::{ResumeTransfer, TransferCheckpoint};
+pub use server::{ServerLimits, TransferServer};
+pub use transfer::{
+  FileTransfer, IncomingConnection, PendingReceive, ReceiveOutcome, ReceiveProgressHandler,
+  SessionProgressCallback, TransferDecision, TransferOffer, TransferOfferHandler,
+};
@@ -0,0 +1,151 @@
+//! 传输进度模块
+//!
+//! 按任务统计已传输字节数、瞬时和平均速度以及预计剩余时间，并限制上报频率，
+//! 避免每个分片都通知界面
+
+use crate::file::manager::TransferDirection;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// 两次进度上报之间的最小间隔
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// 传输进度
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferProgress {
+  pub transfer_id: String,
+  pub direction: TransferDirection,
+  /// 已传输字节数
+  pub transferred: u64,
+  /// 总字节数
+  pub total: u64,
+  /// 瞬时速度（字节/秒，按最近一个上报间隔计算）
+  pub speed: f64,
+  /// 平均速度（字节/秒，不含续传前已完成的部分）
+  pub average_speed: f64,
+  /// 预计剩余时间（秒，速度未知时为空）
+  pub eta_secs: Option<u64>,
+  /// 当前文件（多文件会话中的相对路径，单个文件时为文件名）
+  pub current_file: Option<String>,
+  /// 当前文件序号（仅计文件）
+  pub file_index: usize,
+  pub file_count: usize,
+}
+
+/// 单个任务的进度统计
+pub(crate) struct ProgressTracker {
+  /// 首次上报的时间和字节数（续传时不从 0 开始）
+  baseline: Option<(Instant, u64)>,
+  /// 上一次对外上报的时间和字节数
+  last_emit: Option<(Instant, u64)>,
+}
+
+impl ProgressTracker {
+  pub(crate) fn new() -> Self {
+    Self {
+      baseline: None,
+      last_emit: None,
+    }
+  }
+
+  /// 记录一次进度；距上次上报不足 `PROGRESS_INTERVAL` 且尚未完成时返回 `None`
+  ///
+  /// 返回值为（瞬时速度，平均速度，预计剩余秒数）
+  pub(crate) fn record(&mut self, transferred: u64, total: u64) -> Option<(f64, f64, Option<u64>)> {
+    self.record_at(Instant::now(), transferred, total)
+  }
+
+  fn record_at(
+    &mut self,
+    now: Instant,
+    transferred: u64,
+    total: u64,
+  ) -> Option<(f64, f64, Option<u64>)> {
+    let (start, start_bytes) = *self.baseline.get_or_insert((now, transferred));
+
+    let speed = match self.last_emit {
+      Some((last, _)) if now.duration_since(last) < PROGRESS_INTERVAL && transferred < total => {
+        return None;
+      }
+      // 完成时不受上报间隔限制，可能与上次上报处于同一时刻
+      Some((last, last_bytes)) => rate(
+        transferred.saturating_sub(last_bytes),
+        now.duration_since(last),
+      ),
+      None => 0.0,
+    };
+    self.last_emit = Some((now, transferred));
+
+    let average_speed = rate(
+      transferred.saturating_sub(start_bytes),
+      now.duration_since(start),
+    );
+    let eta_secs = (average_speed > 0.0)
+      .then(|| (total.saturating_sub(transferred) as f64 / average_speed).ceil() as u64);
+
+    Some((speed, average_speed, eta_secs))
+  }
+}
+
+/// 按经过的时间计算速度（字节/秒），时间为 0 时速度未知，返回 0
+fn rate(bytes: u64, elapsed: Duration) -> f64 {
+  let secs = elapsed.as_secs_f64();
+  if secs > 0.0 { bytes as f64 / secs } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn limits_report_rate() {
+    let mut tracker = ProgressTracker::new();
+    let start = Instant::now();
+    assert_eq!(tracker.record_at(start, 0, 1000), Some((0.0, 0.0, None)));
+
+    // 间隔内的进度不上报
+    let soon = start + PROGRESS_INTERVAL / 2;
+    assert_eq!(tracker.record_at(soon, 100, 1000), None);
+    assert!(
+      tracker
+        .record_at(start + PROGRESS_INTERVAL, 200, 1000)
+        .is_some()
+    );
+  }
+
+  #[test]
+  fn completion_is_always_reported() {
+    let mut tracker = ProgressTracker::new();
+    let start = Instant::now();
+    tracker.record_at(start, 0, 1000).unwrap();
+
+    // 与上次上报同一时刻完成：速度未知但必须是有限值
+    let (speed, average_speed, eta_secs) = tracker.record_at(start, 1000, 1000).unwrap();
+    assert_eq!(speed, 0.0);
+    assert_eq!(average_speed, 0.0);
+    assert_eq!(eta_secs, None);
+  }
+
+  #[test]
+  fn computes_speed_and_eta() {
+    let mut tracker = ProgressTracker::new();
+    let start = Instant::now();
+    // 续传从已完成的 500 字节开始，平均速度不计入这部分
+    tracker.record_at(start, 500, 10_500).unwrap();
+
+    let (speed, average_speed, eta_secs) = tracker
+      .record_at(start + Duration::from_secs(1), 1500, 10_500)
+      .unwrap();
+    assert_eq!(speed, 1000.0);
+    assert_eq!(average_speed, 1000.0);
+    assert_eq!(eta_secs, Some(9));
+
+    let (speed, average_speed, eta_secs) = tracker
+      .record_at(start + Duration::from_secs(3), 2500, 10_500)
+      .unwrap();
+    assert_eq!(speed, 500.0);
+    assert!((average_speed - 2000.0 / 3.0).abs() < 1e-9);
+    // 剩余 8000 字节按平均速度约 12 秒，向上取整
+    assert_eq!(eta_secs, Some(12));
+  }
+}
@@ -1,26 +1,326 @@
 //! 断点续传模块
+//!
+//! 接收方为每个未完成的传输保存一份检查点（已收到的分片位图和临时文件路径）。
+//! 连接中断后发送方重新连接时，接收方据此告知还缺哪些分片，发送方只补发这些分片
 
 use crate::Result;
+use crate::persist::{self, to_hex};
+use base64::{Engine as _, engine::general_purpose};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tracing::info;
 
-/// 断点续传
+/// 传输 ID 的域分隔前缀
+const TRANSFER_ID_LABEL: &[u8] = b"stationuli/transfer-id/v1";
+
+/// 由发送方设备 ID 和文件哈希派生传输 ID
+///
+/// 同一设备重新发送同一文件时得到相同的 ID，从而找到之前的检查点
+pub fn transfer_id(sender_id: &str, file_hash: &str) -> String {
+  let mut ctx = digest::Context::new(&digest::SHA256);
+  ctx.update(TRANSFER_ID_LABEL);
+  ctx.update(sender_id.as_bytes());
+  ctx.update(&[0]);
+  ctx.update(file_hash.as_bytes());
+  to_hex(&ctx.finish().as_ref()[..16])
+}
+
+/// 校验对端发来的分片区间（左闭右开）
+///
+/// 每个区间必须非空且不超过分片总数，区间之间按顺序排列且互不重叠
+pub fn valid_ranges(ranges: &[(u64, u64)], total_chunks: u64) -> bool {
+  let mut previous_end = 0;
+  ranges.iter().all(|&(start, end)| {
+    let valid = start >= previous_end && start < end && end <= total_chunks;
+    previous_end = end;
+    valid
+  })
+}
+
+/// 传输检查点
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferCheckpoint {
+  pub transfer_id: String,
+  pub file_name: String,
+  pub file_size: u64,
+  /// 文件内容的 SHA-256（十六进制）
+  pub file_hash: String,
+  pub chunk_size: u64,
+  pub total_chunks: u64,
+  /// 接收中的临时文件路径
+  pub temp_path: String,
+  /// 最近一次更新时间（Unix 秒）
+  pub updated_at: u64,
+  /// 已收到分片的位图（Base64）
+  received: String,
+  #[serde(skip)]
+  bitmap: Vec<u8>,
+}
+
+impl TransferCheckpoint {
+  /// 创建空检查点（尚未收到任何分片）
+  pub fn new(
+    transfer_id: String,
+    file_name: String,
+    file_size: u64,
+    file_hash: String,
+    chunk_size: u64,
+    total_chunks: u64,
+    temp_path: String,
+  ) -> Self {
+    Self {
+      transfer_id,
+      file_name,
+      file_size,
+      file_hash,
+      chunk_size,
+      total_chunks,
+      temp_path,
+      updated_at: 0,
+      received: String::new(),
+      bitmap: vec![0; total_chunks.div_ceil(8) as usize],
+    }
+  }
+
+  /// 分片是否已收到
+  pub fn is_received(&self, chunk_id: u64) -> bool {
+    self
+      .bitmap
+      .get((chunk_id / 8) as usize)
+      .is_some_and(|byte| byte & (1 << (chunk_id % 8)) != 0)
+  }
+
+  /// 标记分片已收到，返回此前是否未收到
+  pub fn mark_received(&mut self, chunk_id: u64) -> bool {
+    if chunk_id >= self.total_chunks || self.is_received(chunk_id) {
+      return false;
+    }
+    self.bitmap[(chunk_id / 8) as usize] |= 1 << (chunk_id % 8);
+    true
+  }
+
+  /// 已收到的分片数量
+  pub fn received_chunks(&self) -> u64 {
+    self
+      .bitmap
+      .iter()
+      .map(|byte| byte.count_ones() as u64)
+      .sum()
+  }
+
+  /// 是否已收齐所有分片
+  pub fn is_complete(&self) -> bool {
+    self.received_chunks() == self.total_chunks
+  }
+
+  /// 缺失的分片区间（左闭右开）
+  pub fn missing_ranges(&self) -> Vec<(u64, u64)> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for chunk_id in 0..self.total_chunks {
+      match (self.is_received(chunk_id), start) {
+        (false, None) => start = Some(chunk_id),
+        (true, Some(begin)) => {
+          ranges.push((begin, chunk_id));
+          start = None;
+        }
+        _ => {}
+      }
+    }
+    if let Some(begin) = start {
+      ranges.push((begin, self.total_chunks));
+    }
+    ranges
+  }
+
+  fn encode_bitmap(&mut self) {
+    self.received = general_purpose::STANDARD.encode(&self.bitmap);
+  }
+
+  fn decode_bitmap(&mut self) -> Result<()> {
+    let bitmap = general_purpose::STANDARD
+      .decode(&self.received)
+      .map_err(|e| crate::Error::File(format!("Decode checkpoint failed: {}", e)))?;
+    if bitmap.len() != self.total_chunks.div_ceil(8) as usize {
+      return Err(crate::Error::File(format!(
+        "Invalid checkpoint bitmap: {}",
+        self.transfer_id
+      )));
+    }
+    self.bitmap = bitmap;
+    Ok(())
+  }
+}
+
+/// 断点续传检查点存储（按传输 ID 索引）
 pub struct ResumeTransfer {
-  // TODO: 实现断点续传
+  path: Option<PathBuf>,
+  checkpoints: RwLock<HashMap<String, TransferCheckpoint>>,
 }
 
 impl ResumeTransfer {
+  /// 创建仅存在于内存中的存储
   pub fn new() -> Self {
-    Self {}
+    Self {
+      path: None,
+      checkpoints: RwLock::new(HashMap::new()),
+    }
+  }
+
+  /// 从文件加载存储（文件不存在时视为空列表）
+  pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    let path = path.as_ref().to_path_buf();
+    let list: Vec<TransferCheckpoint> =
+      persist::load_json(&path, "checkpoints")?.unwrap_or_default();
+    let mut checkpoints = HashMap::new();
+    for mut checkpoint in list {
+      checkpoint.decode_bitmap()?;
+      checkpoints.insert(checkpoint.transfer_id.clone(), checkpoint);
+    }
+
+    info!(
+      "Transfer checkpoints loaded: {} ({} transfers)",
+      path.display(),
+      checkpoints.len()
+    );
+
+    Ok(Self {
+      path: Some(path),
+      checkpoints: RwLock::new(checkpoints),
+    })
   }
 
   /// 保存传输进度
-  pub fn save_progress(&self) -> Result<()> {
-    // TODO: 实现进度保存
-    Ok(())
+  pub async fn save_progress(&self, checkpoint: &TransferCheckpoint) -> Result<()> {
+    let mut checkpoint = checkpoint.clone();
+    checkpoint.updated_at = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_secs();
+    checkpoint.encode_bitmap();
+
+    let mut checkpoints = self.checkpoints.write().await;
+    checkpoints.insert(checkpoint.transfer_id.clone(), checkpoint);
+    self.save(&checkpoints).await
   }
 
-  /// 恢复传输
-  pub fn resume(&mut self) -> Result<()> {
-    // TODO: 实现传输恢复
+  /// 恢复传输：返回仍可继续的检查点
+  ///
+  /// 临时文件已不存在的检查点会被丢弃
+  pub async fn resume(&self, transfer_id: &str) -> Option<TransferCheckpoint> {
+    let checkpoint = self.checkpoints.read().await.get(transfer_id).cloned()?;
+    if Path::new(&checkpoint.temp_path).exists() {
+      info!(
+        "Resuming transfer {}: {}/{} chunks received",
+        transfer_id,
+        checkpoint.received_chunks(),
+        checkpoint.total_chunks
+      );
+      return Some(checkpoint);
+    }
+
+    let _ = self.remove(transfer_id).await;
+    None
+  }
+
+  /// 删除检查点（不存在时忽略）
+  pub async fn remove(&self, transfer_id: &str) -> Result<()> {
+    let mut checkpoints = self.checkpoints.write().await;
+    if checkpoints.remove(transfer_id).is_some() {
+      self.save(&checkpoints).await?;
+    }
     Ok(())
   }
+
+  /// 获取全部未完成的传输
+  pub async fn list(&self) -> Vec<TransferCheckpoint> {
+    self.checkpoints.read().await.values().cloned().collect()
+  }
+
+  async fn save(&self, checkpoints: &HashMap<String, TransferCheckpoint>) -> Result<()> {
+    let Some(ref path) = self.path else {
+      return Ok(());
+    };
+    let list: Vec<&TransferCheckpoint> = checkpoints.values().collect();
+    persist::save_json_atomic(path, &list, "checkpoints").await
+  }
+}
+
+impl Default for ResumeTransfer {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn accepts_ordered_ranges() {
+    assert!(valid_ranges(&[], 10));
+    assert!(valid_ranges(&[(0, 10)], 10));
+    assert!(valid_ranges(&[(0, 2), (2, 4), (7, 10)], 10));
+  }
+
+  #[test]
+  fn rejects_invalid_ranges() {
+    // 空区间和反向区间
+    assert!(!valid_ranges(&[(3, 3)], 10));
+    assert!(!valid_ranges(&[(5, 2)], 10));
+    // 超出分片总数
+    assert!(!valid_ranges(&[(0, 11)], 10));
+    assert!(!valid_ranges(&[(10, 11)], 10));
+    assert!(!valid_ranges(&[(0, u64::MAX)], 10));
+  }
+
+  #[test]
+  fn rejects_overlapping_or_unordered_ranges() {
+    assert!(!valid_ranges(&[(0, 5), (4, 8)], 10));
+    assert!(!valid_ranges(&[(0, 5), (0, 5)], 10));
+    assert!(!valid_ranges(&[(0, 10), (2, 3)], 10));
+    assert!(!valid_ranges(&[(6, 8), (1, 3)], 10));
+  }
+
+  #[test]
+  fn checkpoint_reports_missing_ranges() {
+    let mut checkpoint = TransferCheckpoint::new(
+      "id".to_string(),
+      "file".to_string(),
+      10 * 4,
+      String::new(),
+      4,
+      10,
+      "file.part".to_string(),
+    );
+    assert_eq!(checkpoint.missing_ranges(), vec![(0, 10)]);
+
+    for chunk_id in [0, 1, 4, 9] {
+      assert!(checkpoint.mark_received(chunk_id));
+    }
+    assert!(!checkpoint.mark_received(4));
+    assert!(!checkpoint.mark_received(10));
+    assert_eq!(checkpoint.received_chunks(), 4);
+    assert_eq!(checkpoint.missing_ranges(), vec![(2, 4), (5, 9)]);
+    assert!(valid_ranges(&checkpoint.missing_ranges(), 10));
+
+    for chunk_id in 0..10 {
+      checkpoint.mark_received(chunk_id);
+    }
+    assert!(checkpoint.is_complete());
+    assert!(checkpoint.missing_ranges().is_empty());
+  }
+
+  #[test]
+  fn transfer_id_depends_on_sender_and_file() {
+    assert_eq!(transfer_id("a", "hash"), transfer_id("a", "hash"));
+    assert_ne!(transfer_id("a", "hash"), transfer_id("b", "hash"));
+    assert_ne!(transfer_id("a", "hash"), transfer_id("a", "other"));
+    // 字段之间有分隔，拼接结果相同的输入也不会冲突
+    assert_ne!(transfer_id("ab", "c"), transfer_id("a", "bc"));
+  }
 }
@@ -0,0 +1,192 @@
+//! 传输服务模块
+//!
+//! 持有传输层的监听端，为每个入站连接启动独立任务处理。停止时先关闭监听端不再接受新连接，
+//! 再等待进行中的传输结束，超过等待时间后中止剩余的连接。
+//!
+//! 同一来源 IP 的并发连接数有上限，处理连接时违反协议的对端会被暂时封禁
+
+use crate::Result;
+use crate::p2p::limiter::PeerLimiter;
+use crate::p2p::tcp::{TcpConnection, TcpTransport};
+use crate::p2p::transport::{FramedConnection, Transport};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// 默认的停止等待时间
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// accept 出错后重试前的等待时间（例如文件描述符耗尽）
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+pub use crate::p2p::limiter::{
+  DEFAULT_BLOCK_DURATION, DEFAULT_MAX_CONNECTIONS_PER_IP, ServerLimits,
+};
+
+/// 入站连接服务
+pub struct TransferServer {
+  local_addr: SocketAddr,
+  cancel: CancellationToken,
+  accept_task: Option<JoinHandle<JoinSet<()>>>,
+}
+
+impl TransferServer {
+  /// 在指定 TCP 端口上监听并启动服务
+  pub async fn bind<F, Fut>(port: u16, handler: F) -> Result<Self>
+  where
+    F: Fn(TcpConnection) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+  {
+    let transport = TcpTransport::bind(port).await?;
+    Self::start(transport, handler)
+  }
+
+  /// 使用已监听的传输层启动服务，`handler` 在独立任务中处理每个入站连接
+  ///
+  /// `handler` 返回 `Error::ProtocolViolation` 时，对端 IP 会被暂时封禁
+  pub fn start<T, F, Fut>(transport: T, handler: F) -> Result<Self>
+  where
+    T: Transport,
+    F: Fn(T::Connection) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+  {
+    Self::start_with_limits(transport, ServerLimits::default(), handler)
+  }
+
+  /// 使用指定的来源限制启动服务
+  ///
+  /// 同一份来源记录会交给传输层，QUIC 连接在握手前也按它检查来源
+  pub fn start_with_limits<T, F, Fut>(
+    transport: T,
+    limits: ServerLimits,
+    handler: F,
+  ) -> Result<Self>
+  where
+    T: Transport,
+    F: Fn(T::Connection) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+  {
+    let local_addr = transport.local_addr()?;
+    let limiter = PeerLimiter::new(limits);
+    transport.set_peer_limiter(limiter.clone());
+    let cancel = CancellationToken::new();
+    let accept_task = tokio::spawn(Self::accept_loop(
+      transport,
+      handler,
+      limiter,
+      cancel.clone(),
+    ));
+
+    info!("Transfer server started on {}", local_addr);
+    Ok(Self {
+      local_addr,
+      cancel,
+      accept_task: Some(accept_task),
+    })
+  }
+
+  /// 监听地址
+  pub fn local_addr(&self) -> SocketAddr {
+    self.local_addr
+  }
+
+  /// 是否已停止接受新连接
+  pub fn is_stopped(&self) -> bool {
+    self.cancel.is_cancelled()
+  }
+
+  /// 停止服务：立即关闭监听器，最多等待 `drain_timeout` 让进行中的连接处理完毕，
+  /// 之后中止仍未结束的连接
+  pub async fn shutdown(mut self, drain_timeout: Duration) {
+    self.cancel.cancel();
+    let Some(accept_task) = self.accept_task.take() else {
+      return;
+    };
+    let mut connections = match accept_task.await {
+      Ok(connections) => connections,
+      Err(e) => {
+        warn!("Transfer server accept task failed: {}", e);
+        return;
+      }
+    };
+
+    let active = connections.len();
+    if active > 0 {
+      info!("Waiting for {} active connections to finish", active);
+    }
+    let drained = tokio::time::timeout(drain_timeout, async {
+      while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+      warn!(
+        "Aborting {} connections still active after {:?}",
+        connections.len(),
+        drain_timeout
+      );
+      connections.shutdown().await;
+    }
+
+    info!("Transfer server stopped on {}", self.local_addr);
+  }
+
+  /// 接受连接直到收到停止信号，返回仍在处理中的连接任务
+  async fn accept_loop<T, F, Fut>(
+    transport: T,
+    handler: F,
+    limiter: PeerLimiter,
+    cancel: CancellationToken,
+  ) -> JoinSet<()>
+  where
+    T: Transport,
+    F: Fn(T::Connection) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+  {
+    let mut connections = JoinSet::new();
+    loop {
+      tokio::select! {
+        _ = cancel.cancelled() => break,
+        // 及时回收已结束的连接任务
+        Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        accepted = transport.accept() => match accepted {
+          Ok(connection) => {
+            let ip = connection.peer_addr().ip();
+            // 被拒绝的连接直接丢弃（关闭）
+            let Some(slot) = limiter.admit(ip) else {
+              continue;
+            };
+            let handling = handler(connection);
+            let block_duration = limiter.limits().block_duration;
+            connections.spawn(async move {
+              if let Err(crate::Error::ProtocolViolation(reason)) = handling.await {
+                warn!(
+                  "Blocking {} for {:?} after protocol violation: {}",
+                  ip, block_duration, reason
+                );
+                slot.block();
+              }
+            });
+          }
+          Err(e) => {
+            warn!("Accept connection error: {}", e);
+            tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+          }
+        },
+      }
+    }
+
+    // 先释放监听端口，新的服务可以立即绑定
+    drop(transport);
+    connections
+  }
+}
+
+impl Drop for TransferServer {
+  fn drop(&mut self) {
+    // 未调用 shutdown 时停止接受连接，进行中的连接随连接任务集合一起中止
+    self.cancel.cancel();
+  }
+}
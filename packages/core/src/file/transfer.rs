@@ -1,40 +1,644 @@
 //! 文件传输模块
 
 use crate::Result;
-use crate::file::chunk::FileChunk;
-use crate::p2p::tcp::TcpConnection;
+use crate::crypto::encryption::TAG_LEN;
+use crate::crypto::pairing::short_auth_string;
+use crate::crypto::{
+  Encryption, Handshake, LocalPeer, PairingHandler, PairingRequest, PeerIdentity, SessionKeys,
+  TrustStore,
+};
+use crate::file::frame::{self, PROTOCOL_VERSION};
+use crate::file::history::{HistoryFile, HistoryRecord, TransferHistory, TransferOutcome};
+use crate::file::manager::TransferDirection;
+use crate::file::manifest::{Manifest, ManifestEntry, SessionProgress};
+use crate::file::resume::{self, ResumeTransfer, TransferCheckpoint};
+use crate::p2p::addr;
+use crate::p2p::mdns::{DeviceInfo, InterfaceAddress};
+use crate::p2p::tcp::TcpTransport;
+use crate::p2p::transport::{DEFAULT_MAX_FRAME_SIZE, FramedConnection, Transport};
+use crate::persist;
+use ring::digest;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
+/// 默认分片大小（1MB）
+const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// 接收中的临时文件后缀
+const PART_EXTENSION: &str = "part";
+
+/// 每收到多少个分片保存一次检查点
+const CHECKPOINT_INTERVAL: u64 = 16;
+
+/// 分片摘要长度（SHA-256）
+pub const CHUNK_DIGEST_LEN: usize = 32;
+
+/// 对端声明的分片大小上限：分片连同帧头、摘要和认证标签必须能放进一帧
+pub const MAX_CHUNK_SIZE: u64 =
+  (DEFAULT_MAX_FRAME_SIZE - frame::HEADER_LEN - CHUNK_DIGEST_LEN - TAG_LEN) as u64;
+
+/// 对端声明的分片大小下限：限制分片总数，避免检查点位图和缺失区间计算随文件大小失控
+pub const MIN_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// 默认接收的单个文件大小上限（1TB）
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 1 << 40;
+
+/// 计算文件哈希时每次读取的字节数
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/// 单次传输最多允许的重传轮数
+const MAX_RETRANSMIT_ROUNDS: u32 = 3;
+
+/// 暂停或排队期间重发通知的间隔（需小于对端连接的空闲超时）
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 文件名最大长度（字节，多数文件系统的上限）
+const MAX_FILE_NAME_LEN: usize = 255;
+
+/// 同名文件最多尝试的编号
+const MAX_NAME_SUFFIX: u32 = 10_000;
+
+/// Windows 保留的设备名（不区分大小写，带扩展名同样保留）
+const RESERVED_NAMES: &[&str] = &[
+  "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+  "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+fn default_chunk_size() -> u64 {
+  DEFAULT_CHUNK_SIZE as u64
+}
+
+/// 清理对端发来的文件名，得到可以安全放在保存目录下的单个路径组件
+///
+/// 去掉所有路径部分（包括绝对路径和 `..`），替换控制字符和 Windows 不允许的字符，
+/// 去掉结尾的点和空格，并按字节数截断（尽量保留扩展名）。
+/// 清理后为空、是保留设备名，或在 `allow_hidden` 为 false 时以 `.` 开头，则拒绝
+pub fn sanitize_file_name(file_name: &str, allow_hidden: bool) -> Result<String> {
+  let base = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+  let cleaned: String = base
+    .chars()
+    .map(|c| {
+      if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*') {
+        '_'
+      } else {
+        c
+      }
+    })
+    .collect();
+  let cleaned = cleaned.trim_end_matches(['.', ' ']).trim_start();
+
+  if cleaned.is_empty() || cleaned == "." || cleaned == ".." {
+    return Err(crate::Error::File(format!(
+      "Invalid file name: {:?}",
+      file_name
+    )));
+  }
+
+  let stem = cleaned.split('.').next().unwrap_or_default().trim_end();
+  if RESERVED_NAMES
+    .iter()
+    .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+  {
+    return Err(crate::Error::File(format!(
+      "Reserved file name: {:?}",
+      file_name
+    )));
+  }
+
+  if !allow_hidden && cleaned.starts_with('.') {
+    return Err(crate::Error::File(format!(
+      "Hidden file not allowed: {:?}",
+      file_name
+    )));
+  }
+
+  Ok(truncate_file_name(cleaned, MAX_FILE_NAME_LEN))
+}
+
+/// 把文件名截断到 `max_len` 字节以内，扩展名较短时保留扩展名
+fn truncate_file_name(name: &str, max_len: usize) -> String {
+  if name.len() <= max_len {
+    return name.to_string();
+  }
+
+  let (stem, ext) = match name.rfind('.') {
+    Some(pos) if pos > 0 && name.len() - pos <= 16 => name.split_at(pos),
+    _ => (name, ""),
+  };
+  let mut end = max_len - ext.len();
+  while !stem.is_char_boundary(end) {
+    end -= 1;
+  }
+  format!("{}{}", &stem[..end], ext)
+}
+
+/// 接收中的临时文件路径（目标文件名后追加 `.part`）
+fn part_path(final_path: &Path) -> PathBuf {
+  let mut name = final_path.as_os_str().to_os_string();
+  name.push(".");
+  name.push(PART_EXTENSION);
+  PathBuf::from(name)
+}
+
+/// 若目标路径已存在，依次尝试 `name (1).ext`、`name (2).ext`……，返回第一个未被占用的路径
+///
+/// 同名的临时文件（`.part`）也视为占用，避免与其他未完成的传输冲突
+async fn unique_path(path: &Path) -> Result<PathBuf> {
+  let exists = |path: PathBuf| async move {
+    fs::try_exists(&path).await.unwrap_or(true)
+      || fs::try_exists(part_path(&path)).await.unwrap_or(true)
+  };
+  if !exists(path.to_path_buf()).await {
+    return Ok(path.to_path_buf());
+  }
+
+  let file_name = path
+    .file_name()
+    .and_then(|n| n.to_str())
+    .unwrap_or_default();
+  let (stem, ext) = match file_name.rfind('.') {
+    Some(pos) if pos > 0 => file_name.split_at(pos),
+    _ => (file_name, ""),
+  };
+
+  for index in 1..=MAX_NAME_SUFFIX {
+    let suffix = format!(" ({})", index);
+    let candidate_name = format!(
+      "{}{}{}",
+      truncate_file_name(
+        stem,
+        MAX_FILE_NAME_LEN.saturating_sub(suffix.len() + ext.len())
+      ),
+      suffix,
+      ext
+    );
+    let candidate = path.with_file_name(candidate_name);
+    if !exists(candidate.clone()).await {
+      return Ok(candidate);
+    }
+  }
+
+  Err(crate::Error::File(format!(
+    "Too many files with the same name: {}",
+    path.display()
+  )))
+}
+
 /// 文件传输消息类型
 #[derive(Debug, Serialize, Deserialize)]
 pub enum TransferMessage {
   /// 开始传输
   StartTransfer {
+    /// 发送方使用的传输协议版本（仅作记录，版本由帧头校验；旧版本对端不发送此字段）
+    #[serde(default)]
+    protocol_version: u8,
     file_name: String,
     file_size: u64,
     total_chunks: u64,
+    /// 分片大小，接收方据此计算每个分片的写入偏移
+    #[serde(default = "default_chunk_size")]
+    chunk_size: u64,
+    /// 文件内容的 SHA-256（十六进制），用于识别可续传的传输和最终校验
+    #[serde(default)]
+    file_hash: String,
+  },
+  /// 传输分片（附带分片数据的 SHA-256）
+  Chunk {
+    chunk_id: u64,
+    digest: [u8; CHUNK_DIGEST_LEN],
+    data: Vec<u8>,
   },
-  /// 传输分片
-  Chunk { chunk_id: u64, data: Vec<u8> },
   /// 传输完成
   Complete,
   /// 传输错误
   Error(String),
+  /// 配对请求（代替 StartTransfer 作为会话的第一条消息）
+  PairRequest,
+  /// 配对结果：本端用户是否确认短认证串一致
+  PairResponse { accepted: bool },
+  /// 接收方回复仍缺失的分片区间（左闭右开，新传输时为全部分片）
+  ResumeOffer { missing: Vec<(u64, u64)> },
+  /// 发送方确认续传，并告知接下来要发送的分片数量
+  ResumeAccept { chunks: u64 },
+  /// 接收方要求重传校验失败或缺失的分片区间
+  Retransmit { missing: Vec<(u64, u64)> },
+  /// 接收方已收齐所有分片且整个文件校验通过
+  Verified,
+  /// 接收方同意接收文件
+  Accept,
+  /// 接收方已同意接收，正在等待空闲的并发名额（排队期间定期重发，之后再发送 Accept）
+  Queued,
+  /// 接收方拒绝接收文件
+  Decline { reason: String },
+  /// 发送方暂停发送分片（连接保持）
+  Pause,
+  /// 发送方继续发送分片
+  Resume,
+  /// 多文件会话的清单（代替 StartTransfer 作为会话的第一条消息，之后逐个文件传输）
+  Manifest {
+    protocol_version: u8,
+    manifest: Manifest,
+  },
+}
+
+/// 一次入站连接的处理结果
+#[derive(Debug, Clone)]
+pub enum ReceiveOutcome {
+  /// 收到文件，保存路径
+  File(String),
+  /// 与对端完成配对
+  Paired(PeerIdentity),
+  /// 收到多文件会话，各文件的保存路径
+  Files(Vec<String>),
+  /// 拒绝了对端发来的文件
+  Declined(TransferOffer),
+}
+
+/// 入站文件请求（交给用户决定是否接收）
+#[derive(Debug, Clone)]
+pub struct TransferOffer {
+  /// 发送方设备（地址和端口为本次连接的对端地址）
+  pub sender: DeviceInfo,
+  /// 文件名（多文件会话为会话名称）
+  pub file_name: String,
+  /// 文件大小（多文件会话为总大小）
+  pub file_size: u64,
+  /// 文件数量（单个文件为 1）
+  pub file_count: usize,
+}
+
+/// 入站连接在接收文件内容之前的处理结果
+pub enum IncomingConnection<C> {
+  /// 连接已处理完毕（完成配对或拒绝了文件）
+  Done(ReceiveOutcome),
+  /// 用户已同意接收，等待接收文件内容
+  Accepted(Box<PendingReceive<C>>),
+}
+
+/// 已同意接收、尚未开始接收内容的入站传输
+pub struct PendingReceive<C> {
+  connection: C,
+  kind: PendingKind,
+  record: HistoryRecord,
+}
+
+impl<C: FramedConnection> PendingReceive<C> {
+  /// 文件名（多文件会话为会话名称）
+  pub fn name(&self) -> &str {
+    &self.record.name
+  }
+
+  /// 对端地址
+  pub fn peer_addr(&self) -> SocketAddr {
+    self.connection.peer_addr()
+  }
+}
+
+/// 询问用户之后的结果
+enum Prepared {
+  /// 用户同意接收
+  Accepted(PendingKind),
+  /// 用户拒绝了文件
+  Declined(ReceiveOutcome),
+}
+
+/// 等待接收的内容
+enum PendingKind {
+  File(PendingFile),
+  Session(PendingSession),
+}
+
+/// 等待接收的单个文件
+struct PendingFile {
+  transfer_id: String,
+  header: FileHeader,
+  final_path: PathBuf,
+  existing: Option<TransferCheckpoint>,
+}
+
+/// 等待接收的多文件会话
+struct PendingSession {
+  sender_id: String,
+  manifest: Manifest,
+  local_paths: Vec<PathBuf>,
 }
 
+/// 单个文件的传输参数（来自 StartTransfer）
+struct FileHeader {
+  file_name: String,
+  file_size: u64,
+  total_chunks: u64,
+  chunk_size: u64,
+  file_hash: String,
+}
+
+/// 用户对入站文件请求的决定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferDecision {
+  Accept,
+  Decline(String),
+}
+
+/// 入站文件确认回调：返回用户是否接收该文件
+pub type TransferOfferHandler = Arc<
+  dyn Fn(TransferOffer) -> Pin<Box<dyn Future<Output = TransferDecision> + Send>> + Send + Sync,
+>;
+
+/// 多文件会话的进度回调
+pub type SessionProgressCallback = Box<dyn Fn(&SessionProgress) + Send + Sync>;
+
+/// 对端暂停状态回调：参数为对端是否已暂停
+pub type PeerPauseHandler = Arc<dyn Fn(bool) + Send + Sync>;
+
+/// 接收进度回调（单个文件时按只含一个文件的会话上报）
+pub type ReceiveProgressHandler = Arc<dyn Fn(&SessionProgress) + Send + Sync>;
+
 /// 文件传输
-pub struct FileTransfer {
+///
+/// 出站连接通过传输层 `T` 建立，入站连接可以来自任意实现了 `FramedConnection` 的连接
+pub struct FileTransfer<T: Transport = TcpTransport> {
+  transport: Arc<T>,
   chunk_size: usize,
+  local_peer: LocalPeer,
+  trust_store: Arc<TrustStore>,
+  resume_store: Arc<ResumeTransfer>,
+  history: Arc<TransferHistory>,
+  pairing_handler: Option<PairingHandler>,
+  offer_handler: Option<TransferOfferHandler>,
+  allow_hidden_files: bool,
+  max_file_size: u64,
+  pause_signal: Option<watch::Receiver<bool>>,
+  cancel_signal: Option<CancellationToken>,
+  peer_pause_handler: Option<PeerPauseHandler>,
+  receive_progress_handler: Option<ReceiveProgressHandler>,
 }
 
 impl FileTransfer {
   pub fn new() -> Self {
+    Self::with_transport(TcpTransport::new())
+  }
+}
+
+impl<T: Transport> FileTransfer<T> {
+  /// 使用指定的传输层创建
+  pub fn with_transport(transport: T) -> Self {
+    let device_name = hostname::get()
+      .ok()
+      .and_then(|h| h.to_str().map(|s| s.to_string()))
+      .unwrap_or_else(|| "stationuli-device".to_string());
     Self {
-      chunk_size: 1024 * 1024, // 1MB per chunk
+      transport: Arc::new(transport),
+      chunk_size: DEFAULT_CHUNK_SIZE,
+      local_peer: LocalPeer::generate("unknown".to_string(), device_name, "unknown".to_string()),
+      trust_store: Arc::new(TrustStore::in_memory()),
+      resume_store: Arc::new(ResumeTransfer::new()),
+      history: Arc::new(TransferHistory::new()),
+      pairing_handler: None,
+      offer_handler: None,
+      allow_hidden_files: true,
+      max_file_size: DEFAULT_MAX_FILE_SIZE,
+      pause_signal: None,
+      cancel_signal: None,
+      peer_pause_handler: None,
+      receive_progress_handler: None,
+    }
+  }
+
+  /// 获取传输层
+  pub fn transport(&self) -> Arc<T> {
+    self.transport.clone()
+  }
+
+  /// 设置本端身份
+  pub fn set_local_peer(&mut self, local_peer: LocalPeer) {
+    self.local_peer = local_peer;
+  }
+
+  /// 更新本端设备信息（保留长期密钥）
+  pub fn set_device_info(&mut self, device_id: String, device_name: String, device_type: String) {
+    self.local_peer.device_id = device_id;
+    self.local_peer.device_name = device_name;
+    self.local_peer.device_type = device_type;
+  }
+
+  /// 获取本端身份
+  pub fn local_peer(&self) -> &LocalPeer {
+    &self.local_peer
+  }
+
+  /// 设置受信任设备存储
+  pub fn set_trust_store(&mut self, trust_store: Arc<TrustStore>) {
+    self.trust_store = trust_store;
+  }
+
+  /// 获取受信任设备存储
+  pub fn trust_store(&self) -> Arc<TrustStore> {
+    self.trust_store.clone()
+  }
+
+  /// 设置断点续传检查点存储
+  pub fn set_resume_store(&mut self, resume_store: Arc<ResumeTransfer>) {
+    self.resume_store = resume_store;
+  }
+
+  /// 获取断点续传检查点存储
+  pub fn resume_store(&self) -> Arc<ResumeTransfer> {
+    self.resume_store.clone()
+  }
+
+  /// 设置传输历史存储
+  pub fn set_history(&mut self, history: Arc<TransferHistory>) {
+    self.history = history;
+  }
+
+  /// 获取传输历史存储
+  pub fn history(&self) -> Arc<TransferHistory> {
+    self.history.clone()
+  }
+
+  /// 设置配对确认回调（未设置时拒绝所有配对请求）
+  pub fn set_pairing_handler(&mut self, handler: PairingHandler) {
+    self.pairing_handler = Some(handler);
+  }
+
+  /// 设置入站文件确认回调（未设置时接收所有已配对设备发来的文件）
+  pub fn set_offer_handler(&mut self, handler: TransferOfferHandler) {
+    self.offer_handler = Some(handler);
+  }
+
+  /// 设置是否接收以 `.` 开头的隐藏文件（默认接收）
+  pub fn set_allow_hidden_files(&mut self, allow: bool) {
+    self.allow_hidden_files = allow;
+  }
+
+  /// 设置接收的单个文件大小上限（默认 1TB），更大的文件在创建临时文件和检查点之前被拒绝
+  pub fn set_max_file_size(&mut self, max_file_size: u64) {
+    self.max_file_size = max_file_size;
+  }
+
+  /// 设置暂停信号：值为 true 时发送方在分片之间暂停，并通知接收方
+  pub fn set_pause_signal(&mut self, pause_signal: watch::Receiver<bool>) {
+    self.pause_signal = Some(pause_signal);
+  }
+
+  /// 设置取消信号：接收方取消时通知发送方，并删除临时文件和检查点
+  pub fn set_cancel_signal(&mut self, cancel_signal: CancellationToken) {
+    self.cancel_signal = Some(cancel_signal);
+  }
+
+  /// 设置对端暂停状态回调（接收方据此显示"发送方已暂停"）
+  pub fn set_peer_pause_handler(&mut self, handler: PeerPauseHandler) {
+    self.peer_pause_handler = Some(handler);
+  }
+
+  /// 设置接收进度回调
+  pub fn set_receive_progress_handler(&mut self, handler: ReceiveProgressHandler) {
+    self.receive_progress_handler = Some(handler);
+  }
+
+  /// 建立到已配对设备的加密会话
+  ///
+  /// 完成握手并启用帧加密；对端未配对时拒绝继续
+  pub async fn open_session(
+    &self,
+    target_address: &str,
+    target_port: u16,
+  ) -> Result<T::Connection> {
+    let (connection, _) = self.open_trusted(target_address, target_port).await?;
+    Ok(connection)
+  }
+
+  /// 建立加密会话并返回对端身份；对端未配对时拒绝继续
+  async fn open_trusted(
+    &self,
+    target_address: &str,
+    target_port: u16,
+  ) -> Result<(T::Connection, PeerIdentity)> {
+    let (mut connection, session) = self.connect_secure(target_address, target_port).await?;
+
+    if !self.trust_store.is_trusted(&session.peer).await {
+      let reject = Self::encode(&TransferMessage::Error("Device not paired".to_string()))?;
+      let _ = connection.send_frame(&reject).await;
+      connection.close().await?;
+      return Err(crate::Error::Crypto(format!(
+        "Device not paired: {} ({})",
+        session.peer.device_name, session.peer.device_id
+      )));
+    }
+
+    Ok((connection, session.peer))
+  }
+
+  /// 与目标设备配对
+  ///
+  /// 双方都通过配对确认回调展示短认证串，只有两端用户都确认后才互相信任
+  pub async fn pair(&self, target_address: &str, target_port: u16) -> Result<PeerIdentity> {
+    let (mut connection, session) = self.connect_secure(target_address, target_port).await?;
+    connection
+      .send_frame(&Self::encode(&TransferMessage::PairRequest)?)
+      .await?;
+    let result = self.confirm_pairing(&mut connection, &session).await;
+    connection.close().await?;
+    result
+  }
+
+  /// 建立连接并完成握手
+  async fn connect_secure(
+    &self,
+    target_address: &str,
+    target_port: u16,
+  ) -> Result<(T::Connection, SessionKeys)> {
+    let mut connection = self.transport.connect(target_address, target_port).await?;
+
+    // 在发送任何文件数据之前完成会话握手，之后的所有消息都会加密
+    let session = Handshake::new(&self.local_peer)
+      .initiate(&mut connection)
+      .await?;
+    connection.set_encryption(Encryption::from_session(&session)?);
+
+    Ok((connection, session))
+  }
+
+  /// 配对确认：交换双方用户的确认结果，都确认后写入受信任设备列表
+  async fn confirm_pairing<C: FramedConnection>(
+    &self,
+    connection: &mut C,
+    session: &SessionKeys,
+  ) -> Result<PeerIdentity> {
+    let peer = session.peer.clone();
+    info!(
+      "Pairing with {} ({}), waiting for user confirmation",
+      peer.device_name, peer.device_id
+    );
+
+    let accepted = match self.pairing_handler {
+      // 同一设备 ID 已绑定另一把公钥：不提示用户，直接拒绝
+      _ if self.trust_store.is_key_changed(&peer).await => {
+        warn!(
+          "Declining pairing with {} ({}): device already paired with a different key",
+          peer.device_name, peer.device_id
+        );
+        false
+      }
+      Some(ref handler) => {
+        handler(PairingRequest {
+          peer: peer.clone(),
+          code: short_auth_string(&session.transcript_hash),
+        })
+        .await
+      }
+      None => {
+        warn!("No pairing handler configured, declining pairing request");
+        false
+      }
+    };
+
+    connection
+      .send_frame(&Self::encode(&TransferMessage::PairResponse { accepted })?)
+      .await?;
+
+    let peer_accepted = match Self::decode(&connection.recv_frame().await?)? {
+      TransferMessage::PairResponse { accepted } => accepted,
+      TransferMessage::Error(err) => {
+        return Err(crate::Error::Crypto(format!("Pairing failed: {}", err)));
+      }
+      _ => {
+        return Err(crate::Error::Protocol(
+          "Expected PairResponse message".to_string(),
+        ));
+      }
+    };
+
+    if !accepted || !peer_accepted {
+      return Err(crate::Error::Crypto(format!(
+        "Pairing with {} declined",
+        peer.device_name
+      )));
     }
+
+    self.trust_store.trust(&peer).await?;
+    info!("Paired with {} ({})", peer.device_name, peer.device_id);
+    Ok(peer)
+  }
+
+  fn encode(message: &TransferMessage) -> Result<Vec<u8>> {
+    frame::encode(message)
+  }
+
+  fn decode(data: &[u8]) -> Result<TransferMessage> {
+    frame::decode(data)
   }
 
   /// 发送文件
@@ -57,190 +661,927 @@ impl FileTransfer {
     target_port: u16,
     progress_callback: Option<Box<dyn Fn(u64, u64) + Send + Sync>>, // (sent_bytes, total_bytes)
   ) -> Result<()> {
-    info!(
-      "Sending file: {} to {}:{}",
-      file_path, target_address, target_port
-    );
-
-    // 读取文件
     // 在 Android 上，文件路径可能是 content:// URI，需要特殊处理
-    let file_data = if file_path.starts_with("content://") {
-      // Android content URI - 需要通过 Tauri 的 Android FS 插件读取
+    if file_path.starts_with("content://") {
+      // Android content URI - 需要通过 Tauri 的 Android FS 插件打开文件，再调用 send_stream
       // 注意：这里需要在调用端处理，因为 core 库不应该依赖 Tauri
       return Err(crate::Error::File(
-        "Content URI detected. Please use Tauri's Android FS plugin to read the file first."
+        "Content URI detected. Please open the file with Tauri's Android FS plugin and use send_stream."
           .to_string(),
       ));
-    } else {
-      // 普通文件路径
-      let file_path = Path::new(file_path);
-      fs::read(file_path)
-        .await
-        .map_err(|e| crate::Error::File(format!("Read file failed: {}", e)))?
-    };
+    }
 
     // 获取文件名
-    let file_name = if file_path.starts_with("content://") {
-      // 对于 content URI，尝试从路径中提取文件名，或使用默认名称
-      file_path.split('/').last().unwrap_or("file").to_string()
-    } else {
-      Path::new(file_path)
-        .file_name()
-        .and_then(|n| n.to_str())
-        .ok_or_else(|| crate::Error::File("Invalid file path".to_string()))?
-        .to_string()
-    };
-
-    let file_size = file_data.len() as u64;
+    let file_name = Path::new(file_path)
+      .file_name()
+      .and_then(|n| n.to_str())
+      .ok_or_else(|| crate::Error::File("Invalid file path".to_string()))?
+      .to_string();
 
-    // 建立连接
-    let mut connection = TcpConnection::connect(target_address, target_port).await?;
+    let file = fs::File::open(file_path)
+      .await
+      .map_err(|e| crate::Error::File(format!("Open file failed: {}", e)))?;
+    let file_size = file
+      .metadata()
+      .await
+      .map_err(|e| crate::Error::File(format!("Read file metadata failed: {}", e)))?
+      .len();
 
-    // 分片文件
-    let chunks = FileChunk::split_file(&file_data, self.chunk_size, file_name.clone());
+    self
+      .send_source(
+        file,
+        &file_name,
+        file_size,
+        Some(Path::new(file_path)),
+        target_address,
+        target_port,
+        progress_callback,
+      )
+      .await
+  }
 
-    // 发送开始传输消息
-    let start_msg = TransferMessage::StartTransfer {
-      file_name: file_name.clone(),
-      file_size,
-      total_chunks: chunks.len() as u64,
-    };
-    let start_data = serde_json::to_vec(&start_msg)
-      .map_err(|e| crate::Error::Protocol(format!("Serialize failed: {}", e)))?;
-    connection.send(&start_data).await?;
+  /// 从任意可定位的异步数据源流式发送文件
+  ///
+  /// 每次只读取一个分片，内存占用与文件大小无关。`file_size` 必须与数据源的实际长度一致。
+  /// 发送前先计算文件哈希；接收方若保存了同一文件的检查点，只补发缺失的分片
+  ///
+  /// 数据源必须支持定位（`AsyncSeek`）：计算哈希后要回到开头，续传时要跳过接收方已有的分片，
+  /// 重传时要回到校验失败的分片。管道等不能定位的数据源需先写入临时文件再发送
+  pub async fn send_stream<R>(
+    &self,
+    reader: R,
+    file_name: &str,
+    file_size: u64,
+    target_address: &str,
+    target_port: u16,
+    progress_callback: Option<Box<dyn Fn(u64, u64) + Send + Sync>>, // (sent_bytes, total_bytes)
+  ) -> Result<()>
+  where
+    R: AsyncRead + AsyncSeek + Send,
+  {
+    self
+      .send_source(
+        reader,
+        file_name,
+        file_size,
+        None,
+        target_address,
+        target_port,
+        progress_callback,
+      )
+      .await
+  }
 
+  /// 发送单个文件并记录传输历史（`source_path` 为数据源对应的本地文件）
+  #[allow(clippy::too_many_arguments)]
+  async fn send_source<R>(
+    &self,
+    reader: R,
+    file_name: &str,
+    file_size: u64,
+    source_path: Option<&Path>,
+    target_address: &str,
+    target_port: u16,
+    progress_callback: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+  ) -> Result<()>
+  where
+    R: AsyncRead + AsyncSeek + Send,
+  {
     info!(
-      "Sending file: {} ({} bytes, {} chunks)",
-      file_name,
+      "Sending file: {} ({} bytes) to {}:{}",
+      file_name, file_size, target_address, target_port
+    );
+
+    let mut record = HistoryRecord::new(
+      TransferDirection::Outbound,
+      file_name.to_string(),
+      addr::format_host_port(target_address, target_port),
       file_size,
-      chunks.len()
     );
+    let result = async {
+      // 先在本地计算哈希再建立连接，避免接收方在等待期间空闲超时
+      let mut reader = std::pin::pin!(reader);
+      let hash = Self::hash_stream(&mut reader, file_size).await?;
 
-    // 发送所有分片
-    let mut sent_bytes = 0u64;
-    for (i, chunk) in chunks.iter().enumerate() {
-      let chunk_msg = TransferMessage::Chunk {
-        chunk_id: chunk.chunk_id,
-        data: chunk.data.clone(),
-      };
-      let chunk_data = serde_json::to_vec(&chunk_msg)
-        .map_err(|e| crate::Error::Protocol(format!("Serialize failed: {}", e)))?;
-      connection.send(&chunk_data).await?;
+      // 建立到已配对设备的加密会话
+      let (mut connection, peer) = self.open_trusted(target_address, target_port).await?;
+      record.set_peer(&peer);
+      self
+        .send_over(
+          &mut connection,
+          &mut record,
+          reader,
+          file_name,
+          file_size,
+          &hash,
+          progress_callback.as_deref(),
+        )
+        .await?;
+      record.files.push(HistoryFile {
+        name: file_name.to_string(),
+        path: source_path.map(|path| path.to_string_lossy().to_string()),
+        size: file_size,
+        hash,
+      });
 
-      sent_bytes += chunk.data.len() as u64;
+      connection.close().await?;
+      info!("File transfer completed: {}", file_name);
+      Ok(())
+    }
+    .await;
 
-      // 调用进度回调
-      if let Some(ref callback) = progress_callback {
-        callback(sent_bytes, file_size);
-      }
+    record.finish(&result);
+    self.save_history(record).await;
+    result
+  }
 
-      if (i + 1) % 10 == 0 {
-        info!(
-          "Sent {}/{} chunks ({}%)",
-          i + 1,
-          chunks.len(),
-          (sent_bytes * 100 / file_size)
-        );
-      }
+  /// 在一个会话中发送多个文件或整个目录
+  ///
+  /// 目录会被递归展开，接收方在保存目录下按相对路径重建目录结构
+  pub async fn send_paths(
+    &self,
+    paths: &[String],
+    target_address: &str,
+    target_port: u16,
+    progress_callback: Option<SessionProgressCallback>,
+  ) -> Result<()> {
+    if paths.iter().any(|path| path.starts_with("content://")) {
+      return Err(crate::Error::File(
+        "Content URI detected. Please open the files with Tauri's Android FS plugin and use send_entries."
+          .to_string(),
+      ));
     }
 
-    // 发送完成消息
-    let complete_msg = TransferMessage::Complete;
-    let complete_data = serde_json::to_vec(&complete_msg)
-      .map_err(|e| crate::Error::Protocol(format!("Serialize failed: {}", e)))?;
-    connection.send(&complete_data).await?;
-
-    connection.close()?;
-    info!("File transfer completed: {}", file_name);
+    // 遍历目录是阻塞操作，放到阻塞线程池中执行
+    let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+    let (manifest, sources) = tokio::task::spawn_blocking(move || Manifest::scan(&paths))
+      .await
+      .map_err(|e| crate::Error::File(format!("Scan files failed: {}", e)))??;
 
-    Ok(())
+    self
+      .send_manifest(
+        &manifest,
+        |index| {
+          let path = sources[index].clone();
+          async move {
+            fs::File::open(&path)
+              .await
+              .map_err(|e| crate::Error::File(format!("Open file failed: {}", e)))
+          }
+        },
+        Some(&sources),
+        target_address,
+        target_port,
+        progress_callback,
+      )
+      .await
   }
 
-  /// 接收文件
-  /// 返回接收到的文件路径
-  pub async fn receive_file(
+  /// 按清单在一个会话中发送多个文件
+  ///
+  /// `open` 按清单序号打开对应文件的数据源，调用方可以据此使用文件系统之外的数据源。
+  /// 建立连接前会先打开每个文件计算哈希，发送时再重新打开，因此每个文件会被打开两次
+  pub async fn send_entries<R, F, Fut>(
     &self,
-    save_path: &str,
-    listener: &tokio::net::TcpListener,
-  ) -> Result<String> {
-    info!("Waiting for file transfer on listener...");
+    manifest: &Manifest,
+    open: F,
+    target_address: &str,
+    target_port: u16,
+    progress_callback: Option<SessionProgressCallback>,
+  ) -> Result<()>
+  where
+    R: AsyncRead + AsyncSeek + Send,
+    F: FnMut(usize) -> Fut + Send,
+    Fut: Future<Output = Result<R>> + Send,
+  {
+    self
+      .send_manifest(
+        manifest,
+        open,
+        None,
+        target_address,
+        target_port,
+        progress_callback,
+      )
+      .await
+  }
 
-    // 接受连接
-    let mut connection = TcpConnection::accept(listener).await?;
+  /// 按清单发送多个文件并记录传输历史（`sources` 为清单各项对应的本地路径）
+  async fn send_manifest<R, F, Fut>(
+    &self,
+    manifest: &Manifest,
+    open: F,
+    sources: Option<&[PathBuf]>,
+    target_address: &str,
+    target_port: u16,
+    progress_callback: Option<SessionProgressCallback>,
+  ) -> Result<()>
+  where
+    R: AsyncRead + AsyncSeek + Send,
+    F: FnMut(usize) -> Fut + Send,
+    Fut: Future<Output = Result<R>> + Send,
+  {
+    let mut record = HistoryRecord::new(
+      TransferDirection::Outbound,
+      manifest.name.clone(),
+      addr::format_host_port(target_address, target_port),
+      manifest.total_size(),
+    );
+    let result = self
+      .send_session(
+        manifest,
+        open,
+        sources,
+        target_address,
+        target_port,
+        progress_callback,
+        &mut record,
+      )
+      .await;
 
-    // 接收开始传输消息
-    let start_data = connection.receive().await?;
-    let start_msg: TransferMessage = serde_json::from_slice(&start_data)
-      .map_err(|e| crate::Error::Protocol(format!("Deserialize failed: {}", e)))?;
+    record.finish(&result);
+    self.save_history(record).await;
+    result
+  }
 
-    let (file_name, file_size, total_chunks) = match start_msg {
-      TransferMessage::StartTransfer {
-        file_name,
-        file_size,
-        total_chunks,
-      } => (file_name, file_size, total_chunks),
-      _ => {
-        return Err(crate::Error::Protocol(
-          "Expected StartTransfer message".to_string(),
-        ));
-      }
-    };
+  #[allow(clippy::too_many_arguments)]
+  async fn send_session<R, F, Fut>(
+    &self,
+    manifest: &Manifest,
+    mut open: F,
+    sources: Option<&[PathBuf]>,
+    target_address: &str,
+    target_port: u16,
+    progress_callback: Option<SessionProgressCallback>,
+    record: &mut HistoryRecord,
+  ) -> Result<()>
+  where
+    R: AsyncRead + AsyncSeek + Send,
+    F: FnMut(usize) -> Fut + Send,
+    Fut: Future<Output = Result<R>> + Send,
+  {
+    manifest.validate()?;
+    let file_count = manifest.file_count();
+    let total_size = manifest.total_size();
 
     info!(
-      "Receiving file: {} ({} bytes, {} chunks)",
-      file_name, file_size, total_chunks
+      "Sending {} ({} files, {} bytes) to {}:{}",
+      manifest.name, file_count, total_size, target_address, target_port
     );
 
-    // 接收所有分片
-    let mut chunks = Vec::new();
-    let mut received_chunks = 0;
+    // 建立连接前先计算所有文件的哈希，避免接收方在文件之间等待时空闲超时
+    let mut hashes = Vec::with_capacity(manifest.entries.len());
+    for (index, entry) in manifest.entries.iter().enumerate() {
+      if entry.is_dir {
+        hashes.push(String::new());
+        continue;
+      }
+      let mut reader = std::pin::pin!(open(index).await?);
+      hashes.push(Self::hash_stream(&mut reader, entry.size).await?);
+    }
 
-    loop {
-      let chunk_data = connection.receive().await?;
-      let chunk_msg: TransferMessage = serde_json::from_slice(&chunk_data)
-        .map_err(|e| crate::Error::Protocol(format!("Deserialize failed: {}", e)))?;
+    // 建立到已配对设备的加密会话，先发送清单，由接收方决定是否接收整个会话
+    let (mut connection, peer) = self.open_trusted(target_address, target_port).await?;
+    record.set_peer(&peer);
+    let manifest_msg = TransferMessage::Manifest {
+      protocol_version: PROTOCOL_VERSION,
+      manifest: manifest.clone(),
+    };
+    connection.send_frame(&Self::encode(&manifest_msg)?).await?;
+    Self::await_decision(&mut connection, record).await?;
 
-      match chunk_msg {
-        TransferMessage::Chunk { chunk_id, data } => {
-          chunks.push(FileChunk {
-            chunk_id,
-            data,
-            total_chunks,
-            file_name: file_name.clone(),
+    let mut file_index = 0;
+    let mut total_done = 0u64;
+    for (index, entry) in manifest.entries.iter().enumerate() {
+      if entry.is_dir {
+        continue;
+      }
+
+      let reader = open(index).await?;
+      let progress = progress_callback.as_ref().map(|callback| {
+        move |file_sent, file_size| {
+          callback(&SessionProgress {
+            file_index,
+            file_count,
+            file_name: entry.path.clone(),
+            file_sent,
             file_size,
-          });
-          received_chunks += 1;
+            total_sent: total_done + file_sent,
+            total_size,
+          })
+        }
+      });
+      let hash = hashes[index].clone();
+      self
+        .send_over(
+          &mut connection,
+          record,
+          reader,
+          &entry.path,
+          entry.size,
+          &hash,
+          progress
+            .as_ref()
+            .map(|callback| callback as &(dyn Fn(u64, u64) + Send + Sync)),
+        )
+        .await?;
+      record.files.push(HistoryFile {
+        name: entry.path.clone(),
+        path: sources.map(|sources| sources[index].to_string_lossy().to_string()),
+        size: entry.size,
+        hash,
+      });
 
-          if received_chunks % 10 == 0 {
-            info!("Received {}/{} chunks", received_chunks, total_chunks);
-          }
+      file_index += 1;
+      total_done += entry.size;
+    }
 
-          if received_chunks >= total_chunks {
-            break;
-          }
-        }
-        TransferMessage::Complete => {
-          break;
+    connection.close().await?;
+    info!("Session transfer completed: {}", manifest.name);
+
+    Ok(())
+  }
+
+  /// 在已建立的会话中发送一个文件（`file_hash` 为发送前算好的文件哈希）
+  #[allow(clippy::too_many_arguments)]
+  async fn send_over<C: FramedConnection, R>(
+    &self,
+    connection: &mut C,
+    record: &mut HistoryRecord,
+    reader: R,
+    file_name: &str,
+    file_size: u64,
+    file_hash: &str,
+    progress_callback: Option<&(dyn Fn(u64, u64) + Send + Sync)>,
+  ) -> Result<()>
+  where
+    R: AsyncRead + AsyncSeek + Send,
+  {
+    let mut reader = std::pin::pin!(reader);
+    let chunk_size = self.chunk_size as u64;
+    let total_chunks = file_size.div_ceil(chunk_size);
+
+    // 发送开始传输消息
+    let start_msg = TransferMessage::StartTransfer {
+      protocol_version: PROTOCOL_VERSION,
+      file_name: file_name.to_string(),
+      file_size,
+      total_chunks,
+      chunk_size,
+      file_hash: file_hash.to_string(),
+    };
+    connection.send_frame(&Self::encode(&start_msg)?).await?;
+
+    // 等待接收方（用户）决定是否接收
+    Self::await_decision(connection, record).await?;
+
+    // 接收方告知仍缺失的分片
+    let missing = match Self::decode(&connection.recv_frame().await?)? {
+      TransferMessage::ResumeOffer { missing } => missing,
+      TransferMessage::Error(err) => {
+        return Err(crate::Error::Protocol(format!("Peer error: {}", err)));
+      }
+      _ => {
+        return Err(crate::Error::Protocol(
+          "Expected ResumeOffer message".to_string(),
+        ));
+      }
+    };
+    if !resume::valid_ranges(&missing, total_chunks) {
+      return Err(crate::Error::Protocol(
+        "Invalid chunk range in ResumeOffer".to_string(),
+      ));
+    }
+
+    let chunks = Self::count_chunks(&missing);
+    connection
+      .send_frame(&Self::encode(&TransferMessage::ResumeAccept { chunks })?)
+      .await?;
+
+    let mut sent_bytes =
+      file_size.saturating_sub(Self::count_bytes(&missing, chunk_size, file_size));
+    if sent_bytes > 0 {
+      info!(
+        "Resuming transfer: {}/{} chunks already received",
+        total_chunks - chunks,
+        total_chunks
+      );
+    }
+
+    // 发送缺失的分片，接收方校验失败的分片按其要求重传
+    let mut ranges = missing;
+    let mut rounds = 0;
+    loop {
+      self
+        .send_chunks(
+          connection,
+          &mut reader,
+          &ranges,
+          file_size,
+          &mut sent_bytes,
+          progress_callback,
+        )
+        .await?;
+
+      // 发送完成消息，等待接收方校验整个文件
+      connection
+        .send_frame(&Self::encode(&TransferMessage::Complete)?)
+        .await?;
+
+      match Self::decode(&connection.recv_frame().await?)? {
+        TransferMessage::Verified => break,
+        TransferMessage::Retransmit { missing } => {
+          rounds += 1;
+          if rounds > MAX_RETRANSMIT_ROUNDS || !resume::valid_ranges(&missing, total_chunks) {
+            return Err(crate::Error::Protocol(
+              "Invalid retransmit request".to_string(),
+            ));
+          }
+          warn!(
+            "Receiver requested retransmission of {} chunks",
+            Self::count_chunks(&missing)
+          );
+          sent_bytes =
+            sent_bytes.saturating_sub(Self::count_bytes(&missing, chunk_size, file_size));
+          ranges = missing;
         }
         TransferMessage::Error(err) => {
           return Err(crate::Error::File(format!("Transfer error: {}", err)));
         }
         _ => {
-          warn!("Unexpected message type");
+          return Err(crate::Error::Protocol(
+            "Expected Verified message".to_string(),
+          ));
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  /// 等待接收方同意或拒绝（接收方排队期间定期发来的排队通知只用于保持连接活跃）
+  async fn await_decision<C: FramedConnection>(
+    connection: &mut C,
+    record: &mut HistoryRecord,
+  ) -> Result<()> {
+    let mut queued = false;
+    loop {
+      match Self::decode(&connection.recv_frame().await?)? {
+        TransferMessage::Accept => return Ok(()),
+        TransferMessage::Queued => {
+          if !queued {
+            info!("Transfer queued by receiver");
+            queued = true;
+          }
+        }
+        TransferMessage::Decline { reason } => {
+          record.set_declined(&reason);
+          connection.close().await?;
+          return Err(crate::Error::File(format!(
+            "Transfer declined by receiver: {}",
+            reason
+          )));
+        }
+        TransferMessage::Error(err) => {
+          return Err(crate::Error::Protocol(format!("Peer error: {}", err)));
+        }
+        _ => {
+          return Err(crate::Error::Protocol(
+            "Expected Accept or Decline message".to_string(),
+          ));
+        }
+      }
+    }
+  }
+
+  /// 计算数据源的 SHA-256 并校验长度，完成后回到数据源开头
+  async fn hash_stream<R>(reader: &mut R, file_size: u64) -> Result<String>
+  where
+    R: AsyncRead + AsyncSeek + Unpin,
+  {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+    let mut total = 0u64;
+    loop {
+      let n = reader
+        .read(&mut buffer)
+        .await
+        .map_err(|e| crate::Error::File(format!("Read file failed: {}", e)))?;
+      if n == 0 {
+        break;
+      }
+      ctx.update(&buffer[..n]);
+      total += n as u64;
+    }
+
+    // 长度与声明不一致时，接收方会得到被截断或不完整的文件
+    if total < file_size {
+      return Err(crate::Error::File(
+        "File is smaller than the declared size".to_string(),
+      ));
+    }
+    if total > file_size {
+      return Err(crate::Error::File(format!(
+        "File is larger than the declared size of {} bytes",
+        file_size
+      )));
+    }
+
+    reader
+      .seek(SeekFrom::Start(0))
+      .await
+      .map_err(|e| crate::Error::File(format!("Seek file failed: {}", e)))?;
+    Ok(persist::to_hex(ctx.finish().as_ref()))
+  }
+
+  /// 读取并发送指定区间内的分片，缓冲区在分片之间复用
+  async fn send_chunks<C: FramedConnection, R>(
+    &self,
+    connection: &mut C,
+    reader: &mut R,
+    ranges: &[(u64, u64)],
+    file_size: u64,
+    sent_bytes: &mut u64,
+    progress_callback: Option<&(dyn Fn(u64, u64) + Send + Sync)>,
+  ) -> Result<()>
+  where
+    R: AsyncRead + AsyncSeek + Unpin,
+  {
+    let mut buffer = vec![0u8; self.chunk_size];
+    let chunk_size = self.chunk_size as u64;
+    let chunks = Self::count_chunks(ranges);
+    let mut sent_chunks = 0u64;
+    let mut pause_signal = self.pause_signal.clone();
+    for &(start, end) in ranges {
+      reader
+        .seek(SeekFrom::Start(start * chunk_size))
+        .await
+        .map_err(|e| crate::Error::File(format!("Seek file failed: {}", e)))?;
+
+      for chunk_id in start..end {
+        if let Some(ref mut pause_signal) = pause_signal {
+          Self::wait_while_paused(connection, pause_signal).await?;
+        }
+
+        let expected = (file_size - chunk_id * chunk_size).min(chunk_size) as usize;
+        Self::read_chunk(reader, &mut buffer[..expected]).await?;
+
+        let data = buffer[..expected].to_vec();
+        let chunk_msg = TransferMessage::Chunk {
+          chunk_id,
+          digest: Self::chunk_digest(&data),
+          data,
+        };
+        connection.send_frame(&Self::encode(&chunk_msg)?).await?;
+
+        *sent_bytes += expected as u64;
+        sent_chunks += 1;
+
+        // 调用进度回调
+        if let Some(callback) = progress_callback {
+          callback(*sent_bytes, file_size);
+        }
+
+        if sent_chunks.is_multiple_of(10) {
+          info!(
+            "Sent {}/{} chunks ({}%)",
+            sent_chunks,
+            chunks,
+            (*sent_bytes * 100 / file_size)
+          );
         }
       }
     }
+    Ok(())
+  }
+
+  /// 暂停信号为 true 时通知接收方并等待恢复，连接在暂停期间保持
+  async fn wait_while_paused<C: FramedConnection>(
+    connection: &mut C,
+    pause_signal: &mut watch::Receiver<bool>,
+  ) -> Result<()> {
+    if !*pause_signal.borrow_and_update() {
+      return Ok(());
+    }
+
+    info!("Transfer paused");
+    connection
+      .send_frame(&Self::encode(&TransferMessage::Pause)?)
+      .await?;
+    // 信号的发送端被释放时视为恢复。暂停期间定期重发暂停通知，避免接收方因空闲超时断开
+    while tokio::time::timeout(KEEPALIVE_INTERVAL, pause_signal.wait_for(|paused| !*paused))
+      .await
+      .is_err()
+    {
+      connection
+        .send_frame(&Self::encode(&TransferMessage::Pause)?)
+        .await?;
+    }
+    connection
+      .send_frame(&Self::encode(&TransferMessage::Resume)?)
+      .await?;
+    info!("Transfer resumed");
+    Ok(())
+  }
+
+  /// 区间内的分片总数
+  fn count_chunks(ranges: &[(u64, u64)]) -> u64 {
+    ranges.iter().map(|(start, end)| end - start).sum()
+  }
+
+  /// 区间内的分片总字节数（最后一个分片可能不满）
+  fn count_bytes(ranges: &[(u64, u64)], chunk_size: u64, file_size: u64) -> u64 {
+    ranges
+      .iter()
+      .map(|&(start, end)| (end * chunk_size).min(file_size) - start * chunk_size)
+      .sum()
+  }
+
+  /// 计算分片数据的 SHA-256
+  fn chunk_digest(data: &[u8]) -> [u8; CHUNK_DIGEST_LEN] {
+    digest::digest(&digest::SHA256, data)
+      .as_ref()
+      .try_into()
+      .unwrap()
+  }
+
+  /// 读满一个分片，数据源提前结束时返回错误
+  async fn read_chunk<R>(reader: &mut R, buffer: &mut [u8]) -> Result<()>
+  where
+    R: AsyncRead + Unpin,
+  {
+    reader.read_exact(buffer).await.map_err(|e| {
+      if e.kind() == std::io::ErrorKind::UnexpectedEof {
+        crate::Error::File("File is smaller than the declared size".to_string())
+      } else {
+        crate::Error::File(format!("Read file failed: {}", e))
+      }
+    })?;
+    Ok(())
+  }
+
+  /// 接收文件
+  /// 返回本次连接的处理结果（接收到的文件路径，或完成的配对）
+  pub async fn receive_file<L: Transport>(
+    &self,
+    save_path: &str,
+    listener: &L,
+  ) -> Result<ReceiveOutcome> {
+    info!("Waiting for file transfer on listener...");
+
+    // 接受连接
+    let connection = listener.accept().await?;
+    self.handle_connection(save_path, connection).await
+  }
+
+  /// 处理一个已接受的入站连接
+  ///
+  /// 配对需要等待用户确认，调用方不应对本方法设置过短的超时
+  pub async fn handle_connection<C: FramedConnection>(
+    &self,
+    save_path: &str,
+    connection: C,
+  ) -> Result<ReceiveOutcome> {
+    match self.accept_connection(save_path, connection).await? {
+      IncomingConnection::Done(outcome) => Ok(outcome),
+      IncomingConnection::Accepted(pending) => self.receive_accepted(pending).await,
+    }
+  }
+
+  /// 处理入站连接直到用户同意接收（不接收文件内容）
+  ///
+  /// 依次完成会话握手、配对请求或配对检查、用户确认。
+  /// 配对和确认需要等待用户操作，调用方不应对本方法设置过短的超时
+  pub async fn accept_connection<C: FramedConnection>(
+    &self,
+    save_path: &str,
+    mut connection: C,
+  ) -> Result<IncomingConnection<C>> {
+    // 先完成会话握手，再接收传输消息（之后的所有消息都会解密校验）
+    let session = Handshake::new(&self.local_peer)
+      .respond(&mut connection)
+      .await?;
+    connection.set_encryption(Encryption::from_session(&session)?);
+
+    // 接收会话的第一条消息：配对请求、单个文件或多文件清单。
+    // 协议版本已由帧头校验，版本不一致的帧在解码时就会被拒绝
+    let start_msg = Self::decode(&connection.recv_frame().await?)?;
+    match start_msg {
+      TransferMessage::PairRequest => {
+        let result = self.confirm_pairing(&mut connection, &session).await;
+        connection.close().await?;
+        return result.map(|peer| IncomingConnection::Done(ReceiveOutcome::Paired(peer)));
+      }
+      TransferMessage::StartTransfer { .. } | TransferMessage::Manifest { .. } => {}
+      TransferMessage::Error(err) => {
+        return Err(crate::Error::Protocol(format!("Peer error: {}", err)));
+      }
+      _ => {
+        return Err(crate::Error::Protocol(
+          "Expected StartTransfer or Manifest message".to_string(),
+        ));
+      }
+    }
+
+    if !self.trust_store.is_trusted(&session.peer).await {
+      // 未配对的发送方：拒绝传输，由用户先完成配对
+      let reject = Self::encode(&TransferMessage::Error("Device not paired".to_string()))?;
+      let _ = connection.send_frame(&reject).await;
+      connection.close().await?;
+      return Err(crate::Error::Crypto(format!(
+        "Rejected unpaired sender: {} ({})",
+        session.peer.device_name, session.peer.device_id
+      )));
+    }
+
+    let sender = DeviceInfo {
+      id: session.peer.device_id.clone(),
+      name: session.peer.device_name.clone(),
+      address: addr::host_string(connection.peer_addr()),
+      port: connection.peer_addr().port(),
+      device_type: session.peer.device_type.clone(),
+      // 入站连接不携带对端公布的传输方式
+      transports: Vec::new(),
+      addresses: vec![InterfaceAddress::unnamed(addr::host_string(
+        connection.peer_addr(),
+      ))],
+    };
+
+    let mut record = HistoryRecord::new(
+      TransferDirection::Inbound,
+      String::new(),
+      connection.peer_addr().to_string(),
+      0,
+    );
+    record.set_peer(&session.peer);
+    let prepared = match start_msg {
+      TransferMessage::Manifest { manifest, .. } => {
+        record.name = manifest.name.clone();
+        record.total_size = manifest.total_size();
+        self
+          .prepare_session(&mut connection, sender, save_path, manifest, &mut record)
+          .await
+      }
+      start_msg => {
+        self
+          .prepare_single(&mut connection, sender, save_path, start_msg, &mut record)
+          .await
+      }
+    };
+
+    // 拒绝或出错时传输到此结束，在这里记录历史
+    let result = match prepared {
+      Ok(Prepared::Accepted(kind)) => {
+        return Ok(IncomingConnection::Accepted(Box::new(PendingReceive {
+          connection,
+          kind,
+          record,
+        })));
+      }
+      Ok(Prepared::Declined(outcome)) => Ok(outcome),
+      Err(e) => Err(e),
+    };
+    record.finish(&result);
+    self.save_history(record).await;
+    result.map(IncomingConnection::Done)
+  }
+
+  /// 接收已同意的入站传输的文件内容
+  pub async fn receive_accepted<C: FramedConnection>(
+    &self,
+    pending: Box<PendingReceive<C>>,
+  ) -> Result<ReceiveOutcome> {
+    let PendingReceive {
+      connection,
+      kind,
+      mut record,
+    } = *pending;
+    let result = match kind {
+      PendingKind::File(file) => self.receive_single(connection, file, &mut record).await,
+      PendingKind::Session(session) => self.receive_session(connection, session, &mut record).await,
+    };
+
+    if result.is_err() && self.is_cancelled() {
+      record.outcome = TransferOutcome::Cancelled;
+    }
+    record.finish(&result);
+    self.save_history(record).await;
+    result
+  }
+
+  /// 等待 `ready` 完成（例如等待空闲的并发名额）后再接收内容
+  ///
+  /// 发送方在收到 Accept 之前一直等待，需要排队时先发送排队通知并定期重发，避免发送方空闲超时
+  pub async fn wait_queued<C: FramedConnection, F: Future>(
+    &self,
+    pending: &mut PendingReceive<C>,
+    ready: F,
+  ) -> Result<F::Output> {
+    tokio::pin!(ready);
+    // 名额立即可用时不发送排队通知
+    let mut interval = Duration::ZERO;
+    loop {
+      if let Ok(output) = tokio::time::timeout(interval, &mut ready).await {
+        return Ok(output);
+      }
+      if interval.is_zero() {
+        info!("Transfer queued: {}", pending.name());
+      }
+      pending
+        .connection
+        .send_frame(&Self::encode(&TransferMessage::Queued)?)
+        .await?;
+      interval = KEEPALIVE_INTERVAL;
+    }
+  }
+
+  /// 取消尚未开始接收内容的入站传输：通知发送方并关闭连接
+  pub async fn cancel_accepted<C: FramedConnection>(&self, pending: Box<PendingReceive<C>>) {
+    let mut connection = pending.connection;
+    let _ = Self::reject::<_, ()>(&mut connection, Self::cancelled_error()).await;
+    info!(
+      "Transfer cancelled before receiving: {}",
+      pending.record.name
+    );
+  }
+
+  /// 是否已收到取消信号
+  fn is_cancelled(&self) -> bool {
+    self
+      .cancel_signal
+      .as_ref()
+      .is_some_and(|cancel| cancel.is_cancelled())
+  }
+
+  /// 接收方取消传输时的错误
+  fn cancelled_error() -> crate::Error {
+    crate::Error::File("Transfer cancelled by receiver".to_string())
+  }
+
+  /// 接收下一帧；收到取消信号时通知发送方并返回错误
+  async fn recv_or_cancel<C: FramedConnection>(&self, connection: &mut C) -> Result<Vec<u8>> {
+    let Some(ref cancel) = self.cancel_signal else {
+      return connection.recv_frame().await;
+    };
+    let received = tokio::select! {
+      received = connection.recv_frame() => Some(received),
+      _ = cancel.cancelled() => None,
+    };
+    match received {
+      Some(received) => received,
+      None => Self::reject(connection, Self::cancelled_error()).await,
+    }
+  }
+
+  /// 保存一条传输历史（失败时只记录日志，不影响传输结果）
+  async fn save_history(&self, record: HistoryRecord) {
+    if let Err(e) = self.history.add(record).await {
+      warn!("Save transfer history failed: {}", e);
+    }
+  }
+
+  /// 校验单个文件的参数并询问用户，拒绝时返回处理结果
+  async fn prepare_single<C: FramedConnection>(
+    &self,
+    connection: &mut C,
+    sender: DeviceInfo,
+    save_path: &str,
+    start_msg: TransferMessage,
+    record: &mut HistoryRecord,
+  ) -> Result<Prepared> {
+    let mut header = match self.parse_header(start_msg) {
+      Ok(header) => header,
+      Err(e) => return Self::reject(connection, e).await,
+    };
+    record.name = header.file_name.clone();
+    record.total_size = header.file_size;
 
-    // 合并分片
-    let file_data = FileChunk::merge_chunks(chunks)?;
+    // 文件名来自对端，不能直接拼接到保存路径上
+    header.file_name = match sanitize_file_name(&header.file_name, self.allow_hidden_files) {
+      Ok(file_name) => file_name,
+      Err(e) => return Self::reject(connection, e).await,
+    };
+    record.name = header.file_name.clone();
+
+    info!(
+      "Receiving file: {} ({} bytes, {} chunks)",
+      header.file_name, header.file_size, header.total_chunks
+    );
+
+    // 同一设备重新发送同一文件时，从检查点继续接收
+    let transfer_id = resume::transfer_id(&sender.id, &header.file_hash);
+    let existing = self.find_checkpoint(&transfer_id, &header).await;
 
-    // 保存文件
     // save_path 可以是目录路径或完整文件路径
     let save_path = Path::new(save_path);
     let final_path = if save_path.is_dir() || save_path.ends_with("/") || save_path.ends_with("\\")
     {
-      // 如果是目录路径，使用接收到的文件名
-      save_path.join(&file_name)
+      // 如果是目录路径，使用接收到的文件名，已存在同名文件时自动编号而不是覆盖
+      let final_path = save_path.join(&header.file_name);
+      match existing {
+        Some(ref checkpoint) => Self::resumed_path(checkpoint, final_path).await?,
+        None => unique_path(&final_path).await?,
+      }
     } else {
       // 如果是完整文件路径，直接使用
       save_path.to_path_buf()
@@ -253,14 +1594,1504 @@ impl FileTransfer {
         .map_err(|e| crate::Error::File(format!("Create directory failed: {}", e)))?;
     }
 
-    fs::write(&final_path, &file_data)
-      .await
-      .map_err(|e| crate::Error::File(format!("Write file failed: {}", e)))?;
+    // 新的传输先交给用户决定是否接收，续传此前已接受过的传输时不再询问
+    if existing.is_none() {
+      let offer = TransferOffer {
+        sender,
+        file_name: header.file_name.clone(),
+        file_size: header.file_size,
+        file_count: 1,
+      };
+      if let Some(outcome) = self.ask_offer(connection, offer, record).await? {
+        return Ok(Prepared::Declined(outcome));
+      }
+    }
+
+    Ok(Prepared::Accepted(PendingKind::File(PendingFile {
+      transfer_id,
+      header,
+      final_path,
+      existing,
+    })))
+  }
+
+  /// 接收单个文件
+  async fn receive_single<C: FramedConnection>(
+    &self,
+    mut connection: C,
+    file: PendingFile,
+    record: &mut HistoryRecord,
+  ) -> Result<ReceiveOutcome> {
+    let PendingFile {
+      transfer_id,
+      header,
+      final_path,
+      existing,
+    } = file;
+    connection
+      .send_frame(&Self::encode(&TransferMessage::Accept)?)
+      .await?;
 
-    connection.close()?;
+    let file_name = header.file_name.clone();
+    let file_size = header.file_size;
+    let file_hash = header.file_hash.clone();
+    let on_progress = |received: u64| {
+      self.report_receive_progress(&SessionProgress {
+        file_index: 0,
+        file_count: 1,
+        file_name: file_name.clone(),
+        file_sent: received,
+        file_size,
+        total_sent: received,
+        total_size: file_size,
+      })
+    };
+    self
+      .receive_body(
+        &mut connection,
+        &transfer_id,
+        header,
+        &final_path,
+        existing,
+        &on_progress,
+      )
+      .await?;
+
+    record.files.push(HistoryFile {
+      name: file_name,
+      path: Some(final_path.to_string_lossy().to_string()),
+      size: file_size,
+      hash: file_hash,
+    });
+
+    connection.close().await?;
     info!("File received and saved: {}", final_path.display());
 
     // 返回接收到的文件路径
-    Ok(final_path.to_string_lossy().to_string())
+    Ok(ReceiveOutcome::File(
+      final_path.to_string_lossy().to_string(),
+    ))
+  }
+
+  /// 校验清单、计算本地路径并询问用户，拒绝时返回处理结果
+  async fn prepare_session<C: FramedConnection>(
+    &self,
+    connection: &mut C,
+    sender: DeviceInfo,
+    save_path: &str,
+    manifest: Manifest,
+    record: &mut HistoryRecord,
+  ) -> Result<Prepared> {
+    if let Err(e) = manifest.validate_received() {
+      return Self::reject(connection, e).await;
+    }
+    if let Some(entry) = manifest
+      .entries
+      .iter()
+      .find(|entry| entry.size > self.max_file_size)
+    {
+      let err = crate::Error::File(format!(
+        "File too large: {} ({} bytes, max {})",
+        entry.path, entry.size, self.max_file_size
+      ));
+      return Self::reject(connection, err).await;
+    }
+    let local_paths = match self
+      .local_paths(Path::new(save_path), &sender.id, &manifest)
+      .await
+    {
+      Ok(local_paths) => local_paths,
+      Err(e) => return Self::reject(connection, e).await,
+    };
+
+    info!(
+      "Receiving {} ({} files, {} bytes) from {}",
+      manifest.name,
+      manifest.file_count(),
+      manifest.total_size(),
+      sender.name
+    );
+
+    // 整个会话只询问一次
+    let offer = TransferOffer {
+      sender: sender.clone(),
+      file_name: manifest.name.clone(),
+      file_size: manifest.total_size(),
+      file_count: manifest.file_count(),
+    };
+    if let Some(outcome) = self.ask_offer(connection, offer, record).await? {
+      return Ok(Prepared::Declined(outcome));
+    }
+
+    Ok(Prepared::Accepted(PendingKind::Session(PendingSession {
+      sender_id: sender.id,
+      manifest,
+      local_paths,
+    })))
+  }
+
+  /// 按清单接收多个文件，在保存目录下重建目录结构
+  async fn receive_session<C: FramedConnection>(
+    &self,
+    mut connection: C,
+    session: PendingSession,
+    record: &mut HistoryRecord,
+  ) -> Result<ReceiveOutcome> {
+    let PendingSession {
+      sender_id,
+      manifest,
+      local_paths,
+    } = session;
+    connection
+      .send_frame(&Self::encode(&TransferMessage::Accept)?)
+      .await?;
+
+    // 先创建目录（包括空目录）
+    for (entry, path) in manifest.entries.iter().zip(&local_paths) {
+      if !entry.is_dir {
+        continue;
+      }
+      fs::create_dir_all(path)
+        .await
+        .map_err(|e| crate::Error::File(format!("Create directory failed: {}", e)))?;
+    }
+
+    let file_count = manifest.file_count();
+    let total_size = manifest.total_size();
+    let mut total_received = 0;
+    let mut files = Vec::new();
+    for (entry, final_path) in manifest.entries.iter().zip(&local_paths) {
+      if entry.is_dir {
+        continue;
+      }
+      let header =
+        match self.parse_header(Self::decode(&self.recv_or_cancel(&mut connection).await?)?) {
+          Ok(header) => header,
+          Err(e) => return Self::reject(&mut connection, e).await,
+        };
+      if header.file_name != entry.path || header.file_size != entry.size {
+        return Err(crate::Error::Protocol(format!(
+          "File does not match manifest entry: {}",
+          header.file_name
+        )));
+      }
+
+      if let Some(parent) = final_path.parent() {
+        fs::create_dir_all(parent)
+          .await
+          .map_err(|e| crate::Error::File(format!("Create directory failed: {}", e)))?;
+      }
+
+      let transfer_id = resume::transfer_id(&sender_id, &header.file_hash);
+      let existing = self.find_checkpoint(&transfer_id, &header).await;
+      let file_hash = header.file_hash.clone();
+      connection
+        .send_frame(&Self::encode(&TransferMessage::Accept)?)
+        .await?;
+      let on_progress = |received: u64| {
+        self.report_receive_progress(&SessionProgress {
+          file_index: files.len(),
+          file_count,
+          file_name: entry.path.clone(),
+          file_sent: received,
+          file_size: entry.size,
+          total_sent: total_received + received,
+          total_size,
+        })
+      };
+      self
+        .receive_body(
+          &mut connection,
+          &transfer_id,
+          header,
+          final_path,
+          existing,
+          &on_progress,
+        )
+        .await?;
+      total_received += entry.size;
+
+      if let Err(e) = entry.apply_metadata(final_path).await {
+        warn!(
+          "Apply file metadata failed: {} ({})",
+          final_path.display(),
+          e
+        );
+      }
+      info!("File received and saved: {}", final_path.display());
+      record.files.push(HistoryFile {
+        name: entry.path.clone(),
+        path: Some(final_path.to_string_lossy().to_string()),
+        size: entry.size,
+        hash: file_hash,
+      });
+      files.push(final_path.to_string_lossy().to_string());
+    }
+
+    // 写入子项会改变目录的修改时间，因此最后由深到浅设置目录属性
+    for (entry, path) in manifest.entries.iter().zip(&local_paths).rev() {
+      if !entry.is_dir {
+        continue;
+      }
+      if let Err(e) = entry.apply_metadata(path).await {
+        warn!(
+          "Apply directory metadata failed: {} ({})",
+          path.display(),
+          e
+        );
+      }
+    }
+
+    connection.close().await?;
+    info!(
+      "Session received: {} ({} files)",
+      manifest.name,
+      files.len()
+    );
+
+    Ok(ReceiveOutcome::Files(files))
+  }
+
+  /// 计算清单中每一项在保存目录下的本地路径
+  ///
+  /// 每个路径组件都经过清理；顶层的文件或目录与已有文件重名时自动编号，
+  /// 整个目录树随之放到编号后的目录下。续传同一发送方未完成的会话时沿用上次的顶层路径，
+  /// 沿用的目录中只有检查点记录的文件续写到原路径，其他已存在的同名文件同样自动编号
+  async fn local_paths(
+    &self,
+    root: &Path,
+    sender_id: &str,
+    manifest: &Manifest,
+  ) -> Result<Vec<PathBuf>> {
+    // 顶层名称 -> (本地路径, 是否沿用了上次的目录)
+    let mut top_level: HashMap<&str, (PathBuf, bool)> = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut local_paths = Vec::with_capacity(manifest.entries.len());
+    let checkpoints = self.resume_store.list().await;
+
+    for entry in &manifest.entries {
+      let mut components = entry.path.split('/');
+      let first = components.next().unwrap_or_default();
+      let (mut path, resumed) = match top_level.get(first) {
+        Some(top) => top.clone(),
+        None => {
+          let top = match Self::resumed_top_level(root, sender_id, manifest, first, &checkpoints) {
+            Some(path) => (path, true),
+            None => {
+              let name = sanitize_file_name(first, self.allow_hidden_files)?;
+              (unique_path(&root.join(name)).await?, false)
+            }
+          };
+          top_level.insert(first, top.clone());
+          top
+        }
+      };
+      for component in components {
+        path.push(sanitize_file_name(component, self.allow_hidden_files)?);
+      }
+      if resumed
+        && !entry.is_dir
+        && !Self::is_checkpointed(&path, sender_id, entry, &checkpoints).await
+      {
+        path = unique_path(&path).await?;
+      }
+
+      // 清理后不同的名称可能变得相同
+      if !seen.insert(path.clone()) {
+        return Err(crate::Error::File(format!(
+          "Conflicting file names in manifest: {:?}",
+          entry.path
+        )));
+      }
+      local_paths.push(path);
+    }
+
+    Ok(local_paths)
+  }
+
+  /// 同一发送方未完成的会话中，顶层项 `first` 上次使用的本地路径
+  ///
+  /// 会话中文件的检查点以清单路径作为文件名，临时文件位于上次的目标路径旁，
+  /// 据此找回上次（可能已自动编号的）顶层路径，避免续传时另建一棵目录树
+  fn resumed_top_level(
+    root: &Path,
+    sender_id: &str,
+    manifest: &Manifest,
+    first: &str,
+    checkpoints: &[TransferCheckpoint],
+  ) -> Option<PathBuf> {
+    checkpoints.iter().find_map(|checkpoint| {
+      if checkpoint.transfer_id != resume::transfer_id(sender_id, &checkpoint.file_hash) {
+        return None;
+      }
+      let entry = manifest.entries.iter().find(|entry| {
+        !entry.is_dir
+          && entry.path == checkpoint.file_name
+          && entry.size == checkpoint.file_size
+          && entry.path.split('/').next() == Some(first)
+      })?;
+      let final_path = Path::new(
+        checkpoint
+          .temp_path
+          .strip_suffix(&format!(".{}", PART_EXTENSION))?,
+      );
+      let depth = entry.path.split('/').count();
+      let top = final_path.ancestors().nth(depth - 1)?;
+      // 单个文件只在目标文件尚不存在时沿用；目录中的其他文件可能已经收完
+      let reusable = if depth == 1 {
+        !top.exists()
+      } else {
+        top.is_dir()
+      };
+      (top.parent() == Some(root) && reusable).then(|| top.to_path_buf())
+    })
+  }
+
+  /// `path` 是否为同一发送方未完成的检查点的目标路径，且目标文件尚不存在
+  async fn is_checkpointed(
+    path: &Path,
+    sender_id: &str,
+    entry: &ManifestEntry,
+    checkpoints: &[TransferCheckpoint],
+  ) -> bool {
+    let recorded = checkpoints.iter().any(|checkpoint| {
+      checkpoint.transfer_id == resume::transfer_id(sender_id, &checkpoint.file_hash)
+        && checkpoint.file_name == entry.path
+        && checkpoint.file_size == entry.size
+        && Path::new(&checkpoint.temp_path) == part_path(path)
+    });
+    recorded && !fs::try_exists(path).await.unwrap_or(true)
+  }
+
+  /// 续传时的目标路径
+  ///
+  /// 检查点的临时文件正是目标文件对应的 `.part` 时不算占用，否则与新传输一样自动编号
+  async fn resumed_path(checkpoint: &TransferCheckpoint, final_path: PathBuf) -> Result<PathBuf> {
+    if Path::new(&checkpoint.temp_path) == part_path(&final_path)
+      && !fs::try_exists(&final_path).await.unwrap_or(true)
+    {
+      return Ok(final_path);
+    }
+    unique_path(&final_path).await
+  }
+
+  /// 通知发送方拒绝的原因并关闭连接，返回该错误
+  async fn reject<C: FramedConnection, R>(connection: &mut C, error: crate::Error) -> Result<R> {
+    if let Ok(data) = Self::encode(&TransferMessage::Error(error.to_string())) {
+      let _ = connection.send_frame(&data).await;
+    }
+    let _ = connection.close().await;
+    Err(error)
+  }
+
+  /// 解析并校验开始传输消息
+  fn parse_header(&self, start_msg: TransferMessage) -> Result<FileHeader> {
+    let header = match start_msg {
+      TransferMessage::StartTransfer {
+        file_name,
+        file_size,
+        total_chunks,
+        chunk_size,
+        file_hash,
+        ..
+      } => FileHeader {
+        file_name,
+        file_size,
+        total_chunks,
+        chunk_size,
+        file_hash,
+      },
+      TransferMessage::Error(err) => {
+        return Err(crate::Error::Protocol(format!("Peer error: {}", err)));
+      }
+      _ => {
+        return Err(crate::Error::Protocol(
+          "Expected StartTransfer message".to_string(),
+        ));
+      }
+    };
+
+    if header.file_hash.len() != CHUNK_DIGEST_LEN * 2 {
+      return Err(crate::Error::ProtocolViolation(format!(
+        "Invalid file hash: {:?}",
+        header.file_hash
+      )));
+    }
+
+    if header.file_size > self.max_file_size {
+      return Err(crate::Error::File(format!(
+        "File too large: {} bytes (max {})",
+        header.file_size, self.max_file_size
+      )));
+    }
+
+    // 分片大小决定接收方的缓冲区大小，不能超过单帧能容纳的负载；
+    // 也不能过小，否则分片总数会大到让检查点耗尽内存
+    if header.chunk_size < MIN_CHUNK_SIZE
+      || header.chunk_size > MAX_CHUNK_SIZE
+      || header.file_size.div_ceil(header.chunk_size) != header.total_chunks
+    {
+      return Err(crate::Error::ProtocolViolation(format!(
+        "Invalid transfer layout: {} bytes, {} chunks of {} bytes",
+        header.file_size, header.total_chunks, header.chunk_size
+      )));
+    }
+
+    Ok(header)
+  }
+
+  /// 查找与本次传输参数一致的检查点
+  async fn find_checkpoint(
+    &self,
+    transfer_id: &str,
+    header: &FileHeader,
+  ) -> Option<TransferCheckpoint> {
+    self
+      .resume_store
+      .resume(transfer_id)
+      .await
+      .filter(|checkpoint| {
+        checkpoint.file_size == header.file_size
+          && checkpoint.chunk_size == header.chunk_size
+          && checkpoint.total_chunks == header.total_chunks
+      })
+  }
+
+  /// 询问用户是否接收；拒绝时通知发送方并返回处理结果
+  async fn ask_offer<C: FramedConnection>(
+    &self,
+    connection: &mut C,
+    offer: TransferOffer,
+    record: &mut HistoryRecord,
+  ) -> Result<Option<ReceiveOutcome>> {
+    let decision = match self.offer_handler {
+      Some(ref handler) => handler(offer.clone()).await,
+      None => TransferDecision::Accept,
+    };
+    let TransferDecision::Decline(reason) = decision else {
+      return Ok(None);
+    };
+
+    info!("Transfer declined: {} ({})", offer.file_name, reason);
+    record.set_declined(&reason);
+    let _ = connection
+      .send_frame(&Self::encode(&TransferMessage::Decline { reason })?)
+      .await;
+    connection.close().await?;
+    Ok(Some(ReceiveOutcome::Declined(offer)))
+  }
+
+  /// 接收一个文件的内容，校验通过后重命名为目标文件
+  async fn receive_body<C: FramedConnection>(
+    &self,
+    connection: &mut C,
+    transfer_id: &str,
+    header: FileHeader,
+    final_path: &Path,
+    existing: Option<TransferCheckpoint>,
+    on_progress: &(dyn Fn(u64) + Send + Sync),
+  ) -> Result<()> {
+    // 分片直接写入临时文件，全部收齐并校验后再重命名为目标文件
+    let (mut checkpoint, mut file) = match existing {
+      Some(checkpoint) => {
+        let file = fs::OpenOptions::new()
+          .read(true)
+          .write(true)
+          .open(&checkpoint.temp_path)
+          .await
+          .map_err(|e| crate::Error::File(format!("Open file failed: {}", e)))?;
+        (checkpoint, file)
+      }
+      None => {
+        let part_path = part_path(final_path);
+        // 收齐后需要读回整个文件做校验，因此以读写方式打开
+        let file = fs::OpenOptions::new()
+          .read(true)
+          .write(true)
+          .create(true)
+          .truncate(true)
+          .open(&part_path)
+          .await
+          .map_err(|e| crate::Error::File(format!("Create file failed: {}", e)))?;
+        let checkpoint = TransferCheckpoint::new(
+          transfer_id.to_string(),
+          header.file_name,
+          header.file_size,
+          header.file_hash,
+          header.chunk_size,
+          header.total_chunks,
+          part_path.to_string_lossy().to_string(),
+        );
+        (checkpoint, file)
+      }
+    };
+    let temp_path = PathBuf::from(&checkpoint.temp_path);
+
+    let result = self
+      .receive_chunks(connection, &mut file, &mut checkpoint, on_progress)
+      .await;
+    if let Err(e) = result {
+      // 连接中断时保留临时文件和检查点，等待发送方重新连接后续传
+      if matches!(e, crate::Error::Network(_)) && file.flush().await.is_ok() {
+        if let Err(save_err) = self.resume_store.save_progress(&checkpoint).await {
+          warn!("Save transfer checkpoint failed: {}", save_err);
+        }
+        info!(
+          "Transfer interrupted, checkpoint saved: {}/{} chunks",
+          checkpoint.received_chunks(),
+          checkpoint.total_chunks
+        );
+      } else {
+        let _ = self.resume_store.remove(transfer_id).await;
+        let _ = fs::remove_file(&temp_path).await;
+      }
+      return Err(e);
+    }
+    drop(file);
+
+    let _ = self.resume_store.remove(transfer_id).await;
+    if let Err(e) = fs::rename(&temp_path, final_path).await {
+      let _ = fs::remove_file(&temp_path).await;
+      return Err(crate::Error::File(format!("Rename file failed: {}", e)));
+    }
+
+    Ok(())
+  }
+
+  /// 上报接收进度
+  fn report_receive_progress(&self, progress: &SessionProgress) {
+    if let Some(ref handler) = self.receive_progress_handler {
+      handler(progress);
+    }
+  }
+
+  /// 告知发送方缺失的分片，接收这些分片并按偏移写入临时文件
+  ///
+  /// 摘要不匹配的分片会被丢弃并在发送方发出完成消息后要求重传；
+  /// 收齐后校验整个文件的 SHA-256，不一致时拒绝保存
+  async fn receive_chunks<C: FramedConnection>(
+    &self,
+    connection: &mut C,
+    file: &mut fs::File,
+    checkpoint: &mut TransferCheckpoint,
+    on_progress: &(dyn Fn(u64) + Send + Sync),
+  ) -> Result<()> {
+    let file_size = checkpoint.file_size;
+    let total_chunks = checkpoint.total_chunks;
+    let chunk_size = checkpoint.chunk_size;
+
+    let missing = checkpoint.missing_ranges();
+    let expected_chunks = Self::count_chunks(&missing);
+    let mut received_bytes =
+      file_size.saturating_sub(Self::count_bytes(&missing, chunk_size, file_size));
+    connection
+      .send_frame(&Self::encode(&TransferMessage::ResumeOffer { missing })?)
+      .await?;
+
+    match Self::decode(&self.recv_or_cancel(connection).await?)? {
+      TransferMessage::ResumeAccept { chunks } if chunks == expected_chunks => {}
+      TransferMessage::ResumeAccept { chunks } => {
+        return Err(crate::Error::Protocol(format!(
+          "Resume chunk count mismatch: expected {}, got {}",
+          expected_chunks, chunks
+        )));
+      }
+      TransferMessage::Error(err) => {
+        return Err(crate::Error::File(format!("Transfer error: {}", err)));
+      }
+      _ => {
+        return Err(crate::Error::Protocol(
+          "Expected ResumeAccept message".to_string(),
+        ));
+      }
+    }
+
+    let mut received_chunks = checkpoint.received_chunks();
+    let mut unsaved_chunks = 0;
+    let mut rounds = 0;
+    let mut paused = false;
+
+    loop {
+      let chunk_data = self.recv_or_cancel(connection).await?;
+      let chunk_msg = Self::decode(&chunk_data)?;
+
+      match chunk_msg {
+        TransferMessage::Chunk {
+          chunk_id,
+          digest,
+          data,
+        } => {
+          if chunk_id >= total_chunks {
+            return Err(crate::Error::Protocol(format!(
+              "Chunk id out of range: {} (total {})",
+              chunk_id, total_chunks
+            )));
+          }
+
+          let offset = chunk_id * chunk_size;
+          let expected = (file_size - offset).min(chunk_size);
+          if data.len() as u64 != expected {
+            return Err(crate::Error::Protocol(format!(
+              "Chunk {} size mismatch: expected {}, got {}",
+              chunk_id,
+              expected,
+              data.len()
+            )));
+          }
+
+          // 校验失败的分片不写入，等发送方发完后统一要求重传
+          if Self::chunk_digest(&data) != digest {
+            warn!(
+              "Chunk {} digest mismatch, will request retransmission",
+              chunk_id
+            );
+            continue;
+          }
+
+          file
+            .seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| crate::Error::File(format!("Seek file failed: {}", e)))?;
+          file
+            .write_all(&data)
+            .await
+            .map_err(|e| crate::Error::File(format!("Write file failed: {}", e)))?;
+
+          // 重复的分片覆盖写入同一位置，不重复计数
+          if checkpoint.mark_received(chunk_id) {
+            received_chunks += 1;
+            unsaved_chunks += 1;
+            received_bytes += data.len() as u64;
+            on_progress(received_bytes);
+          }
+
+          // 定期保存检查点（先把数据写出，保证位图不超前于文件内容）
+          if unsaved_chunks >= CHECKPOINT_INTERVAL {
+            file
+              .flush()
+              .await
+              .map_err(|e| crate::Error::File(format!("Write file failed: {}", e)))?;
+            self.resume_store.save_progress(checkpoint).await?;
+            unsaved_chunks = 0;
+          }
+
+          if received_chunks.is_multiple_of(10) {
+            info!("Received {}/{} chunks", received_chunks, total_chunks);
+          }
+        }
+        TransferMessage::Complete if received_chunks == total_chunks => break,
+        TransferMessage::Complete => {
+          rounds += 1;
+          if rounds > MAX_RETRANSMIT_ROUNDS {
+            let err = format!(
+              "Transfer incomplete: received {}/{} chunks",
+              received_chunks, total_chunks
+            );
+            let _ = connection
+              .send_frame(&Self::encode(&TransferMessage::Error(err.clone()))?)
+              .await;
+            return Err(crate::Error::File(err));
+          }
+
+          let missing = checkpoint.missing_ranges();
+          warn!(
+            "Requesting retransmission of {} chunks",
+            Self::count_chunks(&missing)
+          );
+          connection
+            .send_frame(&Self::encode(&TransferMessage::Retransmit { missing })?)
+            .await?;
+        }
+        // 暂停期间发送方定期重发的暂停通知，只用于保持连接活跃
+        TransferMessage::Pause if paused => {}
+        TransferMessage::Pause => {
+          paused = true;
+          // 暂停可能持续较久，先保存检查点，期间连接断开也能续传
+          info!("Transfer paused by sender");
+          file
+            .flush()
+            .await
+            .map_err(|e| crate::Error::File(format!("Write file failed: {}", e)))?;
+          self.resume_store.save_progress(checkpoint).await?;
+          unsaved_chunks = 0;
+          if let Some(ref handler) = self.peer_pause_handler {
+            handler(true);
+          }
+        }
+        TransferMessage::Resume => {
+          paused = false;
+          info!("Transfer resumed by sender");
+          if let Some(ref handler) = self.peer_pause_handler {
+            handler(false);
+          }
+        }
+        TransferMessage::Error(err) => {
+          return Err(crate::Error::File(format!("Transfer error: {}", err)));
+        }
+        _ => {
+          warn!("Unexpected message type");
+        }
+      }
+    }
+
+    file
+      .sync_all()
+      .await
+      .map_err(|e| crate::Error::File(format!("Write file failed: {}", e)))?;
+
+    // 校验文件大小
+    let written = file
+      .metadata()
+      .await
+      .map_err(|e| crate::Error::File(format!("Read file metadata failed: {}", e)))?
+      .len();
+    if written != file_size {
+      return Err(crate::Error::File(format!(
+        "File size mismatch: expected {}, got {}",
+        file_size, written
+      )));
+    }
+
+    // 校验整个文件的 SHA-256
+    file
+      .seek(SeekFrom::Start(0))
+      .await
+      .map_err(|e| crate::Error::File(format!("Seek file failed: {}", e)))?;
+    let file_hash = Self::hash_stream(file, file_size).await?;
+    if file_hash != checkpoint.file_hash {
+      let _ = connection
+        .send_frame(&Self::encode(&TransferMessage::Error(
+          "File hash mismatch".to_string(),
+        ))?)
+        .await;
+      return Err(crate::Error::File(format!(
+        "File hash mismatch: expected {}, got {}",
+        checkpoint.file_hash, file_hash
+      )));
+    }
+
+    connection
+      .send_frame(&Self::encode(&TransferMessage::Verified)?)
+      .await?;
+
+    Ok(())
+  }
+}
+
+impl Default for FileTransfer {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T: Transport> Clone for FileTransfer<T> {
+  fn clone(&self) -> Self {
+    Self {
+      transport: self.transport.clone(),
+      chunk_size: self.chunk_size,
+      local_peer: self.local_peer.clone(),
+      trust_store: self.trust_store.clone(),
+      resume_store: self.resume_store.clone(),
+      history: self.history.clone(),
+      pairing_handler: self.pairing_handler.clone(),
+      offer_handler: self.offer_handler.clone(),
+      allow_hidden_files: self.allow_hidden_files,
+      max_file_size: self.max_file_size,
+      pause_signal: self.pause_signal.clone(),
+      cancel_signal: self.cancel_signal.clone(),
+      peer_pause_handler: self.peer_pause_handler.clone(),
+      receive_progress_handler: self.receive_progress_handler.clone(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::file::history::HistoryQuery;
+  use crate::p2p::memory::{MemoryConnection, MemoryNetwork, MemoryTransport};
+  use std::io::Cursor;
+  use std::sync::Mutex;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  /// 每个测试使用独立的临时目录
+  fn test_dir(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+      "stationuli-transfer-{}-{}-{}",
+      name,
+      std::process::id(),
+      NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn local_peer(name: &str) -> LocalPeer {
+    LocalPeer::generate(
+      format!("device-{}", name),
+      name.to_string(),
+      "desktop".to_string(),
+    )
+  }
+
+  fn identity(peer: &LocalPeer) -> PeerIdentity {
+    PeerIdentity {
+      device_id: peer.device_id.clone(),
+      device_name: peer.device_name.clone(),
+      device_type: peer.device_type.clone(),
+      public_key: peer.public_key(),
+    }
+  }
+
+  /// 以已配对的发送方身份连接接收方并完成握手，返回启用加密后的发送端和接收方的处理任务
+  async fn connect_raw<T: Transport>(
+    receiver: &FileTransfer<T>,
+    save_path: &Path,
+  ) -> (
+    MemoryConnection,
+    tokio::task::JoinHandle<Result<ReceiveOutcome>>,
+  ) {
+    let sender = local_peer("raw");
+    receiver
+      .trust_store()
+      .trust(&identity(&sender))
+      .await
+      .unwrap();
+    let (mut client, server) = MemoryConnection::pair(
+      SocketAddr::from(([127, 0, 0, 1], 1)),
+      SocketAddr::from(([127, 0, 0, 1], 2)),
+    );
+    let task = {
+      let receiver = receiver.clone();
+      let save_path = save_path.to_string_lossy().to_string();
+      tokio::spawn(async move { receiver.handle_connection(&save_path, server).await })
+    };
+    let session = Handshake::new(&sender).initiate(&mut client).await.unwrap();
+    client.set_encryption(Encryption::from_session(&session).unwrap());
+    (client, task)
+  }
+
+  async fn send_message(connection: &mut MemoryConnection, message: &TransferMessage) {
+    connection
+      .send_frame(&frame::encode(message).unwrap())
+      .await
+      .unwrap();
+  }
+
+  async fn recv_message(connection: &mut MemoryConnection) -> TransferMessage {
+    frame::decode(&connection.recv_frame().await.unwrap()).unwrap()
+  }
+
+  fn start_transfer(file_size: u64, total_chunks: u64, chunk_size: u64) -> TransferMessage {
+    TransferMessage::StartTransfer {
+      protocol_version: PROTOCOL_VERSION,
+      file_name: "huge.bin".to_string(),
+      file_size,
+      total_chunks,
+      chunk_size,
+      file_hash: "00".repeat(CHUNK_DIGEST_LEN),
+    }
+  }
+
+  #[tokio::test]
+  async fn rejects_tiny_chunks_before_creating_checkpoint() {
+    let dir = test_dir("tiny-chunks");
+    let receiver = FileTransfer::new();
+    let (mut client, task) = connect_raw(&receiver, &dir).await;
+
+    // 分片大小为 1 时每个字节都是一个分片，检查点位图会随文件大小失控
+    let file_size = DEFAULT_MAX_FILE_SIZE;
+    send_message(&mut client, &start_transfer(file_size, file_size, 1)).await;
+    assert!(matches!(
+      task.await.unwrap(),
+      Err(crate::Error::ProtocolViolation(_))
+    ));
+    assert!(matches!(
+      recv_message(&mut client).await,
+      TransferMessage::Error(_)
+    ));
+    assert!(receiver.resume_store().list().await.is_empty());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn rejects_files_over_size_limit() {
+    let dir = test_dir("size-limit");
+    let mut receiver = FileTransfer::new();
+    receiver.set_max_file_size(1024 * 1024);
+    let (mut client, task) = connect_raw(&receiver, &dir).await;
+
+    let chunk_size = DEFAULT_CHUNK_SIZE as u64;
+    send_message(&mut client, &start_transfer(chunk_size + 1, 2, chunk_size)).await;
+    assert!(matches!(task.await.unwrap(), Err(crate::Error::File(_))));
+    assert!(matches!(
+      recv_message(&mut client).await,
+      TransferMessage::Error(_)
+    ));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn resumed_directory_only_reuses_checkpointed_paths() {
+    let root = test_dir("resumed-top-level");
+    let receiver = FileTransfer::new();
+    let sender_id = "device-sender";
+    let entry = |path: &str, is_dir: bool, size: u64| ManifestEntry {
+      path: path.to_string(),
+      is_dir,
+      size,
+      mode: None,
+      modified: None,
+    };
+    let manifest = Manifest {
+      name: "photos".to_string(),
+      entries: vec![
+        entry("photos", true, 0),
+        entry("photos/a.jpg", false, 3),
+        entry("photos/b.jpg", false, 3),
+      ],
+    };
+
+    // 上次会话在 photos 下留下了 b.jpg 的临时文件和检查点；a.jpg 是本地已有的其他文件
+    let dir = root.join("photos");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.jpg"), b"old").unwrap();
+    std::fs::write(dir.join("b.jpg.part"), b"").unwrap();
+    let file_hash = "11".repeat(CHUNK_DIGEST_LEN);
+    let checkpoint = TransferCheckpoint::new(
+      resume::transfer_id(sender_id, &file_hash),
+      "photos/b.jpg".to_string(),
+      3,
+      file_hash,
+      MIN_CHUNK_SIZE,
+      1,
+      dir.join("b.jpg.part").to_string_lossy().to_string(),
+    );
+    receiver
+      .resume_store()
+      .save_progress(&checkpoint)
+      .await
+      .unwrap();
+
+    let paths = receiver
+      .local_paths(&root, sender_id, &manifest)
+      .await
+      .unwrap();
+    assert_eq!(paths[0], dir);
+    assert_eq!(paths[1], dir.join("a (1).jpg"));
+    assert_eq!(paths[2], dir.join("b.jpg"));
+    assert_eq!(std::fs::read(dir.join("a.jpg")).unwrap(), b"old");
+    let _ = std::fs::remove_dir_all(&root);
+  }
+
+  #[test]
+  fn sanitizes_file_names() {
+    let clean = |name: &str| sanitize_file_name(name, true).unwrap();
+    assert_eq!(clean("report.pdf"), "report.pdf");
+    // 只保留最后一个路径组件
+    assert_eq!(clean("../../etc/passwd"), "passwd");
+    assert_eq!(clean("C:\\Windows\\system.ini"), "system.ini");
+    assert_eq!(clean("/absolute/path.txt"), "path.txt");
+    // 控制字符和 Windows 不允许的字符被替换，结尾的点和空格被去掉
+    assert_eq!(clean("a<b>c:d\"e|f?g*h\u{7}.txt"), "a_b_c_d_e_f_g_h_.txt");
+    assert_eq!(clean("name. . "), "name");
+
+    for invalid in ["", ".", "..", "dir/", "...", "CON", "nul.txt", "Com1.log"] {
+      assert!(sanitize_file_name(invalid, true).is_err(), "{:?}", invalid);
+    }
+    assert!(sanitize_file_name(".hidden", false).is_err());
+    assert_eq!(sanitize_file_name(".hidden", true).unwrap(), ".hidden");
+  }
+
+  #[test]
+  fn truncates_long_file_names() {
+    let long = format!("{}.tar.gz", "a".repeat(300));
+    let name = sanitize_file_name(&long, true).unwrap();
+    assert!(name.len() <= MAX_FILE_NAME_LEN);
+    assert!(name.ends_with(".gz"));
+
+    // 按字符边界截断多字节字符
+    let name = sanitize_file_name(&"文".repeat(100), true).unwrap();
+    assert!(name.len() <= MAX_FILE_NAME_LEN);
+    assert!(name.chars().all(|c| c == '文'));
+  }
+
+  #[tokio::test]
+  async fn numbers_existing_paths() {
+    let dir = test_dir("unique-path");
+    let path = dir.join("photo.jpg");
+    assert_eq!(unique_path(&path).await.unwrap(), path);
+
+    std::fs::write(&path, b"").unwrap();
+    assert_eq!(unique_path(&path).await.unwrap(), dir.join("photo (1).jpg"));
+    // 其他传输未完成的临时文件同样视为占用
+    std::fs::write(dir.join("photo (1).jpg.part"), b"").unwrap();
+    assert_eq!(unique_path(&path).await.unwrap(), dir.join("photo (2).jpg"));
+
+    std::fs::write(dir.join("archive"), b"").unwrap();
+    assert_eq!(
+      unique_path(&dir.join("archive")).await.unwrap(),
+      dir.join("archive (1)")
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn rejects_invalid_manifest_with_error_frame() {
+    let dir = test_dir("invalid-manifest");
+    let receiver = FileTransfer::new();
+    let (mut client, task) = connect_raw(&receiver, &dir).await;
+
+    let manifest = Manifest {
+      name: "escape".to_string(),
+      entries: vec![ManifestEntry {
+        path: "../escape.txt".to_string(),
+        is_dir: false,
+        size: 1,
+        mode: None,
+        modified: None,
+      }],
+    };
+    send_message(
+      &mut client,
+      &TransferMessage::Manifest {
+        protocol_version: PROTOCOL_VERSION,
+        manifest,
+      },
+    )
+    .await;
+    assert!(task.await.unwrap().is_err());
+    assert!(matches!(
+      recv_message(&mut client).await,
+      TransferMessage::Error(_)
+    ));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  fn test_data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+  }
+
+  fn sha256_hex(data: &[u8]) -> String {
+    persist::to_hex(digest::digest(&digest::SHA256, data).as_ref())
+  }
+
+  /// 通过内存网络连接的发送方和接收方（尚未配对），返回接收方的监听端
+  fn transfers() -> (
+    FileTransfer<MemoryTransport>,
+    FileTransfer<MemoryTransport>,
+    Arc<MemoryTransport>,
+  ) {
+    let network = MemoryNetwork::new();
+    let listener = Arc::new(network.bind(0).unwrap());
+    let mut sender = FileTransfer::with_transport(network.transport());
+    sender.set_local_peer(local_peer("sender"));
+    // 使用较小的分片，少量数据也能覆盖多个分片
+    sender.chunk_size = MIN_CHUNK_SIZE as usize;
+    let mut receiver = FileTransfer::with_transport(network.transport());
+    receiver.set_local_peer(local_peer("receiver"));
+    (sender, receiver, listener)
+  }
+
+  /// 已互相信任的发送方和接收方
+  async fn paired_transfers() -> (
+    FileTransfer<MemoryTransport>,
+    FileTransfer<MemoryTransport>,
+    Arc<MemoryTransport>,
+  ) {
+    let (sender, receiver, listener) = transfers();
+    sender
+      .trust_store()
+      .trust(&identity(receiver.local_peer()))
+      .await
+      .unwrap();
+    receiver
+      .trust_store()
+      .trust(&identity(sender.local_peer()))
+      .await
+      .unwrap();
+    (sender, receiver, listener)
+  }
+
+  /// 在后台接收一个入站连接
+  fn spawn_receive(
+    receiver: &FileTransfer<MemoryTransport>,
+    listener: Arc<MemoryTransport>,
+    save_path: &Path,
+  ) -> tokio::task::JoinHandle<Result<ReceiveOutcome>> {
+    let receiver = receiver.clone();
+    let save_path = format!("{}/", save_path.display());
+    tokio::spawn(async move { receiver.receive_file(&save_path, &listener).await })
+  }
+
+  async fn send_data(
+    sender: &FileTransfer<MemoryTransport>,
+    listener: &MemoryTransport,
+    data: &[u8],
+    progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+  ) -> Result<()> {
+    let port = listener.local_addr().unwrap().port();
+    sender
+      .send_stream(
+        Cursor::new(data.to_vec()),
+        "data.bin",
+        data.len() as u64,
+        "127.0.0.1",
+        port,
+        progress,
+      )
+      .await
+  }
+
+  /// 以原始消息发送单个文件的开始消息，并完成到 ResumeAccept 为止的交互
+  async fn start_raw_transfer(client: &mut MemoryConnection, data: &[u8], chunks: u64) {
+    let start = TransferMessage::StartTransfer {
+      protocol_version: PROTOCOL_VERSION,
+      file_name: "raw.bin".to_string(),
+      file_size: data.len() as u64,
+      total_chunks: chunks,
+      chunk_size: MIN_CHUNK_SIZE,
+      file_hash: sha256_hex(data),
+    };
+    send_message(client, &start).await;
+    assert!(matches!(
+      recv_message(client).await,
+      TransferMessage::Accept
+    ));
+    assert!(matches!(
+      recv_message(client).await,
+      TransferMessage::ResumeOffer { missing } if missing == vec![(0, chunks)]
+    ));
+    send_message(client, &TransferMessage::ResumeAccept { chunks }).await;
+  }
+
+  fn chunk_message(data: &[u8], chunk_id: u64) -> TransferMessage {
+    let start = chunk_id as usize * MIN_CHUNK_SIZE as usize;
+    let end = (start + MIN_CHUNK_SIZE as usize).min(data.len());
+    let data = data[start..end].to_vec();
+    TransferMessage::Chunk {
+      chunk_id,
+      digest: FileTransfer::<TcpTransport>::chunk_digest(&data),
+      data,
+    }
+  }
+
+  #[tokio::test]
+  async fn sends_and_receives_file() {
+    let dir = test_dir("send-receive");
+    let (sender, receiver, listener) = paired_transfers().await;
+    let data = test_data(2 * MIN_CHUNK_SIZE as usize + 100);
+    let receive = spawn_receive(&receiver, listener.clone(), &dir);
+
+    send_data(&sender, &listener, &data, None).await.unwrap();
+    let Ok(ReceiveOutcome::File(path)) = receive.await.unwrap() else {
+      panic!("expected a received file");
+    };
+    assert_eq!(Path::new(&path), dir.join("data.bin"));
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    assert!(receiver.resume_store().list().await.is_empty());
+
+    // 两端都记录了完成的传输
+    for (history, direction) in [
+      (sender.history(), TransferDirection::Outbound),
+      (receiver.history(), TransferDirection::Inbound),
+    ] {
+      let records = history.query(&HistoryQuery::default()).await;
+      assert_eq!(records.len(), 1);
+      assert_eq!(records[0].direction, direction);
+      assert_eq!(records[0].outcome, TransferOutcome::Completed);
+      assert_eq!(records[0].total_size, data.len() as u64);
+      assert_eq!(records[0].files[0].hash, sha256_hex(&data));
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn rejects_unpaired_sender() {
+    let dir = test_dir("unpaired");
+    let (sender, receiver, listener) = transfers();
+    // 发送方信任接收方，但接收方没有信任发送方
+    sender
+      .trust_store()
+      .trust(&identity(receiver.local_peer()))
+      .await
+      .unwrap();
+    let receive = spawn_receive(&receiver, listener.clone(), &dir);
+
+    assert!(send_data(&sender, &listener, b"data", None).await.is_err());
+    assert!(matches!(
+      receive.await.unwrap(),
+      Err(crate::Error::Crypto(_))
+    ));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn pairing_trusts_both_devices() {
+    let dir = test_dir("pairing");
+    let (mut sender, mut receiver, listener) = transfers();
+    let confirm: PairingHandler = Arc::new(|_| Box::pin(async { true }));
+    sender.set_pairing_handler(confirm.clone());
+    receiver.set_pairing_handler(confirm);
+    let port = listener.local_addr().unwrap().port();
+
+    let receive = spawn_receive(&receiver, listener.clone(), &dir);
+    let peer = sender.pair("127.0.0.1", port).await.unwrap();
+    assert_eq!(peer.device_id, receiver.local_peer().device_id);
+    assert!(matches!(
+      receive.await.unwrap(),
+      Ok(ReceiveOutcome::Paired(peer)) if peer.device_id == sender.local_peer().device_id
+    ));
+    assert!(
+      sender
+        .trust_store()
+        .is_trusted(&identity(receiver.local_peer()))
+        .await
+    );
+    assert!(
+      receiver
+        .trust_store()
+        .is_trusted(&identity(sender.local_peer()))
+        .await
+    );
+
+    // 配对后可以直接传输
+    let receive = spawn_receive(&receiver, listener.clone(), &dir);
+    send_data(&sender, &listener, b"paired", None)
+      .await
+      .unwrap();
+    assert!(matches!(
+      receive.await.unwrap(),
+      Ok(ReceiveOutcome::File(_))
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn declined_transfer_is_recorded() {
+    let dir = test_dir("declined");
+    let (sender, mut receiver, listener) = paired_transfers().await;
+    receiver.set_offer_handler(Arc::new(|_| {
+      Box::pin(async { TransferDecision::Decline("busy".to_string()) })
+    }));
+    let receive = spawn_receive(&receiver, listener.clone(), &dir);
+
+    assert!(send_data(&sender, &listener, b"data", None).await.is_err());
+    assert!(matches!(
+      receive.await.unwrap(),
+      Ok(ReceiveOutcome::Declined(_))
+    ));
+    for history in [sender.history(), receiver.history()] {
+      let records = history.query(&HistoryQuery::default()).await;
+      assert_eq!(records.len(), 1);
+      assert_eq!(records[0].outcome, TransferOutcome::Declined);
+      assert!(records[0].error.as_ref().unwrap().contains("busy"));
+    }
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn resumes_from_checkpoint() {
+    let dir = test_dir("resume");
+    let (sender, receiver, listener) = paired_transfers().await;
+    let chunk_size = MIN_CHUNK_SIZE as usize;
+    let data = test_data(3 * chunk_size);
+
+    // 上次传输已收到第一个分片
+    let part_path = dir.join("data.bin.part");
+    std::fs::write(&part_path, &data[..chunk_size]).unwrap();
+    let file_hash = sha256_hex(&data);
+    let mut checkpoint = TransferCheckpoint::new(
+      resume::transfer_id(&sender.local_peer().device_id, &file_hash),
+      "data.bin".to_string(),
+      data.len() as u64,
+      file_hash,
+      MIN_CHUNK_SIZE,
+      3,
+      part_path.to_string_lossy().to_string(),
+    );
+    checkpoint.mark_received(0);
+    receiver
+      .resume_store()
+      .save_progress(&checkpoint)
+      .await
+      .unwrap();
+
+    let receive = spawn_receive(&receiver, listener.clone(), &dir);
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let reported = progress.clone();
+    send_data(
+      &sender,
+      &listener,
+      &data,
+      Some(Box::new(move |sent, _| reported.lock().unwrap().push(sent))),
+    )
+    .await
+    .unwrap();
+
+    let Ok(ReceiveOutcome::File(path)) = receive.await.unwrap() else {
+      panic!("expected a received file");
+    };
+    assert_eq!(Path::new(&path), dir.join("data.bin"));
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    // 只发送了缺失的两个分片
+    let chunk_size = chunk_size as u64;
+    assert_eq!(
+      *progress.lock().unwrap(),
+      vec![2 * chunk_size, 3 * chunk_size]
+    );
+    assert!(receiver.resume_store().list().await.is_empty());
+    assert!(!part_path.exists());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn retransmits_corrupted_chunks() {
+    let dir = test_dir("retransmit");
+    let receiver = FileTransfer::new();
+    let (mut client, task) = connect_raw(&receiver, &dir).await;
+    let data = test_data(MIN_CHUNK_SIZE as usize + 10);
+    start_raw_transfer(&mut client, &data, 2).await;
+
+    // 第一个分片的摘要与数据不符
+    let TransferMessage::Chunk {
+      chunk_id,
+      data: chunk,
+      ..
+    } = chunk_message(&data, 0)
+    else {
+      unreachable!();
+    };
+    let corrupted = TransferMessage::Chunk {
+      chunk_id,
+      digest: [0; CHUNK_DIGEST_LEN],
+      data: chunk,
+    };
+    send_message(&mut client, &corrupted).await;
+    send_message(&mut client, &chunk_message(&data, 1)).await;
+    send_message(&mut client, &TransferMessage::Complete).await;
+    assert!(matches!(
+      recv_message(&mut client).await,
+      TransferMessage::Retransmit { missing } if missing == vec![(0, 1)]
+    ));
+
+    send_message(&mut client, &chunk_message(&data, 0)).await;
+    send_message(&mut client, &TransferMessage::Complete).await;
+    assert!(matches!(
+      recv_message(&mut client).await,
+      TransferMessage::Verified
+    ));
+    let Ok(ReceiveOutcome::File(path)) = task.await.unwrap() else {
+      panic!("expected a received file");
+    };
+    assert_eq!(std::fs::read(path).unwrap(), data);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn rejects_file_hash_mismatch() {
+    let dir = test_dir("hash-mismatch");
+    let receiver = FileTransfer::new();
+    let (mut client, task) = connect_raw(&receiver, &dir).await;
+    let data = test_data(100);
+    start_raw_transfer(&mut client, &data, 1).await;
+
+    // 分片摘要正确，但内容与声明的文件哈希不符
+    let mut tampered = data.clone();
+    tampered[0] ^= 0xff;
+    send_message(&mut client, &chunk_message(&tampered, 0)).await;
+    send_message(&mut client, &TransferMessage::Complete).await;
+    assert!(matches!(
+      recv_message(&mut client).await,
+      TransferMessage::Error(_)
+    ));
+    assert!(matches!(task.await.unwrap(), Err(crate::Error::File(_))));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    assert!(receiver.resume_store().list().await.is_empty());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn pauses_and_resumes_sending() {
+    let dir = test_dir("pause");
+    let (mut sender, mut receiver, listener) = paired_transfers().await;
+    let (pause, pause_signal) = watch::channel(true);
+    sender.set_pause_signal(pause_signal);
+    let (paused_tx, mut paused_rx) = tokio::sync::mpsc::unbounded_channel();
+    receiver.set_peer_pause_handler(Arc::new(move |paused| {
+      let _ = paused_tx.send(paused);
+    }));
+
+    let data = test_data(2 * MIN_CHUNK_SIZE as usize);
+    let receive = spawn_receive(&receiver, listener.clone(), &dir);
+    let send = {
+      let data = data.clone();
+      tokio::spawn(async move { send_data(&sender, &listener, &data, None).await })
+    };
+
+    // 发送方在第一个分片之前暂停，接收方收到暂停通知
+    assert_eq!(paused_rx.recv().await, Some(true));
+    assert!(!send.is_finished());
+    pause.send_replace(false);
+    assert_eq!(paused_rx.recv().await, Some(false));
+
+    send.await.unwrap().unwrap();
+    let Ok(ReceiveOutcome::File(path)) = receive.await.unwrap() else {
+      panic!("expected a received file");
+    };
+    assert_eq!(std::fs::read(path).unwrap(), data);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn receives_session() {
+    let source = test_dir("session-source");
+    let dir = test_dir("session");
+    let (sender, receiver, listener) = paired_transfers().await;
+    let top = source.join("album");
+    std::fs::create_dir_all(top.join("nested")).unwrap();
+    std::fs::create_dir_all(top.join("empty")).unwrap();
+    let large = test_data(MIN_CHUNK_SIZE as usize + 1);
+    std::fs::write(top.join("a.txt"), b"first").unwrap();
+    std::fs::write(top.join("nested").join("b.bin"), &large).unwrap();
+
+    let receive = spawn_receive(&receiver, listener.clone(), &dir);
+    let port = listener.local_addr().unwrap().port();
+    sender
+      .send_paths(
+        &[top.to_string_lossy().to_string()],
+        "127.0.0.1",
+        port,
+        None,
+      )
+      .await
+      .unwrap();
+
+    let Ok(ReceiveOutcome::Files(files)) = receive.await.unwrap() else {
+      panic!("expected received files");
+    };
+    assert_eq!(files.len(), 2);
+    let received = dir.join("album");
+    assert_eq!(std::fs::read(received.join("a.txt")).unwrap(), b"first");
+    assert_eq!(
+      std::fs::read(received.join("nested").join("b.bin")).unwrap(),
+      large
+    );
+    assert!(received.join("empty").is_dir());
+
+    let records = receiver.history().query(&HistoryQuery::default()).await;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].name, "album");
+    assert_eq!(records[0].files.len(), 2);
+    assert_eq!(records[0].outcome, TransferOutcome::Completed);
+    let _ = std::fs::remove_dir_all(&source);
+    let _ = std::fs::remove_dir_all(&dir);
   }
 }
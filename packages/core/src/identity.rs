@@ -0,0 +1,274 @@
+//! 设备身份模块
+//!
+//! 每台设备持有一组长期密钥：Ed25519 用于签名，X25519 用于会话握手。
+//! 密钥在首次启动时生成并保存在应用数据目录，设备 ID 由公钥指纹派生，
+//! 因此重启后保持不变，同名主机之间也不会冲突
+
+use crate::Result;
+use crate::crypto::LocalPeer;
+use crate::crypto::key_exchange::PUBLIC_KEY_LEN;
+use crate::persist::{self, to_hex};
+use base64::{Engine as _, engine::general_purpose};
+use ring::digest;
+use ring::rand::SystemRandom;
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::info;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// 身份文件格式版本
+const IDENTITY_FILE_VERSION: u8 = 1;
+
+/// 设备 ID 指纹的域分隔前缀
+const FINGERPRINT_LABEL: &[u8] = b"stationuli/device-id/v1";
+
+/// 设备 ID 指纹长度（字节）
+const FINGERPRINT_LEN: usize = 16;
+
+/// 身份文件内容（私钥均为 Base64）
+#[derive(Serialize, Deserialize)]
+struct IdentityFile {
+  version: u8,
+  /// Ed25519 私钥（PKCS#8）
+  signing_key: String,
+  /// X25519 长期私钥
+  static_secret: String,
+}
+
+/// 设备身份
+pub struct DeviceIdentity {
+  signing_key: Ed25519KeyPair,
+  signing_pkcs8: Vec<u8>,
+  static_secret: StaticSecret,
+  device_id: String,
+}
+
+impl DeviceIdentity {
+  /// 生成新的设备身份
+  pub fn generate() -> Result<Self> {
+    let rng = SystemRandom::new();
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng)
+      .map_err(|_| crate::Error::Crypto("Generate signing key failed".to_string()))?;
+    Self::from_parts(pkcs8.as_ref().to_vec(), StaticSecret::random())
+  }
+
+  /// 从文件加载设备身份，文件不存在时生成并保存
+  ///
+  /// 多个进程同时首次启动时只有一个身份文件会被创建，其余进程加载这个文件
+  pub fn load_or_create(path: impl AsRef<Path>) -> Result<Self> {
+    let path = path.as_ref();
+    if let Some(identity) = Self::read(path)? {
+      return Ok(identity);
+    }
+
+    let identity = Self::generate()?;
+    if !persist::create_private_json(path, &identity.to_file(), "identity")? {
+      // 其他进程已抢先创建了身份文件
+      return Self::load(path);
+    }
+    info!(
+      "Device identity created: {} ({})",
+      identity.device_id,
+      path.display()
+    );
+    Ok(identity)
+  }
+
+  /// 从文件加载设备身份
+  pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    let path = path.as_ref();
+    Self::read(path)?
+      .ok_or_else(|| crate::Error::File(format!("Identity not found: {}", path.display())))
+  }
+
+  /// 从文件加载设备身份，文件不存在时返回 `None`
+  fn read(path: &Path) -> Result<Option<Self>> {
+    let Some(file) = persist::load_json::<IdentityFile>(path, "identity")? else {
+      return Ok(None);
+    };
+    if file.version != IDENTITY_FILE_VERSION {
+      return Err(crate::Error::File(format!(
+        "Unsupported identity version: {}",
+        file.version
+      )));
+    }
+
+    let signing_pkcs8 = Self::decode_key(&file.signing_key)?;
+    let static_secret: [u8; 32] = Self::decode_key(&file.static_secret)?
+      .try_into()
+      .map_err(|_| crate::Error::Crypto("Invalid static secret length".to_string()))?;
+
+    let identity = Self::from_parts(signing_pkcs8, StaticSecret::from(static_secret))?;
+    info!(
+      "Device identity loaded: {} ({})",
+      identity.device_id,
+      path.display()
+    );
+    Ok(Some(identity))
+  }
+
+  /// 保存设备身份到文件
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+    // 私钥文件只允许当前用户读写
+    persist::save_private_json_atomic(path.as_ref(), &self.to_file(), "identity")
+  }
+
+  fn to_file(&self) -> IdentityFile {
+    IdentityFile {
+      version: IDENTITY_FILE_VERSION,
+      signing_key: general_purpose::STANDARD.encode(&self.signing_pkcs8),
+      static_secret: general_purpose::STANDARD.encode(self.static_secret.to_bytes()),
+    }
+  }
+
+  /// 获取设备 ID（公钥指纹）
+  pub fn device_id(&self) -> &str {
+    &self.device_id
+  }
+
+  /// 获取 Ed25519 签名公钥
+  pub fn signing_public_key(&self) -> &[u8] {
+    self.signing_key.public_key().as_ref()
+  }
+
+  /// 获取 Ed25519 签名私钥（PKCS#8），用于签发 QUIC 证书
+  pub(crate) fn signing_pkcs8(&self) -> &[u8] {
+    &self.signing_pkcs8
+  }
+
+  /// 获取 X25519 长期公钥
+  pub fn static_public_key(&self) -> [u8; PUBLIC_KEY_LEN] {
+    PublicKey::from(&self.static_secret).to_bytes()
+  }
+
+  /// 使用 Ed25519 私钥签名
+  pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+    self.signing_key.sign(message).as_ref().to_vec()
+  }
+
+  /// 验证 Ed25519 签名
+  pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<()> {
+    UnparsedPublicKey::new(&ED25519, public_key)
+      .verify(message, signature)
+      .map_err(|_| crate::Error::Crypto("Signature verification failed".to_string()))
+  }
+
+  /// 创建握手使用的本端身份
+  pub fn local_peer(&self, device_name: String, device_type: String) -> LocalPeer {
+    LocalPeer::new(
+      self.device_id.clone(),
+      device_name,
+      device_type,
+      self.static_secret.clone(),
+    )
+  }
+
+  fn from_parts(signing_pkcs8: Vec<u8>, static_secret: StaticSecret) -> Result<Self> {
+    let signing_key = Ed25519KeyPair::from_pkcs8(&signing_pkcs8)
+      .map_err(|_| crate::Error::Crypto("Invalid signing key".to_string()))?;
+    let device_id = fingerprint(
+      signing_key.public_key().as_ref(),
+      PublicKey::from(&static_secret).as_bytes(),
+    );
+    Ok(Self {
+      signing_key,
+      signing_pkcs8,
+      static_secret,
+      device_id,
+    })
+  }
+
+  fn decode_key(encoded: &str) -> Result<Vec<u8>> {
+    general_purpose::STANDARD
+      .decode(encoded)
+      .map_err(|e| crate::Error::Crypto(format!("Decode key failed: {}", e)))
+  }
+}
+
+/// 设备 ID 是否符合 `fingerprint` 生成的格式
+///
+/// 手动添加的设备等没有经过身份派生的 ID 不符合该格式
+pub fn is_fingerprint(device_id: &str) -> bool {
+  device_id.strip_prefix("device-").is_some_and(|hex| {
+    hex.len() == FINGERPRINT_LEN * 2 && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+  })
+}
+
+/// 由签名公钥和长期公钥计算设备 ID 指纹
+pub fn fingerprint(signing_public_key: &[u8], static_public_key: &[u8]) -> String {
+  let mut ctx = digest::Context::new(&digest::SHA256);
+  ctx.update(FINGERPRINT_LABEL);
+  ctx.update(signing_public_key);
+  ctx.update(static_public_key);
+  let hash = ctx.finish();
+  format!("device-{}", to_hex(&hash.as_ref()[..FINGERPRINT_LEN]))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn test_path(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+      "stationuli-identity-{}-{}",
+      name,
+      std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir.join("identity.json")
+  }
+
+  #[test]
+  fn load_or_create_keeps_identity() {
+    let path = test_path("reload");
+    let created = DeviceIdentity::load_or_create(&path).unwrap();
+    let loaded = DeviceIdentity::load_or_create(&path).unwrap();
+    assert_eq!(created.device_id(), loaded.device_id());
+    assert_eq!(created.static_public_key(), loaded.static_public_key());
+    assert_eq!(
+      created.device_id(),
+      fingerprint(created.signing_public_key(), &created.static_public_key())
+    );
+
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+      assert_eq!(mode & 0o777, 0o600);
+    }
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+  }
+
+  #[test]
+  fn concurrent_creation_yields_one_identity() {
+    let path = test_path("concurrent");
+    let ids: Vec<String> = std::thread::scope(|scope| {
+      let handles: Vec<_> = (0..8)
+        .map(|_| scope.spawn(|| DeviceIdentity::load_or_create(&path).unwrap().device_id))
+        .collect();
+      handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert!(ids.iter().all(|id| *id == ids[0]));
+    assert_eq!(DeviceIdentity::load(&path).unwrap().device_id, ids[0]);
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+  }
+
+  #[test]
+  fn recognizes_fingerprints() {
+    let identity = DeviceIdentity::generate().unwrap();
+    assert!(is_fingerprint(identity.device_id()));
+    assert!(!is_fingerprint("192.168.1.10:8080"));
+    assert!(!is_fingerprint("device-00"));
+    assert!(!is_fingerprint(&identity.device_id().to_uppercase()));
+    assert!(!is_fingerprint(&format!("{}0", identity.device_id())));
+  }
+
+  #[test]
+  fn signatures_verify() {
+    let identity = DeviceIdentity::generate().unwrap();
+    let signature = identity.sign(b"message");
+    assert!(DeviceIdentity::verify(identity.signing_public_key(), b"message", &signature).is_ok());
+    assert!(DeviceIdentity::verify(identity.signing_public_key(), b"other", &signature).is_err());
+  }
+}
@@ -4,7 +4,9 @@
 
 pub mod crypto;
 pub mod file;
+pub mod identity;
 pub mod p2p;
+mod persist;
 pub mod projection; // 设备投影模块，应用层暂时不使用，等稳定后再使用
 
 /// 核心错误类型
@@ -22,6 +24,10 @@ pub enum Error {
   #[error("Protocol error: {0}")]
   Protocol(String),
 
+  /// 对端违反协议（超大帧、畸形帧、超时未发完一帧等），接收方可据此封禁对端
+  #[error("Protocol violation: {0}")]
+  ProtocolViolation(String),
+
   #[error("Not found: {0}")]
   NotFound(String),
 }
@@ -0,0 +1,304 @@
+//! 网络地址
+//!
+//! 解析 IPv4 / IPv6 地址（IPv6 可带方括号，链路本地地址可带 `%` 作用域），
+//! 绑定同时接受 IPv4 和 IPv6 的双栈端口，以及枚举本机所有可用网卡上的地址（不含 IPv6 链路本地地址）
+
+use crate::Result;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use tracing::warn;
+
+/// TCP 监听队列长度
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// 解析目标地址和端口
+///
+/// 支持 `192.168.1.10`、`fd00::10`、`[fd00::10]`，以及带作用域的链路本地地址
+/// `fe80::1%eth0` / `fe80::1%2`（作用域可以是网卡名称或编号）
+pub fn parse_socket_addr(address: &str, port: u16) -> Result<SocketAddr> {
+  let address = address.trim();
+  if address.is_empty() {
+    return Err(crate::Error::Network(
+      "Invalid address: address cannot be empty".to_string(),
+    ));
+  }
+
+  let host = address
+    .strip_prefix('[')
+    .and_then(|host| host.strip_suffix(']'))
+    .unwrap_or(address);
+  let (host, scope) = match host.split_once('%') {
+    Some((host, scope)) => (host, Some(scope)),
+    None => (host, None),
+  };
+  let ip = host
+    .parse::<IpAddr>()
+    .map_err(|e| crate::Error::Network(format!("Invalid address: '{}' - {}", address, e)))?;
+
+  match (ip, scope) {
+    (IpAddr::V4(ip), None) => Ok(SocketAddr::from((ip, port))),
+    (IpAddr::V4(_), Some(_)) => Err(crate::Error::Network(format!(
+      "Invalid address: '{}' - scope id is only valid for IPv6",
+      address
+    ))),
+    (IpAddr::V6(ip), scope) => {
+      let scope_id = match scope {
+        Some(scope) => scope_id(scope).ok_or_else(|| {
+          crate::Error::Network(format!(
+            "Invalid address: '{}' - unknown interface '{}'",
+            address, scope
+          ))
+        })?,
+        None => 0,
+      };
+      Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)))
+    }
+  }
+}
+
+/// 把 IPv4 映射的 IPv6 地址（双栈端口上收到的 IPv4 连接）还原为 IPv4 地址
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+  match addr {
+    SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+      Some(ip) => SocketAddr::from((ip, v6.port())),
+      None => addr,
+    },
+    SocketAddr::V4(_) => addr,
+  }
+}
+
+/// 地址的文本形式（不含端口），IPv6 地址带有非零作用域时附加 `%作用域`，
+/// 结果可以交给 `parse_socket_addr` 解析回原地址
+pub fn host_string(addr: SocketAddr) -> String {
+  match addr {
+    SocketAddr::V6(v6) if v6.scope_id() != 0 => format!("{}%{}", v6.ip(), v6.scope_id()),
+    _ => addr.ip().to_string(),
+  }
+}
+
+/// 地址和端口的文本形式（IPv6 地址加方括号，与 `SocketAddr` 的显示一致）
+pub fn format_host_port(address: &str, port: u16) -> String {
+  let address = address.trim();
+  if address.contains(':') && !address.starts_with('[') {
+    format!("[{}]:{}", address, port)
+  } else {
+    format!("{}:{}", address, port)
+  }
+}
+
+/// 本机所有可用网卡上的地址，以（网卡名称，地址）的形式返回
+///
+/// 排除回环地址和 IPv6 链路本地地址：链路本地地址的作用域 ID 只在本机有效，其他设备无法据此连接，
+/// 需要时可由对方以 `fe80::1%网卡` 的形式手动指定。IPv4 地址排在前面，第一个地址即为首选地址
+pub fn local_addresses() -> Vec<(String, IpAddr)> {
+  let interfaces = match if_addrs::get_if_addrs() {
+    Ok(interfaces) => interfaces,
+    Err(e) => {
+      warn!("Failed to enumerate network interfaces: {}", e);
+      return Vec::new();
+    }
+  };
+
+  let mut addresses: Vec<(String, IpAddr)> = interfaces
+    .into_iter()
+    .filter(|interface| is_usable(&interface.ip()))
+    .map(|interface| (interface.name.clone(), interface.ip()))
+    .collect();
+  // 同一地址可能出现在多块网卡上，只保留第一块网卡上的记录
+  let mut seen = HashSet::new();
+  addresses.retain(|(_, ip)| seen.insert(*ip));
+  addresses.sort_by_key(|(_, ip)| ip.is_ipv6());
+  addresses
+}
+
+/// 绑定双栈 TCP 端口（系统不支持 IPv6 时只监听 IPv4）
+pub(crate) fn bind_tcp(port: u16) -> Result<std::net::TcpListener> {
+  let bind = |domain: Domain, addr: SocketAddr| -> std::io::Result<std::net::TcpListener> {
+    let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
+    if domain == Domain::IPV6 {
+      socket.set_only_v6(false)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(socket.into())
+  };
+  bind_dual_stack(port, bind)
+}
+
+/// 绑定双栈 UDP 端口（系统不支持 IPv6 时只绑定 IPv4）
+pub(crate) fn bind_udp(port: u16) -> Result<std::net::UdpSocket> {
+  let bind = |domain: Domain, addr: SocketAddr| -> std::io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+    if domain == Domain::IPV6 {
+      socket.set_only_v6(false)?;
+    }
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+  };
+  bind_dual_stack(port, bind)
+}
+
+/// 先尝试绑定 `[::]`，失败时退回 `0.0.0.0`
+fn bind_dual_stack<S>(
+  port: u16,
+  bind: impl Fn(Domain, SocketAddr) -> std::io::Result<S>,
+) -> Result<S> {
+  let v6 = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+  match bind(Domain::IPV6, v6) {
+    Ok(socket) => Ok(socket),
+    Err(v6_error) => {
+      let v4 = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+      bind(Domain::IPV4, v4)
+        .map_err(|e| crate::Error::Network(format!("Bind failed: {} (IPv6: {})", e, v6_error)))
+    }
+  }
+}
+
+/// 是否可以公布给其他设备
+pub(crate) fn is_usable(ip: &IpAddr) -> bool {
+  if ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() {
+    return false;
+  }
+  match ip {
+    IpAddr::V4(_) => true,
+    IpAddr::V6(ip) => !ip.is_unicast_link_local(),
+  }
+}
+
+/// 地址是否与本机某块网卡处于同一子网（可以直接到达）
+pub(crate) fn is_on_link(ip: &IpAddr) -> bool {
+  let Ok(interfaces) = if_addrs::get_if_addrs() else {
+    return false;
+  };
+  interfaces
+    .iter()
+    .any(|interface| match (&interface.addr, ip) {
+      (if_addrs::IfAddr::V4(local), IpAddr::V4(ip)) => {
+        let mask = u32::from(local.netmask);
+        u32::from(local.ip) & mask == u32::from(*ip) & mask
+      }
+      (if_addrs::IfAddr::V6(local), IpAddr::V6(ip)) => {
+        let mask = u128::from(local.netmask);
+        u128::from(local.ip) & mask == u128::from(*ip) & mask
+      }
+      _ => false,
+    })
+}
+
+/// 把作用域（网卡编号或名称）解析为作用域 ID
+fn scope_id(scope: &str) -> Option<u32> {
+  if let Ok(index) = scope.parse::<u32>() {
+    return Some(index);
+  }
+  if_addrs::get_if_addrs()
+    .ok()?
+    .into_iter()
+    .find(|interface| interface.name == scope)
+    .and_then(|interface| interface.index)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_ipv4_and_ipv6() {
+    assert_eq!(
+      parse_socket_addr(" 192.168.1.10 ", 8080).unwrap(),
+      "192.168.1.10:8080".parse().unwrap()
+    );
+    assert_eq!(
+      parse_socket_addr("fd00::10", 8080).unwrap(),
+      "[fd00::10]:8080".parse().unwrap()
+    );
+    assert_eq!(
+      parse_socket_addr("[fd00::10]", 8080).unwrap(),
+      "[fd00::10]:8080".parse().unwrap()
+    );
+  }
+
+  #[test]
+  fn parses_scope_ids() {
+    for address in ["fe80::1%2", "[fe80::1%2]"] {
+      match parse_socket_addr(address, 8080).unwrap() {
+        SocketAddr::V6(v6) => {
+          assert_eq!(*v6.ip(), "fe80::1".parse::<Ipv6Addr>().unwrap());
+          assert_eq!(v6.port(), 8080);
+          assert_eq!(v6.scope_id(), 2);
+        }
+        addr => panic!("unexpected address: {}", addr),
+      }
+    }
+
+    // 作用域也可以是网卡名称
+    let interface = if_addrs::get_if_addrs()
+      .unwrap()
+      .into_iter()
+      .find(|interface| interface.index.is_some());
+    if let Some(interface) = interface {
+      let address = format!("fe80::1%{}", interface.name);
+      match parse_socket_addr(&address, 8080).unwrap() {
+        SocketAddr::V6(v6) => assert_eq!(Some(v6.scope_id()), interface.index),
+        addr => panic!("unexpected address: {}", addr),
+      }
+    }
+  }
+
+  #[test]
+  fn rejects_invalid_addresses() {
+    for address in [
+      "",
+      "   ",
+      "not an address",
+      "192.168.1.10%2",
+      "fe80::1%no-such-interface",
+      "[fe80::1",
+      "192.168.1.10:8080",
+    ] {
+      assert!(
+        parse_socket_addr(address, 8080).is_err(),
+        "{:?} should be rejected",
+        address
+      );
+    }
+  }
+
+  #[test]
+  fn host_string_round_trips() {
+    for address in ["192.168.1.10", "fd00::10", "fe80::1%3"] {
+      let addr = parse_socket_addr(address, 8080).unwrap();
+      assert_eq!(host_string(addr), address);
+      assert_eq!(parse_socket_addr(&host_string(addr), 8080).unwrap(), addr);
+    }
+  }
+
+  #[test]
+  fn canonicalizes_ipv4_mapped_addresses() {
+    let mapped: SocketAddr = "[::ffff:192.168.1.10]:8080".parse().unwrap();
+    assert_eq!(canonical(mapped), "192.168.1.10:8080".parse().unwrap());
+    let v6: SocketAddr = "[fd00::10]:8080".parse().unwrap();
+    assert_eq!(canonical(v6), v6);
+  }
+
+  #[test]
+  fn lists_addresses_with_interface_names() {
+    let addresses = local_addresses();
+    for (interface, ip) in &addresses {
+      assert!(!interface.is_empty());
+      assert!(is_usable(ip), "{} on {} should be usable", ip, interface);
+    }
+    // IPv4 地址排在前面
+    assert!(addresses.is_sorted_by_key(|(_, ip)| ip.is_ipv6()));
+  }
+
+  #[test]
+  fn formats_host_and_port() {
+    assert_eq!(format_host_port("192.168.1.10", 80), "192.168.1.10:80");
+    assert_eq!(format_host_port("fd00::10", 80), "[fd00::10]:80");
+    assert_eq!(format_host_port("[fd00::10]", 80), "[fd00::10]:80");
+  }
+}
@@ -0,0 +1,243 @@
+//! 入站连接的来源限制
+//!
+//! 按来源 IP 统计并发连接数，并暂时封禁违反协议的对端。传输服务在处理每个入站连接前占用名额，
+//! QUIC 传输在 TLS 握手前也按同一份记录检查来源，被封禁的对端无法再建立新连接
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// 默认的每个来源 IP 的并发连接数上限
+pub const DEFAULT_MAX_CONNECTIONS_PER_IP: usize = 8;
+
+/// 违反协议的对端的默认封禁时长
+pub const DEFAULT_BLOCK_DURATION: Duration = Duration::from_secs(10 * 60);
+
+/// 入站连接的来源限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerLimits {
+  /// 每个来源 IP 的并发连接数上限，超过时新连接直接关闭
+  pub max_connections_per_ip: usize,
+  /// 违反协议的对端的封禁时长
+  pub block_duration: Duration,
+}
+
+impl Default for ServerLimits {
+  fn default() -> Self {
+    Self {
+      max_connections_per_ip: DEFAULT_MAX_CONNECTIONS_PER_IP,
+      block_duration: DEFAULT_BLOCK_DURATION,
+    }
+  }
+}
+
+/// 名额的种类：传输层连接（QUIC 连接）和交给处理函数的连接（TCP 连接或 QUIC 流）分开计数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SlotKind {
+  Transport,
+  Handler,
+}
+
+/// 按来源 IP 记录的连接状态
+#[derive(Default)]
+struct PeerTable {
+  /// (名额种类, 来源 IP) -> 正在使用的名额数
+  active: HashMap<(SlotKind, IpAddr), usize>,
+  /// 来源 IP -> 封禁到期时间
+  blocked: HashMap<IpAddr, Instant>,
+}
+
+impl PeerTable {
+  /// 来源 IP 是否仍在封禁中（已到期的封禁会被移除）
+  fn is_blocked(&mut self, ip: IpAddr) -> bool {
+    match self.blocked.get(&ip) {
+      Some(&until) if Instant::now() < until => true,
+      Some(_) => {
+        self.blocked.remove(&ip);
+        false
+      }
+      None => false,
+    }
+  }
+}
+
+/// 入站连接的来源限制器，克隆后共享同一份记录
+#[derive(Clone, Default)]
+pub struct PeerLimiter {
+  limits: ServerLimits,
+  peers: Arc<Mutex<PeerTable>>,
+}
+
+impl PeerLimiter {
+  /// 创建限制器
+  pub fn new(limits: ServerLimits) -> Self {
+    Self {
+      limits,
+      peers: Arc::new(Mutex::new(PeerTable::default())),
+    }
+  }
+
+  /// 来源限制
+  pub fn limits(&self) -> ServerLimits {
+    self.limits
+  }
+
+  /// 为交给处理函数的连接占用名额，被封禁或已达上限时返回 `None`
+  pub fn admit(&self, ip: IpAddr) -> Option<ConnectionSlot> {
+    self.acquire(SlotKind::Handler, ip)
+  }
+
+  /// 为传输层连接（例如 QUIC 连接，其上可以打开多个流）占用名额，被封禁或已达上限时返回 `None`
+  pub fn admit_connection(&self, ip: IpAddr) -> Option<ConnectionSlot> {
+    self.acquire(SlotKind::Transport, ip)
+  }
+
+  /// 来源 IP 是否被封禁
+  pub fn is_blocked(&self, ip: IpAddr) -> bool {
+    self.peers.lock().unwrap().is_blocked(ip.to_canonical())
+  }
+
+  /// 封禁来源 IP
+  pub fn block(&self, ip: IpAddr) {
+    self.peers.lock().unwrap().blocked.insert(
+      ip.to_canonical(),
+      Instant::now() + self.limits.block_duration,
+    );
+  }
+
+  fn acquire(&self, kind: SlotKind, ip: IpAddr) -> Option<ConnectionSlot> {
+    // 双栈套接字上的 IPv4 来源以映射地址出现，统一后才能与 IPv4 记录对应
+    let ip = ip.to_canonical();
+    let mut table = self.peers.lock().unwrap();
+    if table.is_blocked(ip) {
+      warn!("Rejecting connection from blocked peer {}", ip);
+      return None;
+    }
+
+    let active = table.active.entry((kind, ip)).or_default();
+    if *active >= self.limits.max_connections_per_ip {
+      warn!(
+        "Rejecting connection from {}: {} connections already active",
+        ip, active
+      );
+      return None;
+    }
+    *active += 1;
+    Some(ConnectionSlot {
+      limiter: self.clone(),
+      kind,
+      ip,
+    })
+  }
+}
+
+/// 占用的连接名额，连接结束（包括被中止）时归还
+pub struct ConnectionSlot {
+  limiter: PeerLimiter,
+  kind: SlotKind,
+  ip: IpAddr,
+}
+
+impl ConnectionSlot {
+  /// 来源 IP
+  pub fn ip(&self) -> IpAddr {
+    self.ip
+  }
+
+  /// 来源 IP 是否已被封禁
+  pub fn is_blocked(&self) -> bool {
+    self.limiter.is_blocked(self.ip)
+  }
+
+  /// 封禁该来源 IP
+  pub fn block(&self) {
+    self.limiter.block(self.ip);
+  }
+}
+
+impl Drop for ConnectionSlot {
+  fn drop(&mut self) {
+    let mut table = self.limiter.peers.lock().unwrap();
+    let key = (self.kind, self.ip);
+    if let Some(active) = table.active.get_mut(&key) {
+      *active -= 1;
+      if *active == 0 {
+        table.active.remove(&key);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::net::{Ipv4Addr, Ipv6Addr};
+
+  const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+
+  fn limiter(max_connections_per_ip: usize) -> PeerLimiter {
+    PeerLimiter::new(ServerLimits {
+      max_connections_per_ip,
+      block_duration: Duration::from_secs(60),
+    })
+  }
+
+  #[test]
+  fn limits_concurrent_connections_per_ip() {
+    let limiter = limiter(2);
+    let first = limiter.admit(PEER).unwrap();
+    let _second = limiter.admit(PEER).unwrap();
+    assert!(limiter.admit(PEER).is_none());
+    // 其他来源不受影响
+    assert!(limiter.admit(IpAddr::V4(Ipv4Addr::LOCALHOST)).is_some());
+
+    drop(first);
+    assert!(limiter.admit(PEER).is_some());
+  }
+
+  #[test]
+  fn counts_transport_connections_separately() {
+    let limiter = limiter(1);
+    let _connection = limiter.admit_connection(PEER).unwrap();
+    assert!(limiter.admit_connection(PEER).is_none());
+    // 连接上的流仍可占用处理名额
+    assert!(limiter.admit(PEER).is_some());
+  }
+
+  #[test]
+  fn blocked_peers_are_rejected() {
+    let limiter = limiter(4);
+    let slot = limiter.admit(PEER).unwrap();
+    slot.block();
+    drop(slot);
+
+    assert!(limiter.is_blocked(PEER));
+    assert!(limiter.admit(PEER).is_none());
+    assert!(limiter.admit_connection(PEER).is_none());
+  }
+
+  #[test]
+  fn block_expires() {
+    let limiter = PeerLimiter::new(ServerLimits {
+      max_connections_per_ip: 4,
+      block_duration: Duration::ZERO,
+    });
+    limiter.block(PEER);
+    assert!(!limiter.is_blocked(PEER));
+    assert!(limiter.admit(PEER).is_some());
+  }
+
+  #[test]
+  fn mapped_ipv4_addresses_share_the_record() {
+    let limiter = limiter(1);
+    let mapped = IpAddr::V6(Ipv4Addr::new(192, 168, 1, 20).to_ipv6_mapped());
+    let _slot = limiter.admit(mapped).unwrap();
+    assert!(limiter.admit(PEER).is_none());
+
+    limiter.block(mapped);
+    assert!(limiter.is_blocked(PEER));
+    assert!(!limiter.is_blocked(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+  }
+}
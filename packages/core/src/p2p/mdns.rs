@@ -1,14 +1,78 @@
-//! mDNS 设备发现实现
+//! 设备发现实现（mDNS，以及用于屏蔽组播网络的 UDP 广播）
 
 use crate::Result;
+use crate::p2p::addr;
+use crate::p2p::negotiate::{self, TransportKind};
 use libmdns::Responder;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::{Arc, Mutex};
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::{RwLock, broadcast};
 use tokio::task::JoinHandle;
-use tracing::info;
+use tracing::{info, warn};
+
+/// mDNS 服务类型
+pub const SERVICE_TYPE: &str = "_stationuli._tcp";
+
+/// 发现协议版本（写入 TXT 记录，版本不一致的设备会被忽略）
+pub const DISCOVERY_PROTOCOL_VERSION: u32 = 1;
+
+/// 单条 TXT 记录的最大长度（字节）
+const MAX_TXT_ENTRY_LEN: usize = 255;
+
+/// 设备事件通道容量（订阅者落后超过该数量时会丢失最旧的事件）
+const DEVICE_EVENT_CAPACITY: usize = 64;
+
+/// UDP 广播发现的默认端口
+pub const DEFAULT_BROADCAST_PORT: u16 = 47820;
+
+/// 广播信标发送间隔
+const BEACON_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 超过该时间未收到信标的设备视为离线
+const BEACON_TTL: Duration = Duration::from_secs(15);
+
+/// 单个信标报文的最大长度（字节）
+const MAX_BEACON_LEN: usize = 2048;
+
+/// 接收信标出错后的首次等待时间，连续出错时逐次加倍，最长为 `BEACON_INTERVAL`
+const RECV_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// 设备发现方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscoveryMode {
+  /// 仅使用 mDNS
+  Mdns,
+  /// 仅使用 UDP 广播（适用于屏蔽组播的网络）
+  Broadcast,
+  /// 同时使用 mDNS 和 UDP 广播
+  #[default]
+  Both,
+}
+
+impl DiscoveryMode {
+  fn uses_mdns(self) -> bool {
+    matches!(self, DiscoveryMode::Mdns | DiscoveryMode::Both)
+  }
+
+  fn uses_broadcast(self) -> bool {
+    matches!(self, DiscoveryMode::Broadcast | DiscoveryMode::Both)
+  }
+}
+
+/// UDP 广播信标
+#[derive(Debug, Serialize, Deserialize)]
+struct Beacon {
+  version: u32,
+  device: DeviceInfo,
+  /// 设备即将下线
+  leaving: bool,
+}
 
 /// 设备信息
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -18,37 +82,170 @@ pub struct DeviceInfo {
   pub address: String,
   pub port: u16,
   pub device_type: String, // "desktop" or "mobile"
+  /// 支持的传输方式（为空表示未知，连接时先尝试 QUIC 再回退到 TCP）
+  #[serde(default)]
+  pub transports: Vec<TransportKind>,
+  /// 设备所有可用网卡上的地址（`address` 为其中的首选地址，为空表示只有 `address`）
+  ///
+  /// 不含 IPv6 链路本地地址，其作用域只在公布地址的设备上有效
+  #[serde(default)]
+  pub addresses: Vec<InterfaceAddress>,
+}
+
+/// 设备某块网卡上的地址
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InterfaceAddress {
+  /// 网卡名称（为空表示未知，例如从报文来源得到的地址）
+  #[serde(default)]
+  pub interface: String,
+  pub address: String,
+}
+
+impl InterfaceAddress {
+  /// 网卡未知的地址
+  pub fn unnamed(address: String) -> Self {
+    Self {
+      interface: String::new(),
+      address,
+    }
+  }
+
+  /// TXT 记录中的形式：`网卡=地址`，网卡未知时只有地址
+  fn to_txt(&self) -> String {
+    if self.interface.is_empty() {
+      self.address.clone()
+    } else {
+      format!("{}={}", self.interface, self.address)
+    }
+  }
+
+  /// 解析 TXT 记录中的一项（地址中不会出现 `=`，从右侧拆分以容忍网卡名称中的 `=`）
+  fn parse_txt(entry: &str) -> Option<(String, IpAddr)> {
+    let (interface, address) = entry.rsplit_once('=').unwrap_or(("", entry));
+    let ip = address.trim().parse().ok()?;
+    Some((interface.trim().to_string(), ip))
+  }
+}
+
+/// 设备在线状态事件
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DeviceEvent {
+  /// 新设备出现
+  Appeared(DeviceInfo),
+  /// 已知设备的信息发生变化
+  Updated(DeviceInfo),
+  /// 设备离线或被移除（携带最后一次已知的信息）
+  Lost(DeviceInfo),
+}
+
+/// 设备列表，所有变更都会广播对应的设备事件
+#[derive(Clone)]
+struct DeviceTable {
+  devices: Arc<RwLock<HashMap<String, DeviceInfo>>>,
+  events: broadcast::Sender<DeviceEvent>,
+}
+
+impl DeviceTable {
+  fn new() -> Self {
+    let (events, _) = broadcast::channel(DEVICE_EVENT_CAPACITY);
+    Self {
+      devices: Arc::new(RwLock::new(HashMap::new())),
+      events,
+    }
+  }
+
+  /// 插入或更新设备，信息未变化时不发送事件
+  async fn upsert(&self, device: DeviceInfo) {
+    let previous = self
+      .devices
+      .write()
+      .await
+      .insert(device.id.clone(), device.clone());
+    let event = match previous {
+      None => DeviceEvent::Appeared(device),
+      Some(previous) if previous != device => DeviceEvent::Updated(device),
+      Some(_) => return,
+    };
+    // 没有订阅者时发送会失败，忽略即可
+    let _ = self.events.send(event);
+  }
+
+  /// 移除设备，返回被移除的设备信息
+  async fn remove(&self, device_id: &str) -> Option<DeviceInfo> {
+    let removed = self.devices.write().await.remove(device_id)?;
+    let _ = self.events.send(DeviceEvent::Lost(removed.clone()));
+    Some(removed)
+  }
+
+  /// 清空设备列表，返回被移除的设备数量
+  async fn clear(&self) -> usize {
+    let removed: Vec<DeviceInfo> = self.devices.write().await.drain().map(|(_, d)| d).collect();
+    let count = removed.len();
+    for device in removed {
+      let _ = self.events.send(DeviceEvent::Lost(device));
+    }
+    count
+  }
+
+  async fn get(&self, device_id: &str) -> Option<DeviceInfo> {
+    self.devices.read().await.get(device_id).cloned()
+  }
+
+  async fn list(&self) -> Vec<DeviceInfo> {
+    self.devices.read().await.values().cloned().collect()
+  }
 }
 
 /// mDNS 设备发现
 pub struct MdnsDiscovery {
-  devices: Arc<RwLock<HashMap<String, DeviceInfo>>>,
+  devices: DeviceTable,
   port: u16,
   device_id: String,
   device_name: String,
   device_type: String, // "desktop" or "mobile"
+  transports: Vec<TransportKind>,
   service_registration: Option<libmdns::Service>,
   discovery_handle: Option<JoinHandle<()>>,
   responder: Option<Arc<Mutex<Responder>>>,
   broadcast_handle: Option<JoinHandle<()>>,
   local_ip: Arc<RwLock<Option<String>>>, // 记录实际使用的本地 IP 地址
+  local_addresses: Vec<InterfaceAddress>, // 本机所有可用网卡上的地址，首个为首选地址
+  browser: Option<ServiceDaemon>,
+  // 通过 mDNS 发现的设备（服务实例全名 -> 设备 ID），手动添加的设备不在其中，不会过期
+  discovered: Arc<RwLock<HashMap<String, String>>>,
+  discovery_mode: DiscoveryMode,
+  broadcast_port: u16,
+  // 通过 UDP 广播发现的设备（设备 ID -> 最近一次收到信标的时间）
+  beacon_seen: Arc<RwLock<HashMap<String, Instant>>>,
+  // 手动添加的设备 ID，mDNS 记录和广播信标不会覆盖其信息，也不会使其过期
+  manual: Arc<RwLock<HashSet<String>>>,
 }
 
 impl MdnsDiscovery {
-  pub fn new(port: u16) -> Self {
-    let device_id = Self::generate_device_id();
+  /// 创建设备发现服务
+  ///
+  /// `device_id` 应来自持久化的设备身份（见 `identity::DeviceIdentity`）
+  pub fn new(port: u16, device_id: String) -> Self {
     let device_name = Self::get_device_name();
     Self {
-      devices: Arc::new(RwLock::new(HashMap::new())),
+      devices: DeviceTable::new(),
       port,
       device_id,
       device_name,
       device_type: "unknown".to_string(),
+      transports: negotiate::legacy_transports(),
       service_registration: None,
       discovery_handle: None,
       responder: None,
       broadcast_handle: None,
       local_ip: Arc::new(RwLock::new(None)),
+      local_addresses: Vec::new(),
+      browser: None,
+      discovered: Arc::new(RwLock::new(HashMap::new())),
+      discovery_mode: DiscoveryMode::default(),
+      broadcast_port: DEFAULT_BROADCAST_PORT,
+      beacon_seen: Arc::new(RwLock::new(HashMap::new())),
+      manual: Arc::new(RwLock::new(HashSet::new())),
     }
   }
 
@@ -57,7 +254,22 @@ impl MdnsDiscovery {
     self.device_type = device_type;
   }
 
-  /// 开始服务（简化版：只获取本地IP，不启动自动发现）
+  /// 设置公布的传输方式（需在 `start` 之前调用）
+  pub fn set_transports(&mut self, transports: Vec<TransportKind>) {
+    self.transports = transports;
+  }
+
+  /// 设置设备发现方式（需在 `start` 之前调用）
+  pub fn set_discovery_mode(&mut self, mode: DiscoveryMode) {
+    self.discovery_mode = mode;
+  }
+
+  /// 设置 UDP 广播端口（需在 `start` 之前调用，同一网络内的设备需使用相同端口）
+  pub fn set_broadcast_port(&mut self, port: u16) {
+    self.broadcast_port = port;
+  }
+
+  /// 开始服务：获取本地 IP，按发现方式注册 mDNS 服务和/或发送 UDP 广播信标
   pub async fn start(&mut self) -> Result<()> {
     let device_type_upper = self.device_type.to_uppercase();
     info!(
@@ -68,17 +280,50 @@ impl MdnsDiscovery {
       "[{}] Device Type: {}, Port: {}, Device ID: {}, Device Name: {}",
       device_type_upper, self.device_type, self.port, self.device_id, self.device_name
     );
+    info!(
+      "[{}] Discovery mode: {:?}, Broadcast port: {}",
+      device_type_upper, self.discovery_mode, self.broadcast_port
+    );
 
-    // 只获取本地 IP 地址，不启动自动发现
-    let local_ip = Self::get_local_ip_address().unwrap_or_else(|| "0.0.0.0".to_string());
-    info!("[{}] Local IP address: {}", device_type_upper, local_ip);
+    // 枚举本机网卡上的地址，首个地址作为首选地址
+    self.local_addresses = addr::local_addresses()
+      .into_iter()
+      .map(|(interface, ip)| InterfaceAddress {
+        interface,
+        address: ip.to_string(),
+      })
+      .collect();
+    info!(
+      "[{}] Local IP addresses: {:?}",
+      device_type_upper, self.local_addresses
+    );
 
     // 记录本地 IP
-    *self.local_ip.write().await = if local_ip != "0.0.0.0" {
-      Some(local_ip)
-    } else {
-      None
-    };
+    *self.local_ip.write().await = self
+      .local_addresses
+      .first()
+      .map(|address| address.address.clone());
+
+    // 自动发现失败时只记录警告，手动添加设备仍然可用
+    if self.discovery_mode.uses_mdns() {
+      if let Err(e) = self.start_advertising() {
+        warn!(
+          "[{}] mDNS advertising unavailable: {}",
+          device_type_upper, e
+        );
+      }
+      if let Err(e) = self.start_browsing() {
+        warn!("[{}] mDNS browsing unavailable: {}", device_type_upper, e);
+      }
+    }
+    if self.discovery_mode.uses_broadcast()
+      && let Err(e) = self.start_broadcasting()
+    {
+      warn!(
+        "[{}] UDP broadcast discovery unavailable: {}",
+        device_type_upper, e
+      );
+    }
 
     info!(
       "========== [{}] Service started successfully ==========",
@@ -90,13 +335,21 @@ impl MdnsDiscovery {
   /// 手动添加设备（用于测试或手动连接）
   pub async fn add_device(&self, device: DeviceInfo) {
     info!("Manually adding device: {:?}", device);
-    self.devices.write().await.insert(device.id.clone(), device);
+    // 手动添加的设备不随 mDNS 记录或广播信标过期
+    self.manual.write().await.insert(device.id.clone());
+    self
+      .discovered
+      .write()
+      .await
+      .retain(|_, device_id| *device_id != device.id);
+    self.beacon_seen.write().await.remove(&device.id);
+    self.devices.upsert(device).await;
   }
 
   /// 删除设备
   pub async fn remove_device(&self, device_id: &str) -> Result<()> {
-    let mut devices = self.devices.write().await;
-    if devices.remove(device_id).is_some() {
+    self.manual.write().await.remove(device_id);
+    if self.devices.remove(device_id).await.is_some() {
       info!("Device removed: {}", device_id);
       Ok(())
     } else {
@@ -107,12 +360,17 @@ impl MdnsDiscovery {
     }
   }
 
-  /// 更新设备信息
-  pub async fn update_device(&self, device: DeviceInfo) -> Result<()> {
-    let mut devices = self.devices.write().await;
-    if devices.contains_key(&device.id) {
+  /// 更新设备信息（未携带传输方式或地址列表时保留已知的值）
+  pub async fn update_device(&self, mut device: DeviceInfo) -> Result<()> {
+    if let Some(existing) = self.devices.get(&device.id).await {
+      if device.transports.is_empty() {
+        device.transports = existing.transports;
+      }
+      if device.addresses.is_empty() {
+        device.addresses = existing.addresses;
+      }
       info!("Updating device: {:?}", device);
-      devices.insert(device.id.clone(), device);
+      self.devices.upsert(device).await;
       Ok(())
     } else {
       Err(crate::Error::NotFound(format!(
@@ -122,7 +380,7 @@ impl MdnsDiscovery {
     }
   }
 
-  /// 停止服务（简化版）
+  /// 停止服务
   pub async fn stop(&mut self) -> Result<()> {
     let device_type_upper = self.device_type.to_uppercase();
     info!(
@@ -136,7 +394,9 @@ impl MdnsDiscovery {
       device_type_upper
     );
     if let Some(service) = self.service_registration.take() {
+      // 注销时响应器会发送 TTL 为 0 的下线通知，稍等片刻再关闭响应器，让通知发出去
       drop(service);
+      tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
       info!(
         "[{}] ✅ mDNS service registration stopped",
         device_type_upper
@@ -150,6 +410,15 @@ impl MdnsDiscovery {
 
     // 步骤2: 取消发现任务（如果存在）
     info!("[{}] Step 2: Stopping discovery task...", device_type_upper);
+    if let Some(browser) = self.browser.take() {
+      if let Err(e) = browser.shutdown() {
+        warn!(
+          "[{}] mDNS browser shutdown failed: {}",
+          device_type_upper, e
+        );
+      }
+      info!("[{}] ✅ mDNS browser stopped", device_type_upper);
+    }
     if let Some(handle) = self.discovery_handle.take() {
       handle.abort();
       info!("[{}] ✅ Discovery task aborted", device_type_upper);
@@ -161,6 +430,13 @@ impl MdnsDiscovery {
     info!("[{}] Step 3: Stopping broadcast task...", device_type_upper);
     if let Some(handle) = self.broadcast_handle.take() {
       handle.abort();
+      // 通知其他设备本机下线，不必等待信标超时
+      if let Err(e) = self.send_goodbye_beacon().await {
+        warn!(
+          "[{}] Failed to send goodbye beacon: {}",
+          device_type_upper, e
+        );
+      }
       info!("[{}] ✅ Broadcast task aborted", device_type_upper);
     } else {
       info!("[{}] ℹ️  No broadcast task to stop", device_type_upper);
@@ -168,8 +444,10 @@ impl MdnsDiscovery {
 
     // 步骤4: 清理设备列表（使用异步写操作）
     info!("[{}] Step 4: Clearing device list...", device_type_upper);
-    let device_count = self.devices.read().await.len();
-    self.devices.write().await.clear();
+    let device_count = self.devices.clear().await;
+    self.discovered.write().await.clear();
+    self.beacon_seen.write().await.clear();
+    self.manual.write().await.clear();
     info!(
       "[{}] ✅ Device list cleared (removed {} devices)",
       device_type_upper, device_count
@@ -187,6 +465,7 @@ impl MdnsDiscovery {
     // 步骤6: 清理本地 IP 缓存
     info!("[{}] Step 6: Clearing local IP cache...", device_type_upper);
     *self.local_ip.write().await = None;
+    self.local_addresses.clear();
     info!("[{}] ✅ Local IP cache cleared", device_type_upper);
 
     info!(
@@ -198,7 +477,14 @@ impl MdnsDiscovery {
 
   /// 获取发现的设备列表
   pub async fn get_devices(&self) -> Vec<DeviceInfo> {
-    self.devices.read().await.values().cloned().collect()
+    self.devices.list().await
+  }
+
+  /// 订阅设备事件（设备出现、更新、离线）
+  ///
+  /// 自动发现和手动添加、更新、删除设备都会产生事件
+  pub fn subscribe(&self) -> broadcast::Receiver<DeviceEvent> {
+    self.devices.events.subscribe()
   }
 
   /// 获取设备 ID
@@ -206,117 +492,528 @@ impl MdnsDiscovery {
     &self.device_id
   }
 
+  /// 获取设备名称
+  pub fn device_name(&self) -> &str {
+    &self.device_name
+  }
+
+  /// 获取设备类型
+  pub fn device_type(&self) -> &str {
+    &self.device_type
+  }
+
   /// 获取本地 IP 地址（实际可连接的地址）
   pub async fn get_local_ip(&self) -> Option<String> {
     self.local_ip.read().await.clone()
   }
 
-  /// 生成设备 ID（基于主机名和时间戳）
-  fn generate_device_id() -> String {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    use std::time::{SystemTime, UNIX_EPOCH};
-
-    let hostname = hostname::get()
-      .ok()
-      .and_then(|h| h.to_str().map(|s| s.to_string()))
-      .unwrap_or_else(|| "unknown".to_string());
+  /// 获取本机所有可用网卡上的地址（首个为首选地址）
+  pub fn get_local_addresses(&self) -> &[InterfaceAddress] {
+    &self.local_addresses
+  }
 
-    let timestamp = SystemTime::now()
-      .duration_since(UNIX_EPOCH)
-      .unwrap()
-      .as_secs();
+  /// 注册 mDNS 服务，TXT 记录携带设备信息
+  fn start_advertising(&mut self) -> Result<()> {
+    let responder = Responder::spawn(&tokio::runtime::Handle::current())
+      .map_err(|e| crate::Error::Network(format!("Failed to start mDNS responder: {}", e)))?;
+
+    let txt = self.txt_records();
+    let txt: Vec<&str> = txt.iter().map(String::as_str).collect();
+    // 以设备 ID 作为服务实例名，避免同名主机冲突
+    let service = responder.register(
+      SERVICE_TYPE.to_string(),
+      self.device_id.clone(),
+      self.port,
+      &txt,
+    );
 
-    let mut hasher = DefaultHasher::new();
-    hostname.hash(&mut hasher);
-    timestamp.hash(&mut hasher);
-    format!("device-{:x}", hasher.finish())
+    info!(
+      "mDNS service registered: {}.{}.local (port: {})",
+      self.device_id, SERVICE_TYPE, self.port
+    );
+    self.service_registration = Some(service);
+    self.responder = Some(Arc::new(Mutex::new(responder)));
+    Ok(())
   }
 
-  /// 获取设备名称
-  fn get_device_name() -> String {
-    hostname::get()
-      .ok()
-      .and_then(|h| h.to_str().map(|s| s.to_string()))
-      .unwrap_or_else(|| "stationuli-device".to_string())
-  }
+  /// 浏览局域网内的 Stationuli 服务，维护发现的设备列表
+  ///
+  /// 记录按 TTL 过期，过期或收到下线通知时设备会从列表中移除
+  fn start_browsing(&mut self) -> Result<()> {
+    let browser = ServiceDaemon::new()
+      .map_err(|e| crate::Error::Network(format!("Failed to start mDNS browser: {}", e)))?;
+    let receiver = browser
+      .browse(&format!("{}.local.", SERVICE_TYPE))
+      .map_err(|e| crate::Error::Network(format!("Failed to browse mDNS services: {}", e)))?;
+
+    let devices = self.devices.clone();
+    let discovered = self.discovered.clone();
+    let beacon_seen = self.beacon_seen.clone();
+    let manual = self.manual.clone();
+    let own_device_id = self.device_id.clone();
+
+    let handle = tokio::spawn(async move {
+      while let Ok(event) = receiver.recv_async().await {
+        match event {
+          ServiceEvent::ServiceResolved(service) => {
+            let Some(device) = Self::device_from_service(&service) else {
+              continue;
+            };
+            if device.id == own_device_id || manual.read().await.contains(&device.id) {
+              continue;
+            }
 
-  /// 获取本地 IP 地址
-  fn get_local_ip_address() -> Option<String> {
-    use std::net::UdpSocket;
-
-    // 通过连接到一个远程地址来获取本地 IP
-    // 这不会实际发送数据，只是用来确定本地 IP
-    match UdpSocket::bind("0.0.0.0:0") {
-      Ok(socket) => {
-        // 连接到 Google DNS（不会实际连接，只是用来确定路由）
-        if socket.connect("8.8.8.8:80").is_ok() {
-          if let Ok(local_addr) = socket.local_addr() {
-            let ip = local_addr.ip();
-            // 在Android模拟器中，如果获取到的是10.0.2.15，尝试获取Mac的实际IP
-            if ip.to_string() == "10.0.2.15" {
-              if let Some(mac_ip) = Self::get_mac_ip_from_simulator() {
-                return Some(mac_ip.to_string());
+            info!("Device discovered via mDNS: {:?}", device);
+            discovered
+              .write()
+              .await
+              .insert(service.get_fullname().to_string(), device.id.clone());
+            devices.upsert(device).await;
+          }
+          ServiceEvent::ServiceRemoved(_, fullname) => {
+            if let Some(device_id) = discovered.write().await.remove(&fullname) {
+              info!("Device lost via mDNS: {}", device_id);
+              // 仍能收到广播信标的设备保留在列表中
+              if !beacon_seen.read().await.contains_key(&device_id) {
+                devices.remove(&device_id).await;
               }
             }
-            return Some(ip.to_string());
           }
+          _ => {}
         }
       }
-      Err(_) => {}
-    }
-    None
+    });
+
+    self.browser = Some(browser);
+    self.discovery_handle = Some(handle);
+    Ok(())
   }
 
-  /// 在Android模拟器中获取Mac的实际IP地址
-  /// 模拟器的网络配置：
-  /// - 10.0.2.15: 模拟器内部IP
-  /// - 10.0.2.2: Mac的网关IP（在模拟器中可以访问Mac）
-  ///
-  /// 注意：在Android模拟器中，无法直接获取Mac的实际局域网IP
-  /// 这个方法主要用于桌面端，在移动端会返回None
-  /// 移动端应该依赖桌面端在广播消息中提供的IP地址
-  fn get_mac_ip_from_simulator() -> Option<Ipv4Addr> {
-    #[cfg(target_os = "android")]
-    {
-      // 在Android上（包括模拟器），无法直接获取Mac的实际IP
-      // 移动端应该依赖桌面端在广播消息中提供的IP地址
-      return None;
-    }
+  /// 启动 UDP 广播发现：定期广播本机信标，监听其他设备的信标并合并到设备列表
+  fn start_broadcasting(&mut self) -> Result<()> {
+    let socket = Self::bind_broadcast_socket(self.broadcast_port)?;
+    let target = SocketAddr::from((Ipv4Addr::BROADCAST, self.broadcast_port));
+
+    let devices = self.devices.clone();
+    let discovered = self.discovered.clone();
+    let beacon_seen = self.beacon_seen.clone();
+    let manual = self.manual.clone();
+    let local_ip = self.local_ip.clone();
+    let mut local_device = self.local_device_info();
+
+    let handle = tokio::spawn(async move {
+      let mut ticker = tokio::time::interval(BEACON_INTERVAL);
+      let mut buf = vec![0u8; MAX_BEACON_LEN];
+      let mut recv_errors = 0u32;
+
+      loop {
+        tokio::select! {
+          _ = ticker.tick() => {
+            // 广播本机信标
+            local_device.address = local_ip
+              .read()
+              .await
+              .clone()
+              .unwrap_or_else(|| "0.0.0.0".to_string());
+            if let Err(e) = Self::send_beacon(&socket, target, &local_device, false).await {
+              warn!("Failed to send beacon: {}", e);
+            }
 
-    #[cfg(not(target_os = "android"))]
-    {
-      // 在桌面端（Mac/Windows/Linux），尝试获取所有网络接口
-      // 使用标准库的方法枚举网络接口
-      use std::net::{IpAddr, UdpSocket};
-
-      // 方法1: 尝试通过连接到外部地址获取实际IP
-      // 尝试连接到多个可能的网关地址来获取本地IP
-      let test_addresses = vec!["8.8.8.8:80", "1.1.1.1:80"];
-      for addr in test_addresses {
-        if let Ok(socket) = UdpSocket::bind("0.0.0.0:0") {
-          if socket.connect(addr).is_ok() {
-            if let Ok(local_addr) = socket.local_addr() {
-              let ip = local_addr.ip();
-              if let IpAddr::V4(ipv4) = ip {
-                // 跳过模拟器网络和特殊地址
-                if !ipv4.octets().starts_with(&[10, 0, 2])
-                  && !ipv4.is_loopback()
-                  && !ipv4.is_unspecified()
-                {
-                  return Some(ipv4);
-                }
+            // 移除超时未收到信标的设备（mDNS 仍能发现的设备保留）
+            let now = Instant::now();
+            let mut expired = Vec::new();
+            beacon_seen.write().await.retain(|device_id, seen| {
+              let alive = now.duration_since(*seen) < BEACON_TTL;
+              if !alive {
+                expired.push(device_id.clone());
+              }
+              alive
+            });
+            for device_id in expired {
+              let still_on_mdns = discovered.read().await.values().any(|id| *id == device_id);
+              if !still_on_mdns {
+                info!("Device lost via broadcast: {}", device_id);
+                devices.remove(&device_id).await;
+              }
+            }
+          }
+          result = socket.recv_from(&mut buf) => {
+            let (len, source) = match result {
+              Ok(received) => {
+                recv_errors = 0;
+                received
               }
+              Err(e) => {
+                // 套接字持续出错时逐次延长等待，避免空转占满 CPU
+                let backoff = RECV_ERROR_BACKOFF
+                  .saturating_mul(1 << recv_errors.min(16))
+                  .min(BEACON_INTERVAL);
+                recv_errors = recv_errors.saturating_add(1);
+                warn!(
+                  "Failed to receive beacon ({} consecutive errors, retrying in {:?}): {}",
+                  recv_errors, backoff, e
+                );
+                tokio::time::sleep(backoff).await;
+                continue;
+              }
+            };
+            let Ok(beacon) = serde_json::from_slice::<Beacon>(&buf[..len]) else {
+              continue;
+            };
+            // 手动添加的设备以用户填写的信息为准，信标既不更新也不移除它
+            if beacon.version != DISCOVERY_PROTOCOL_VERSION
+              || beacon.device.id == local_device.id
+              || manual.read().await.contains(&beacon.device.id)
+            {
+              continue;
+            }
+
+            let device_id = beacon.device.id.clone();
+            if beacon.leaving {
+              if beacon_seen.write().await.remove(&device_id).is_some() {
+                info!("Device left via broadcast: {}", device_id);
+                devices.remove(&device_id).await;
+              }
+              continue;
+            }
+
+            let is_new = beacon_seen
+              .write()
+              .await
+              .insert(device_id.clone(), Instant::now())
+              .is_none();
+            // mDNS 已发现的设备只刷新存活时间，避免两种方式报告的地址不同导致反复更新
+            if discovered.read().await.values().any(|id| *id == device_id) {
+              continue;
             }
+
+            // 以报文来源地址为准，设备自报的地址可能是 0.0.0.0 或其他网卡的地址
+            let mut device = beacon.device;
+            device.address = addr::host_string(addr::canonical(source));
+            if !device
+              .addresses
+              .iter()
+              .any(|address| address.address == device.address)
+            {
+              device
+                .addresses
+                .insert(0, InterfaceAddress::unnamed(device.address.clone()));
+            }
+            if is_new {
+              info!("Device discovered via broadcast: {:?}", device);
+            }
+            devices.upsert(device).await;
           }
         }
       }
+    });
+
+    info!(
+      "UDP broadcast discovery started (port: {})",
+      self.broadcast_port
+    );
+    self.broadcast_handle = Some(handle);
+    Ok(())
+  }
+
+  /// 发送下线信标
+  async fn send_goodbye_beacon(&self) -> Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0")
+      .await
+      .map_err(|e| crate::Error::Network(format!("Failed to bind UDP socket: {}", e)))?;
+    socket
+      .set_broadcast(true)
+      .map_err(|e| crate::Error::Network(format!("Failed to enable broadcast: {}", e)))?;
+    let target = SocketAddr::from((Ipv4Addr::BROADCAST, self.broadcast_port));
+    Self::send_beacon(&socket, target, &self.local_device_info(), true).await
+  }
+
+  async fn send_beacon(
+    socket: &UdpSocket,
+    target: SocketAddr,
+    device: &DeviceInfo,
+    leaving: bool,
+  ) -> Result<()> {
+    let beacon = Beacon {
+      version: DISCOVERY_PROTOCOL_VERSION,
+      device: device.clone(),
+      leaving,
+    };
+    let data = serde_json::to_vec(&beacon)
+      .map_err(|e| crate::Error::Protocol(format!("Serialize beacon failed: {}", e)))?;
+    socket
+      .send_to(&data, target)
+      .await
+      .map_err(|e| crate::Error::Network(format!("Failed to send beacon: {}", e)))?;
+    Ok(())
+  }
+
+  /// 绑定广播端口（允许同一主机上的多个实例共用端口）
+  fn bind_broadcast_socket(port: u16) -> Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let to_error = |e: std::io::Error| {
+      crate::Error::Network(format!("Failed to bind broadcast port {}: {}", port, e))
+    };
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).map_err(to_error)?;
+    socket.set_reuse_address(true).map_err(to_error)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true).map_err(to_error)?;
+    socket.set_broadcast(true).map_err(to_error)?;
+    socket.set_nonblocking(true).map_err(to_error)?;
+    socket
+      .bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())
+      .map_err(to_error)?;
+    UdpSocket::from_std(socket.into()).map_err(to_error)
+  }
+
+  /// 本机设备信息（首选地址由调用方填写）
+  fn local_device_info(&self) -> DeviceInfo {
+    DeviceInfo {
+      id: self.device_id.clone(),
+      name: self.device_name.clone(),
+      address: "0.0.0.0".to_string(),
+      port: self.port,
+      device_type: self.device_type.clone(),
+      transports: self.transports.clone(),
+      addresses: self.local_addresses.clone(),
+    }
+  }
+
+  /// 生成 TXT 记录
+  fn txt_records(&self) -> Vec<String> {
+    let mut records = vec![
+      format!("id={}", self.device_id),
+      format!("device_type={}", self.device_type),
+      format!("port={}", self.port),
+      format!("version={}", DISCOVERY_PROTOCOL_VERSION),
+      format!(
+        "transports={}",
+        self
+          .transports
+          .iter()
+          .map(|transport| transport.as_str())
+          .collect::<Vec<_>>()
+          .join(",")
+      ),
+    ];
+
+    // 地址列表按整个地址截断到单条 TXT 记录的上限
+    let mut addresses = String::from("addresses=");
+    for (i, address) in self.local_addresses.iter().enumerate() {
+      let address = address.to_txt();
+      let separator = if i == 0 { "" } else { "," };
+      if addresses.len() + separator.len() + address.len() > MAX_TXT_ENTRY_LEN {
+        break;
+      }
+      addresses.push_str(separator);
+      addresses.push_str(&address);
+    }
+    records.push(addresses);
+
+    // 设备名称可能很长，按字符截断到单条 TXT 记录的上限
+    let mut name = String::from("name=");
+    for c in self.device_name.chars() {
+      if name.len() + c.len_utf8() > MAX_TXT_ENTRY_LEN {
+        break;
+      }
+      name.push(c);
+    }
+    records.push(name);
+    records
+  }
 
-      // 方法2: 如果方法1失败，尝试使用if_addrs crate（如果可用）
-      // 注意：这里不直接依赖if_addrs，而是通过条件编译
-      // 如果项目中有if_addrs依赖，可以使用它
-      // 否则返回None，让调用者使用其他方法
+  /// 从解析出的 mDNS 服务构造设备信息
+  fn device_from_service(service: &ServiceInfo) -> Option<DeviceInfo> {
+    let version = service.get_property_val_str("version")?;
+    if version.parse::<u32>().ok()? != DISCOVERY_PROTOCOL_VERSION {
+      warn!(
+        "Ignoring {} with discovery protocol version {}",
+        service.get_fullname(),
+        version
+      );
       return None;
     }
+
+    // 首选地址只从 mDNS 解析出的地址中选择：与本机同一子网的地址优先，其次是 IPv4 地址。
+    // TXT 记录中公布的地址可能属于本机无法到达的网卡（如 VPN），只追加到地址列表末尾，
+    // 解析出的地址的网卡名称也从 TXT 记录中查找
+    let published: Vec<(String, IpAddr)> = service
+      .get_property_val_str("addresses")
+      .map(|published| {
+        published
+          .split(',')
+          .filter_map(InterfaceAddress::parse_txt)
+          .filter(|(_, ip)| addr::is_usable(ip))
+          .collect()
+      })
+      .unwrap_or_default();
+    let mut addresses: Vec<IpAddr> = service
+      .get_addresses()
+      .iter()
+      .copied()
+      .filter(addr::is_usable)
+      .collect();
+    addresses.sort_by_key(|ip| (!addr::is_on_link(ip), ip.is_ipv6(), *ip));
+    let address = addresses.first()?.to_string();
+    for (_, ip) in &published {
+      if !addresses.contains(ip) {
+        addresses.push(*ip);
+      }
+    }
+    let addresses = addresses
+      .iter()
+      .map(|ip| InterfaceAddress {
+        interface: published
+          .iter()
+          .find(|(_, published)| published == ip)
+          .map(|(interface, _)| interface.clone())
+          .unwrap_or_default(),
+        address: ip.to_string(),
+      })
+      .collect();
+    let port = service
+      .get_property_val_str("port")
+      .and_then(|port| port.parse().ok())
+      .unwrap_or_else(|| service.get_port());
+    // 旧版本设备不公布传输方式，只支持 TCP
+    let transports = service
+      .get_property_val_str("transports")
+      .map(|transports| {
+        transports
+          .split(',')
+          .filter_map(TransportKind::parse)
+          .collect()
+      })
+      .unwrap_or_else(negotiate::legacy_transports);
+
+    Some(DeviceInfo {
+      id: service.get_property_val_str("id")?.to_string(),
+      name: service
+        .get_property_val_str("name")
+        .unwrap_or_else(|| service.get_hostname())
+        .to_string(),
+      address,
+      port,
+      device_type: service
+        .get_property_val_str("device_type")
+        .unwrap_or("unknown")
+        .to_string(),
+      transports,
+      addresses,
+    })
+  }
+
+  /// 获取设备名称
+  fn get_device_name() -> String {
+    hostname::get()
+      .ok()
+      .and_then(|h| h.to_str().map(|s| s.to_string()))
+      .unwrap_or_else(|| "stationuli-device".to_string())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn publishes_interface_addresses_in_txt_records() {
+    let mut discovery = MdnsDiscovery::new(8080, "device-id".to_string());
+    discovery.local_addresses = vec![
+      InterfaceAddress {
+        interface: "eth0".to_string(),
+        address: "192.168.1.10".to_string(),
+      },
+      InterfaceAddress {
+        interface: "wlan0".to_string(),
+        address: "fd00::10".to_string(),
+      },
+    ];
+
+    let records = discovery.txt_records();
+    let published = records
+      .iter()
+      .find_map(|record| record.strip_prefix("addresses="))
+      .unwrap();
+    assert_eq!(published, "eth0=192.168.1.10,wlan0=fd00::10");
+
+    let parsed: Vec<(String, IpAddr)> = published
+      .split(',')
+      .filter_map(InterfaceAddress::parse_txt)
+      .collect();
+    assert_eq!(
+      parsed,
+      vec![
+        ("eth0".to_string(), "192.168.1.10".parse().unwrap()),
+        ("wlan0".to_string(), "fd00::10".parse().unwrap()),
+      ]
+    );
+  }
+
+  fn device(id: &str, name: &str) -> DeviceInfo {
+    DeviceInfo {
+      id: id.to_string(),
+      name: name.to_string(),
+      address: "192.168.1.20".to_string(),
+      port: 8080,
+      device_type: "desktop".to_string(),
+      transports: Vec::new(),
+      addresses: Vec::new(),
+    }
+  }
+
+  async fn send_beacon(socket: &UdpSocket, port: u16, device: DeviceInfo, leaving: bool) {
+    let beacon = Beacon {
+      version: DISCOVERY_PROTOCOL_VERSION,
+      device,
+      leaving,
+    };
+    socket
+      .send_to(&serde_json::to_vec(&beacon).unwrap(), ("127.0.0.1", port))
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn beacons_do_not_override_manual_devices() {
+    let port = std::net::UdpSocket::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap()
+      .port();
+    let mut discovery = MdnsDiscovery::new(8080, "local".to_string());
+    discovery.set_discovery_mode(DiscoveryMode::Broadcast);
+    discovery.set_broadcast_port(port);
+    discovery.start().await.unwrap();
+
+    let manual = device("manual", "Manual");
+    discovery.add_device(manual.clone()).await;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    send_beacon(&socket, port, device("manual", "Renamed"), false).await;
+    send_beacon(&socket, port, device("manual", "Renamed"), true).await;
+    // 信标按顺序处理，另一台设备出现时前面的信标已处理完
+    send_beacon(&socket, port, device("other", "Other"), false).await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+      while discovery.devices.get("other").await.is_none() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+      }
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(discovery.devices.get("manual").await, Some(manual));
+    assert!(!discovery.beacon_seen.read().await.contains_key("manual"));
+    discovery.stop().await.unwrap();
+  }
+
+  #[test]
+  fn parses_addresses_without_interface() {
+    assert_eq!(
+      InterfaceAddress::parse_txt("192.168.1.10"),
+      Some((String::new(), "192.168.1.10".parse().unwrap()))
+    );
+    assert_eq!(InterfaceAddress::parse_txt("eth0=not an address"), None);
   }
 }
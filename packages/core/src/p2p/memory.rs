@@ -0,0 +1,209 @@
+//! 内存回环传输
+//!
+//! 同一进程内按端口号建立连接，帧通过通道直接传递，不经过网络。
+//! 用于在没有网络的环境下运行传输流程
+
+use crate::Result;
+use crate::crypto::Encryption;
+use crate::p2p::transport::{self, FramedConnection, Transport};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// 每个方向上缓存的帧数量（写满后发送方等待）
+const MEMORY_CHANNEL_CAPACITY: usize = 64;
+
+/// 等待 `accept` 取走的连接数量
+const MEMORY_BACKLOG: usize = 16;
+
+/// 出站连接使用的起始端口
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// 内存网络：同一网络中的传输可以互相连接
+#[derive(Clone)]
+pub struct MemoryNetwork {
+  listeners: Arc<Mutex<HashMap<u16, mpsc::Sender<MemoryConnection>>>>,
+  next_port: Arc<AtomicU16>,
+}
+
+impl MemoryNetwork {
+  pub fn new() -> Self {
+    Self {
+      listeners: Arc::new(Mutex::new(HashMap::new())),
+      next_port: Arc::new(AtomicU16::new(EPHEMERAL_PORT_START)),
+    }
+  }
+
+  /// 创建在指定端口上监听的传输（端口为 0 时自动分配）
+  pub fn bind(&self, port: u16) -> Result<MemoryTransport> {
+    let port = if port == 0 {
+      self.ephemeral_port()
+    } else {
+      port
+    };
+    let mut listeners = self.listeners.lock().unwrap();
+    if listeners.get(&port).is_some_and(|tx| !tx.is_closed()) {
+      return Err(crate::Error::Network(format!(
+        "Bind failed: port {} already in use",
+        port
+      )));
+    }
+
+    let (tx, rx) = mpsc::channel(MEMORY_BACKLOG);
+    listeners.insert(port, tx);
+    Ok(MemoryTransport {
+      network: self.clone(),
+      port,
+      incoming: Some(tokio::sync::Mutex::new(rx)),
+    })
+  }
+
+  /// 创建只用于出站连接的传输
+  pub fn transport(&self) -> MemoryTransport {
+    MemoryTransport {
+      network: self.clone(),
+      port: self.ephemeral_port(),
+      incoming: None,
+    }
+  }
+
+  fn ephemeral_port(&self) -> u16 {
+    self.next_port.fetch_add(1, Ordering::Relaxed)
+  }
+}
+
+impl Default for MemoryNetwork {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// 内存传输
+pub struct MemoryTransport {
+  network: MemoryNetwork,
+  port: u16,
+  incoming: Option<tokio::sync::Mutex<mpsc::Receiver<MemoryConnection>>>,
+}
+
+impl Transport for MemoryTransport {
+  type Connection = MemoryConnection;
+
+  async fn connect(&self, _address: &str, port: u16) -> Result<MemoryConnection> {
+    let listener = self
+      .network
+      .listeners
+      .lock()
+      .unwrap()
+      .get(&port)
+      .cloned()
+      .ok_or_else(|| crate::Error::Network(format!("Connection refused: port {}", port)))?;
+
+    let local = MemoryConnection::address(self.network.ephemeral_port());
+    let (connection, remote) = MemoryConnection::pair(local, MemoryConnection::address(port));
+    listener
+      .send(remote)
+      .await
+      .map_err(|_| crate::Error::Network(format!("Connection refused: port {}", port)))?;
+    Ok(connection)
+  }
+
+  async fn accept(&self) -> Result<MemoryConnection> {
+    let incoming = self
+      .incoming
+      .as_ref()
+      .ok_or_else(|| crate::Error::Network("Memory transport is not listening".to_string()))?;
+    incoming
+      .lock()
+      .await
+      .recv()
+      .await
+      .ok_or_else(|| crate::Error::Network("Memory transport closed".to_string()))
+  }
+
+  fn local_addr(&self) -> Result<SocketAddr> {
+    Ok(MemoryConnection::address(self.port))
+  }
+}
+
+impl Drop for MemoryTransport {
+  fn drop(&mut self) {
+    // 释放监听的端口
+    if self.incoming.is_some() {
+      self.network.listeners.lock().unwrap().remove(&self.port);
+    }
+  }
+}
+
+/// 内存连接
+pub struct MemoryConnection {
+  tx: Option<mpsc::Sender<Vec<u8>>>,
+  rx: mpsc::Receiver<Vec<u8>>,
+  address: SocketAddr,
+  encryption: Option<Encryption>, // 握手完成后启用，之后所有帧都会加密
+}
+
+impl MemoryConnection {
+  /// 创建一对互相连接的连接，`a` 和 `b` 分别是两端的本地地址
+  pub fn pair(a: SocketAddr, b: SocketAddr) -> (Self, Self) {
+    let (a_tx, b_rx) = mpsc::channel(MEMORY_CHANNEL_CAPACITY);
+    let (b_tx, a_rx) = mpsc::channel(MEMORY_CHANNEL_CAPACITY);
+    (
+      Self {
+        tx: Some(a_tx),
+        rx: a_rx,
+        address: b,
+        encryption: None,
+      },
+      Self {
+        tx: Some(b_tx),
+        rx: b_rx,
+        address: a,
+        encryption: None,
+      },
+    )
+  }
+
+  fn address(port: u16) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+  }
+}
+
+impl FramedConnection for MemoryConnection {
+  async fn send_frame(&mut self, data: &[u8]) -> Result<()> {
+    let data = match self.encryption {
+      Some(ref mut encryption) => encryption.encrypt(data)?,
+      None => data.to_vec(),
+    };
+    let tx = self
+      .tx
+      .as_ref()
+      .ok_or_else(|| crate::Error::Network("Connection not established".to_string()))?;
+    tx.send(data)
+      .await
+      .map_err(|_| crate::Error::Network("Write data failed: connection closed".to_string()))
+  }
+
+  async fn recv_frame(&mut self) -> Result<Vec<u8>> {
+    let data = self
+      .rx
+      .recv()
+      .await
+      .ok_or_else(|| crate::Error::Network("Read data failed: connection closed".to_string()))?;
+    transport::open_frame(self.encryption.as_mut(), data)
+  }
+
+  async fn close(&mut self) -> Result<()> {
+    self.tx = None;
+    Ok(())
+  }
+
+  fn peer_addr(&self) -> SocketAddr {
+    self.address
+  }
+
+  fn set_encryption(&mut self, encryption: Encryption) {
+    self.encryption = Some(encryption);
+  }
+}
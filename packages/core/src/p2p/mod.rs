@@ -1,19 +1,24 @@
 //! P2P 协议栈模块
 //!
-//! 提供 QUIC、TCP、mDNS 等网络协议实现
+//! 提供 QUIC、TCP、mDNS 等网络协议实现：
+//!
+//! - 统一的传输抽象
+//! - QUIC / TCP 自动协商
+//! - IPv4 / IPv6 双栈地址处理
 
+pub mod addr;
+pub mod limiter;
 pub mod mdns;
+pub mod memory;
+pub mod negotiate;
 pub mod quic;
 pub mod tcp;
+pub mod transport;
 
-pub use mdns::MdnsDiscovery;
-pub use quic::QuicConnection;
-pub use tcp::TcpConnection;
-
-/// P2P 连接 trait
-pub trait Connection {
-  fn connect(&mut self) -> crate::Result<()>;
-  fn send(&mut self, data: &[u8]) -> crate::Result<()>;
-  fn receive(&mut self) -> crate::Result<Vec<u8>>;
-  fn close(&mut self) -> crate::Result<()>;
-}
+pub use limiter::{ConnectionSlot, PeerLimiter, ServerLimits};
+pub use mdns::{DeviceEvent, DiscoveryMode, InterfaceAddress, MdnsDiscovery};
+pub use memory::{MemoryConnection, MemoryNetwork, MemoryTransport};
+pub use negotiate::{NegotiatedConnection, NegotiatedTransport, Negotiation, TransportKind};
+pub use quic::{QuicConnection, QuicEndpoint, QuicPeer, QuicStream, QuicTransport};
+pub use tcp::{TcpConnection, TcpTransport};
+pub use transport::{ConnectionLimits, FramedConnection, Transport};
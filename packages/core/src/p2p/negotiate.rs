@@ -0,0 +1,547 @@
+//! 传输协商
+//!
+//! 发起连接时优先尝试 QUIC，在限定时间内无法建立时回退到 TCP，调用方无需关心对端支持哪种传输。
+//! 设备通过 `DeviceInfo::transports` 公布自己支持的传输，已知不支持 QUIC 的对端直接使用 TCP。
+//! 每次协商的结果（传输方式和协议版本）都会被记录下来
+
+use crate::Result;
+use crate::crypto::{Encryption, PeerIdentity, TrustStore};
+use crate::file::frame;
+use crate::identity::{self, DeviceIdentity};
+use crate::p2p::limiter::PeerLimiter;
+use crate::p2p::quic::{QuicStream, QuicTransport};
+use crate::p2p::tcp::{TcpConnection, TcpTransport};
+use crate::p2p::transport::{ConnectionLimits, FramedConnection, Transport};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
+
+/// QUIC 连接的默认等待时间，超时后回退到 TCP
+pub const DEFAULT_NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// QUIC 连接失败后只使用 TCP 的时长，到期后重新尝试 QUIC
+pub const QUIC_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// 传输方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+  Quic,
+  Tcp,
+}
+
+impl TransportKind {
+  /// 名称（与序列化结果及 TXT 记录中的写法一致）
+  pub fn as_str(self) -> &'static str {
+    match self {
+      TransportKind::Quic => "quic",
+      TransportKind::Tcp => "tcp",
+    }
+  }
+
+  /// 解析传输名称，未知的名称返回 `None`
+  pub fn parse(name: &str) -> Option<Self> {
+    match name.trim() {
+      "quic" => Some(TransportKind::Quic),
+      "tcp" => Some(TransportKind::Tcp),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for TransportKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+/// 旧版本设备只支持 TCP
+pub fn legacy_transports() -> Vec<TransportKind> {
+  vec![TransportKind::Tcp]
+}
+
+/// 协商结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Negotiation {
+  pub transport: TransportKind,
+  pub protocol_version: u32,
+}
+
+/// 协商后的连接
+pub enum NegotiatedConnection {
+  Quic(QuicStream),
+  Tcp(TcpConnection),
+}
+
+impl NegotiatedConnection {
+  /// 设置接收限制
+  pub fn set_limits(&mut self, limits: ConnectionLimits) {
+    match self {
+      NegotiatedConnection::Quic(stream) => stream.set_limits(limits),
+      NegotiatedConnection::Tcp(connection) => connection.set_limits(limits),
+    }
+  }
+
+  /// 连接使用的传输方式和协议版本
+  pub fn negotiation(&self) -> Negotiation {
+    match self {
+      NegotiatedConnection::Quic(stream) => Negotiation {
+        transport: TransportKind::Quic,
+        protocol_version: stream.protocol_version(),
+      },
+      // TCP 没有带内协商，使用本端的传输协议版本（由帧头校验）
+      NegotiatedConnection::Tcp(_) => Negotiation {
+        transport: TransportKind::Tcp,
+        protocol_version: u32::from(frame::PROTOCOL_VERSION),
+      },
+    }
+  }
+}
+
+impl FramedConnection for NegotiatedConnection {
+  async fn send_frame(&mut self, data: &[u8]) -> Result<()> {
+    match self {
+      NegotiatedConnection::Quic(stream) => stream.send_frame(data).await,
+      NegotiatedConnection::Tcp(connection) => connection.send_frame(data).await,
+    }
+  }
+
+  async fn recv_frame(&mut self) -> Result<Vec<u8>> {
+    match self {
+      NegotiatedConnection::Quic(stream) => stream.recv_frame().await,
+      NegotiatedConnection::Tcp(connection) => connection.recv_frame().await,
+    }
+  }
+
+  async fn close(&mut self) -> Result<()> {
+    match self {
+      NegotiatedConnection::Quic(stream) => FramedConnection::close(stream).await,
+      NegotiatedConnection::Tcp(connection) => FramedConnection::close(connection).await,
+    }
+  }
+
+  fn peer_addr(&self) -> SocketAddr {
+    match self {
+      NegotiatedConnection::Quic(stream) => stream.peer_addr(),
+      NegotiatedConnection::Tcp(connection) => connection.peer_addr(),
+    }
+  }
+
+  fn set_encryption(&mut self, encryption: Encryption) {
+    match self {
+      NegotiatedConnection::Quic(stream) => stream.set_encryption(encryption),
+      NegotiatedConnection::Tcp(connection) => {
+        FramedConnection::set_encryption(connection, encryption)
+      }
+    }
+  }
+
+  fn verify_peer(&self, peer: &PeerIdentity) -> Result<()> {
+    match self {
+      NegotiatedConnection::Quic(stream) => stream.verify_peer(peer),
+      NegotiatedConnection::Tcp(connection) => connection.verify_peer(peer),
+    }
+  }
+}
+
+/// 自动协商的传输：QUIC 优先，失败或超时后回退到 TCP
+///
+/// 监听时 QUIC 端点与 TCP 使用相同的端口号（UDP），入站连接来自哪种传输都会交给 `accept`
+pub struct NegotiatedTransport {
+  tcp: TcpTransport,
+  /// QUIC 传输（只用于出站连接时在第一次连接前才创建，创建失败时为 `None`）
+  quic: OnceCell<Option<QuicTransport>>,
+  identity: Arc<DeviceIdentity>,
+  timeout: Duration,
+  limits: ConnectionLimits,
+  /// 对端公布的传输能力（地址、端口 -> 支持的传输）
+  peers: Mutex<HashMap<(String, u16), Vec<TransportKind>>>,
+  /// QUIC 连接失败的时间（地址、端口 -> 失败时间），`QUIC_RETRY_INTERVAL` 内只使用 TCP
+  quic_failures: Mutex<HashMap<(String, u16), Instant>>,
+  /// 对端公布的设备 ID（地址、端口 -> 设备 ID），QUIC 连接时要求证书与之一致
+  device_ids: Mutex<HashMap<(String, u16), String>>,
+  /// 受信任设备存储，已配对设备的 ID 不一致时拒绝连接而不是回退到 TCP
+  trust_store: Option<Arc<TrustStore>>,
+  /// 最近一次出站连接的协商结果（地址、端口 -> 协商结果）
+  negotiated: Mutex<HashMap<(String, u16), Negotiation>>,
+  /// 入站连接的来源限制器（QUIC 传输延迟创建时再交给它）
+  peer_limiter: Mutex<Option<PeerLimiter>>,
+}
+
+impl NegotiatedTransport {
+  /// 创建只用于出站连接的传输
+  pub fn new(identity: Arc<DeviceIdentity>) -> Self {
+    Self::from_parts(TcpTransport::new(), OnceCell::new(), identity)
+  }
+
+  /// 在指定端口上同时监听 TCP 和 QUIC
+  ///
+  /// QUIC 端点创建失败时只记录警告，仍然可以通过 TCP 收发
+  pub async fn bind(port: u16, identity: Arc<DeviceIdentity>) -> Result<Self> {
+    let tcp = TcpTransport::bind(port).await?;
+    let port = tcp.local_addr()?.port();
+    let quic = match QuicTransport::bind(port, &identity) {
+      Ok(quic) => Some(quic),
+      Err(e) => {
+        warn!("QUIC unavailable on port {}, using TCP only: {}", port, e);
+        None
+      }
+    };
+    Ok(Self::from_parts(
+      tcp,
+      OnceCell::new_with(Some(quic)),
+      identity,
+    ))
+  }
+
+  fn from_parts(
+    tcp: TcpTransport,
+    quic: OnceCell<Option<QuicTransport>>,
+    identity: Arc<DeviceIdentity>,
+  ) -> Self {
+    Self {
+      tcp,
+      quic,
+      identity,
+      timeout: DEFAULT_NEGOTIATION_TIMEOUT,
+      limits: ConnectionLimits::default(),
+      peers: Mutex::new(HashMap::new()),
+      quic_failures: Mutex::new(HashMap::new()),
+      device_ids: Mutex::new(HashMap::new()),
+      trust_store: None,
+      negotiated: Mutex::new(HashMap::new()),
+      peer_limiter: Mutex::new(None),
+    }
+  }
+
+  /// 设置 QUIC 连接的等待时间
+  pub fn set_timeout(&mut self, timeout: Duration) {
+    self.timeout = timeout;
+  }
+
+  /// 设置之后建立的连接的接收限制
+  pub fn set_limits(&mut self, limits: ConnectionLimits) {
+    self.limits = limits;
+  }
+
+  /// 设置受信任设备存储（用于判断设备 ID 不一致时是否回退到 TCP）
+  pub fn set_trust_store(&mut self, trust_store: Arc<TrustStore>) {
+    self.trust_store = Some(trust_store);
+  }
+
+  /// 本端支持的传输（用于设备发现时公布）
+  pub fn transports(&self) -> Vec<TransportKind> {
+    match self.quic.get() {
+      Some(None) => legacy_transports(),
+      _ => vec![TransportKind::Quic, TransportKind::Tcp],
+    }
+  }
+
+  /// 记录对端公布的传输能力，列表为空表示未知（先尝试 QUIC 再回退）
+  pub fn set_peer_transports(&self, address: &str, port: u16, transports: Vec<TransportKind>) {
+    let key = (address.trim().to_string(), port);
+    let mut peers = self.peers.lock().unwrap();
+    if transports.is_empty() {
+      peers.remove(&key);
+    } else {
+      peers.insert(key, transports);
+    }
+  }
+
+  /// 记录对端的设备 ID（来自设备发现）
+  ///
+  /// 之后到该地址的 QUIC 连接要求证书对应此设备 ID。设备发现的记录未经认证，
+  /// 因此只有已配对的设备 ID 不一致时才拒绝连接，其余情况回退到 TCP，由握手和配对检查决定是否继续。
+  /// 不是身份指纹格式的 ID（如手动添加的设备）不会被记录
+  pub fn set_peer_device_id(&self, address: &str, port: u16, device_id: &str) {
+    let key = (address.trim().to_string(), port);
+    let mut device_ids = self.device_ids.lock().unwrap();
+    if identity::is_fingerprint(device_id) {
+      device_ids.insert(key, device_id.to_string());
+    } else {
+      device_ids.remove(&key);
+    }
+  }
+
+  /// 到指定对端的最近一次协商结果
+  pub fn negotiated(&self, address: &str, port: u16) -> Option<Negotiation> {
+    self
+      .negotiated
+      .lock()
+      .unwrap()
+      .get(&(address.trim().to_string(), port))
+      .copied()
+  }
+
+  /// 获取 QUIC 传输，只用于出站连接时在第一次调用时创建
+  async fn quic(&self) -> Option<&QuicTransport> {
+    self
+      .quic
+      .get_or_init(|| async {
+        let quic = QuicTransport::bind(0, &self.identity)
+          .inspect_err(|e| warn!("QUIC unavailable, using TCP only: {}", e))
+          .ok()?;
+        if let Some(limiter) = self.peer_limiter.lock().unwrap().clone() {
+          quic.set_peer_limiter(limiter);
+        }
+        Some(quic)
+      })
+      .await
+      .as_ref()
+  }
+
+  /// 是否应尝试 QUIC（能力未知时视为支持，最近失败过的对端暂时只用 TCP）
+  fn peer_supports_quic(&self, key: &(String, u16)) -> bool {
+    let advertised = self
+      .peers
+      .lock()
+      .unwrap()
+      .get(key)
+      .is_none_or(|transports| transports.contains(&TransportKind::Quic));
+    if !advertised {
+      return false;
+    }
+
+    let mut failures = self.quic_failures.lock().unwrap();
+    match failures.get(key) {
+      Some(failed_at) if failed_at.elapsed() < QUIC_RETRY_INTERVAL => false,
+      Some(_) => {
+        failures.remove(key);
+        true
+      }
+      None => true,
+    }
+  }
+
+  /// 设备 ID 是否属于已配对的设备
+  async fn is_paired(&self, device_id: Option<&str>) -> bool {
+    match (device_id, &self.trust_store) {
+      (Some(device_id), Some(trust_store)) => trust_store.contains(device_id).await,
+      _ => false,
+    }
+  }
+
+  /// 尝试在限定时间内建立 QUIC 连接
+  ///
+  /// 无法建立时返回 `None` 以回退到 TCP；对端证书与已配对的设备 ID 不一致时直接返回错误
+  async fn connect_quic(&self, address: &str, port: u16) -> Result<Option<QuicStream>> {
+    let Some(quic) = self.quic().await else {
+      return Ok(None);
+    };
+    let key = (address.trim().to_string(), port);
+    let expected = self.device_ids.lock().unwrap().get(&key).cloned();
+    let connect = quic.connect_to(address, port, expected.as_deref());
+    match tokio::time::timeout(self.timeout, connect).await {
+      Ok(Ok(stream)) => Ok(Some(stream)),
+      Ok(Err(e @ crate::Error::Crypto(_))) if self.is_paired(expected.as_deref()).await => Err(e),
+      Ok(Err(e)) => {
+        warn!(
+          "QUIC connection to {}:{} failed, falling back to TCP: {}",
+          address, port, e
+        );
+        Ok(None)
+      }
+      Err(_) => {
+        warn!(
+          "QUIC connection to {}:{} timed out after {:?}, falling back to TCP",
+          address, port, self.timeout
+        );
+        Ok(None)
+      }
+    }
+  }
+}
+
+impl Transport for NegotiatedTransport {
+  type Connection = NegotiatedConnection;
+
+  async fn connect(&self, address: &str, port: u16) -> Result<NegotiatedConnection> {
+    let key = (address.trim().to_string(), port);
+    let mut connection = None;
+    if self.peer_supports_quic(&key) {
+      connection = self
+        .connect_quic(address, port)
+        .await?
+        .map(NegotiatedConnection::Quic);
+      if connection.is_none() {
+        // 回退后一段时间内该对端只使用 TCP，避免每次连接都等待超时；到期后再尝试 QUIC
+        self
+          .quic_failures
+          .lock()
+          .unwrap()
+          .insert(key.clone(), Instant::now());
+      }
+    }
+    let mut connection = match connection {
+      Some(connection) => connection,
+      None => NegotiatedConnection::Tcp(self.tcp.connect(address, port).await?),
+    };
+    connection.set_limits(self.limits);
+
+    let negotiation = connection.negotiation();
+    info!(
+      "Negotiated {} (protocol v{}) with {}:{}",
+      negotiation.transport, negotiation.protocol_version, address, port
+    );
+    self.negotiated.lock().unwrap().insert(key, negotiation);
+    Ok(connection)
+  }
+
+  async fn accept(&self) -> Result<NegotiatedConnection> {
+    let mut connection = match self.quic.get() {
+      Some(Some(quic)) => tokio::select! {
+        accepted = self.tcp.accept() => NegotiatedConnection::Tcp(accepted?),
+        accepted = quic.accept() => NegotiatedConnection::Quic(accepted?),
+      },
+      _ => NegotiatedConnection::Tcp(self.tcp.accept().await?),
+    };
+    connection.set_limits(self.limits);
+    Ok(connection)
+  }
+
+  fn local_addr(&self) -> Result<SocketAddr> {
+    self.tcp.local_addr()
+  }
+
+  fn set_peer_limiter(&self, limiter: PeerLimiter) {
+    if let Some(Some(quic)) = self.quic.get() {
+      quic.set_peer_limiter(limiter.clone());
+    }
+    *self.peer_limiter.lock().unwrap() = Some(limiter);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn client() -> NegotiatedTransport {
+    let mut transport = NegotiatedTransport::new(Arc::new(DeviceIdentity::generate().unwrap()));
+    transport.set_timeout(Duration::from_millis(500));
+    transport
+  }
+
+  #[tokio::test]
+  async fn falls_back_to_tcp_only_peer() {
+    let server = TcpTransport::bind(0).await.unwrap();
+    let port = server.local_addr().unwrap().port();
+    let client = client();
+
+    let connection = client.connect("127.0.0.1", port).await.unwrap();
+    let expected = Negotiation {
+      transport: TransportKind::Tcp,
+      protocol_version: u32::from(frame::PROTOCOL_VERSION),
+    };
+    assert_eq!(connection.negotiation(), expected);
+    assert_eq!(client.negotiated("127.0.0.1", port), Some(expected));
+
+    // 回退后暂时不再尝试 QUIC
+    let key = ("127.0.0.1".to_string(), port);
+    assert!(!client.peer_supports_quic(&key));
+    let started = Instant::now();
+    let connection = client.connect("127.0.0.1", port).await.unwrap();
+    assert_eq!(connection.negotiation().transport, TransportKind::Tcp);
+    assert!(started.elapsed() < client.timeout);
+  }
+
+  #[test]
+  fn retries_quic_after_fallback_expires() {
+    let client = client();
+    let key = ("127.0.0.1".to_string(), 1);
+    let expired = Instant::now().checked_sub(QUIC_RETRY_INTERVAL);
+    let Some(expired) = expired else { return };
+    client
+      .quic_failures
+      .lock()
+      .unwrap()
+      .insert(key.clone(), expired);
+    assert!(client.peer_supports_quic(&key));
+    assert!(client.quic_failures.lock().unwrap().is_empty());
+  }
+
+  async fn quic_server() -> (NegotiatedTransport, u16) {
+    let server = NegotiatedTransport::bind(0, Arc::new(DeviceIdentity::generate().unwrap()))
+      .await
+      .unwrap();
+    let port = server.local_addr().unwrap().port();
+    (server, port)
+  }
+
+  #[tokio::test]
+  async fn prefers_quic() {
+    let (_server, port) = quic_server().await;
+    let client = client();
+
+    let connection = client.connect("127.0.0.1", port).await.unwrap();
+    assert_eq!(connection.negotiation().transport, TransportKind::Quic);
+
+    // 对端只公布了 TCP 时直接使用 TCP
+    client.set_peer_transports("127.0.0.1", port, legacy_transports());
+    let connection = client.connect("127.0.0.1", port).await.unwrap();
+    assert_eq!(connection.negotiation().transport, TransportKind::Tcp);
+  }
+
+  #[tokio::test]
+  async fn falls_back_on_unverified_identity_mismatch() {
+    let (_server, port) = quic_server().await;
+    let client = client();
+    // 设备发现记录未经认证，未配对的设备 ID 不一致时交给握手和配对检查
+    let other = DeviceIdentity::generate().unwrap();
+    client.set_peer_device_id("127.0.0.1", port, other.device_id());
+
+    let connection = client.connect("127.0.0.1", port).await.unwrap();
+    assert_eq!(connection.negotiation().transport, TransportKind::Tcp);
+  }
+
+  #[tokio::test]
+  async fn ignores_non_fingerprint_device_ids() {
+    let (_server, port) = quic_server().await;
+    let client = client();
+    // 手动添加的设备使用 "地址:端口" 作为占位 ID
+    client.set_peer_device_id("127.0.0.1", port, &format!("127.0.0.1:{}", port));
+
+    let connection = client.connect("127.0.0.1", port).await.unwrap();
+    assert_eq!(connection.negotiation().transport, TransportKind::Quic);
+  }
+
+  #[tokio::test]
+  async fn rejects_paired_identity_mismatch() {
+    let (_server, port) = quic_server().await;
+    let mut client = client();
+    let paired = DeviceIdentity::generate().unwrap();
+    let trust_store = Arc::new(TrustStore::in_memory());
+    trust_store
+      .trust(&PeerIdentity {
+        device_id: paired.device_id().to_string(),
+        device_name: "paired".to_string(),
+        device_type: "desktop".to_string(),
+        public_key: paired.static_public_key(),
+      })
+      .await
+      .unwrap();
+    client.set_trust_store(trust_store);
+    client.set_peer_device_id("127.0.0.1", port, paired.device_id());
+
+    let result = client.connect("127.0.0.1", port).await;
+    assert!(matches!(result, Err(crate::Error::Crypto(_))));
+    assert_eq!(client.negotiated("127.0.0.1", port), None);
+  }
+
+  #[tokio::test]
+  async fn blocked_peers_cannot_open_quic_connections() {
+    let (server, port) = quic_server().await;
+    let limiter = PeerLimiter::default();
+    limiter.block("127.0.0.1".parse().unwrap());
+    server.set_peer_limiter(limiter);
+    let client = client();
+
+    // QUIC 连接在握手前被丢弃，客户端回退到 TCP，由传输服务按同一个限制器拒绝
+    let connection = client.connect("127.0.0.1", port).await.unwrap();
+    assert_eq!(connection.negotiation().transport, TransportKind::Tcp);
+  }
+}
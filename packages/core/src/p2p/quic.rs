@@ -1,36 +1,744 @@
 //! QUIC 协议实现
+//!
+//! 每个端点使用设备 Ed25519 签名密钥签发的自签名证书，证书扩展中携带 X25519 长期公钥。
+//! 双方在 TLS 握手中互相出示证书，并用两把公钥重新计算设备 ID，因此证书被固定到设备身份上。
+//! 一个连接可以同时打开多个双向流，文件传输和屏幕投影各自使用独立的流，互不阻塞
 
 use crate::Result;
+use crate::crypto::key_exchange::PUBLIC_KEY_LEN;
+use crate::crypto::{Encryption, PeerIdentity};
+use crate::file::frame;
+use crate::identity::{self, DeviceIdentity};
+use crate::p2p::addr;
+use crate::p2p::limiter::{ConnectionSlot, PeerLimiter};
+use crate::p2p::transport::{self, ConnectionLimits, FramedConnection, Transport};
+use quinn::{ClientConfig, Endpoint, EndpointConfig, ServerConfig, TransportConfig};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::server::{ClientCertVerified, ClientCertVerifier};
+use rustls::{Certificate, CertificateError, DistinguishedName, PrivateKey, ServerName};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use x509_parser::oid_registry::OID_SIG_ED25519;
+
+/// ALPN 协议标识的前缀，其后为传输协议版本号
+const ALPN_PREFIX: &[u8] = b"stationuli/";
+
+/// TLS 握手使用的服务器名称（证书按设备 ID 校验，不使用域名）
+const SERVER_NAME: &str = "stationuli";
+
+/// 证书中 X25519 长期公钥扩展的 OID（2.25 UUID 分支下的私有值）
+const STATIC_KEY_OID: &[u64] = &[2, 25, 9_216_437_108_214_355_811, 1];
+
+/// DER OCTET STRING 头（长度为 32 字节）
+const STATIC_KEY_PREFIX: [u8; 2] = [0x04, PUBLIC_KEY_LEN as u8];
+
+/// 空闲保活间隔，防止 NAT 映射和空闲超时关闭连接
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 空闲超时
+const MAX_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 等待 `accept` 取走的入站流数量上限
+const INCOMING_STREAM_CAPACITY: usize = 64;
+
+/// ALPN 协议标识：前缀加传输协议版本号，版本与帧头中的版本一致
+///
+/// 最初的 QUIC 实现固定使用 `stationuli/1`；帧格式升级到版本 2 后改为随版本号变化（当前为
+/// `stationuli/2`），只支持旧版本的对端在 TLS 握手时即协商失败，并回退到 TCP 由帧头版本处理
+pub fn alpn_protocol() -> Vec<u8> {
+  let mut protocol = ALPN_PREFIX.to_vec();
+  protocol.extend_from_slice(frame::PROTOCOL_VERSION.to_string().as_bytes());
+  protocol
+}
+
+/// 证书中携带的对端身份（设备 ID 已由两把公钥重新计算并核对）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuicPeer {
+  pub device_id: String,
+  /// Ed25519 签名公钥（即证书公钥）
+  pub signing_public_key: Vec<u8>,
+  /// X25519 长期公钥
+  pub static_public_key: [u8; PUBLIC_KEY_LEN],
+}
+
+impl QuicPeer {
+  /// 从 DER 证书中解析对端身份
+  fn from_certificate(der: &[u8]) -> Result<Self> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der)
+      .map_err(|e| crate::Error::Crypto(format!("Parse certificate failed: {}", e)))?;
+
+    let public_key = cert.public_key();
+    if public_key.algorithm.algorithm != OID_SIG_ED25519 {
+      return Err(crate::Error::Crypto(
+        "Certificate key is not Ed25519".to_string(),
+      ));
+    }
+    let signing_public_key = public_key.subject_public_key.data.to_vec();
+
+    let extension = cert
+      .extensions()
+      .iter()
+      .find(|ext| {
+        ext
+          .oid
+          .iter()
+          .is_some_and(|arcs| arcs.eq(STATIC_KEY_OID.iter().copied()))
+      })
+      .ok_or_else(|| crate::Error::Crypto("Certificate has no static key".to_string()))?;
+    let static_public_key: [u8; PUBLIC_KEY_LEN] = extension
+      .value
+      .strip_prefix(&STATIC_KEY_PREFIX)
+      .and_then(|key| key.try_into().ok())
+      .ok_or_else(|| crate::Error::Crypto("Invalid static key in certificate".to_string()))?;
+
+    Ok(Self {
+      device_id: identity::fingerprint(&signing_public_key, &static_public_key),
+      signing_public_key,
+      static_public_key,
+    })
+  }
+}
+
+/// 证书校验：只接受携带有效设备身份的自签名证书
+struct PeerVerifier {
+  /// 请求客户端证书时发送的颁发者名称（必须非空，否则 rustls 不会请求客户端证书）
+  root_subjects: Vec<DistinguishedName>,
+}
+
+impl PeerVerifier {
+  fn verify(&self, end_entity: &Certificate) -> std::result::Result<(), rustls::Error> {
+    QuicPeer::from_certificate(&end_entity.0)
+      .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+    Ok(())
+  }
+}
+
+impl ServerCertVerifier for PeerVerifier {
+  fn verify_server_cert(
+    &self,
+    end_entity: &Certificate,
+    _intermediates: &[Certificate],
+    _server_name: &ServerName,
+    _scts: &mut dyn Iterator<Item = &[u8]>,
+    _ocsp_response: &[u8],
+    _now: SystemTime,
+  ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+    self.verify(end_entity)?;
+    Ok(ServerCertVerified::assertion())
+  }
+}
+
+impl ClientCertVerifier for PeerVerifier {
+  fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+    &self.root_subjects
+  }
+
+  fn verify_client_cert(
+    &self,
+    end_entity: &Certificate,
+    _intermediates: &[Certificate],
+    _now: SystemTime,
+  ) -> std::result::Result<ClientCertVerified, rustls::Error> {
+    self.verify(end_entity)?;
+    Ok(ClientCertVerified::assertion())
+  }
+}
+
+/// 本端证书和私钥
+struct LocalCertificate {
+  cert: Certificate,
+  key: PrivateKey,
+  subject: DistinguishedName,
+}
+
+impl LocalCertificate {
+  /// 用设备签名密钥签发自签名证书
+  fn generate(identity: &DeviceIdentity) -> Result<Self> {
+    let key_pair = rcgen::KeyPair::from_der(identity.signing_pkcs8())
+      .map_err(|e| crate::Error::Crypto(format!("Load signing key failed: {}", e)))?;
+
+    let mut params = rcgen::CertificateParams::new(Vec::new());
+    params.alg = &rcgen::PKCS_ED25519;
+    params.key_pair = Some(key_pair);
+    params
+      .distinguished_name
+      .push(rcgen::DnType::CommonName, identity.device_id());
+    let mut static_key = STATIC_KEY_PREFIX.to_vec();
+    static_key.extend_from_slice(&identity.static_public_key());
+    params
+      .custom_extensions
+      .push(rcgen::CustomExtension::from_oid_content(
+        STATIC_KEY_OID,
+        static_key,
+      ));
+
+    let der = rcgen::Certificate::from_params(params)
+      .and_then(|cert| cert.serialize_der())
+      .map_err(|e| crate::Error::Crypto(format!("Generate certificate failed: {}", e)))?;
+    let (_, parsed) = x509_parser::parse_x509_certificate(&der)
+      .map_err(|e| crate::Error::Crypto(format!("Parse certificate failed: {}", e)))?;
+    let subject = DistinguishedName::from(parsed.subject().as_raw().to_vec());
+
+    Ok(Self {
+      cert: Certificate(der),
+      key: PrivateKey(identity.signing_pkcs8().to_vec()),
+      subject,
+    })
+  }
+
+  fn verifier(&self) -> Arc<PeerVerifier> {
+    Arc::new(PeerVerifier {
+      root_subjects: vec![self.subject.clone()],
+    })
+  }
+}
+
+/// QUIC 端点（同时作为服务端和客户端）
+pub struct QuicEndpoint {
+  endpoint: Endpoint,
+  certificate: LocalCertificate,
+  transport: Arc<TransportConfig>,
+}
+
+impl QuicEndpoint {
+  /// 在指定 UDP 端口上创建端点
+  pub fn bind(port: u16, identity: &DeviceIdentity) -> Result<Self> {
+    // 双栈 UDP 端口，同时接受 IPv4 和 IPv6 连接
+    let socket = addr::bind_udp(port)?;
+
+    let certificate = LocalCertificate::generate(identity)?;
+
+    let mut transport = TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    transport.max_idle_timeout(Some(
+      MAX_IDLE_TIMEOUT
+        .try_into()
+        .map_err(|e| crate::Error::Network(format!("Invalid idle timeout: {}", e)))?,
+    ));
+    let transport = Arc::new(transport);
+
+    let mut crypto = rustls::ServerConfig::builder()
+      .with_safe_defaults()
+      .with_client_cert_verifier(certificate.verifier())
+      .with_single_cert(vec![certificate.cert.clone()], certificate.key.clone())
+      .map_err(|e| crate::Error::Crypto(format!("Build TLS config failed: {}", e)))?;
+    crypto.alpn_protocols = vec![alpn_protocol()];
+    let mut server_config = ServerConfig::with_crypto(Arc::new(crypto));
+    server_config.transport_config(transport.clone());
+
+    let runtime = quinn::default_runtime()
+      .ok_or_else(|| crate::Error::Network("No async runtime found".to_string()))?;
+    let endpoint = Endpoint::new(
+      EndpointConfig::default(),
+      Some(server_config),
+      socket,
+      runtime,
+    )
+    .map_err(|e| crate::Error::Network(format!("Bind failed: {}", e)))?;
+
+    let addr = endpoint
+      .local_addr()
+      .map_err(|e| crate::Error::Network(format!("Get local address failed: {}", e)))?;
+    info!("QUIC endpoint listening on {}", addr);
+    Ok(Self {
+      endpoint,
+      certificate,
+      transport,
+    })
+  }
+
+  /// 本地地址
+  pub fn local_addr(&self) -> Result<SocketAddr> {
+    self
+      .endpoint
+      .local_addr()
+      .map_err(|e| crate::Error::Network(format!("Get local address failed: {}", e)))
+  }
+
+  /// 连接到对端
+  ///
+  /// 指定 `expected_device_id` 时，对端证书对应的设备 ID 必须一致，否则关闭连接并返回 `Error::Crypto`
+  pub async fn connect(
+    &self,
+    address: &str,
+    port: u16,
+    expected_device_id: Option<&str>,
+  ) -> Result<QuicConnection> {
+    let addr = addr::parse_socket_addr(address, port)?;
+
+    let mut crypto = rustls::ClientConfig::builder()
+      .with_safe_defaults()
+      .with_custom_certificate_verifier(self.certificate.verifier())
+      .with_client_auth_cert(
+        vec![self.certificate.cert.clone()],
+        self.certificate.key.clone(),
+      )
+      .map_err(|e| crate::Error::Crypto(format!("Build TLS config failed: {}", e)))?;
+    crypto.alpn_protocols = vec![alpn_protocol()];
+    let mut client_config = ClientConfig::new(Arc::new(crypto));
+    client_config.transport_config(self.transport.clone());
+
+    info!("Connecting to {} over QUIC", addr);
+    let connection = self
+      .endpoint
+      .connect_with(client_config, addr, SERVER_NAME)
+      .map_err(|e| crate::Error::Network(format!("Connection failed: {}", e)))?
+      .await
+      .map_err(|e| crate::Error::Network(format!("Connection failed: {}", e)))?;
+
+    let connection = QuicConnection::new(connection)?;
+    if let Some(expected) = expected_device_id
+      && expected != connection.peer.device_id
+    {
+      connection.close();
+      return Err(crate::Error::Crypto(format!(
+        "QUIC peer identity mismatch: expected {}, got {}",
+        expected, connection.peer.device_id
+      )));
+    }
+    Ok(connection)
+  }
+
+  /// 接受一个入站连接，端点关闭后返回 `None`
+  pub async fn accept(&self) -> Option<Result<QuicConnection>> {
+    let connecting = self.endpoint.accept().await?;
+    Some(Self::establish(connecting).await)
+  }
+
+  /// 等待入站连接完成 TLS 握手
+  async fn establish(connecting: quinn::Connecting) -> Result<QuicConnection> {
+    let connection = connecting
+      .await
+      .map_err(|e| crate::Error::Network(format!("Accept failed: {}", e)))?;
+    QuicConnection::new(connection)
+  }
+
+  /// 关闭端点上的所有连接并等待对端确认
+  pub async fn close(&self) {
+    self.endpoint.close(0u32.into(), b"closed");
+    self.endpoint.wait_idle().await;
+    info!("QUIC endpoint closed");
+  }
+}
 
 /// QUIC 连接
+#[derive(Clone)]
 pub struct QuicConnection {
-  // TODO: 实现 QUIC 连接
+  connection: quinn::Connection,
+  address: SocketAddr,
+  peer: QuicPeer,
+  protocol_version: u32,
 }
 
 impl QuicConnection {
-  pub fn new() -> Self {
-    Self {}
+  fn new(connection: quinn::Connection) -> Result<Self> {
+    // 双栈端点上的 IPv4 对端地址以映射形式出现，还原为 IPv4 地址
+    let address = addr::canonical(connection.remote_address());
+    let certificate = connection
+      .peer_identity()
+      .and_then(|identity| identity.downcast::<Vec<Certificate>>().ok())
+      .and_then(|certs| certs.into_iter().next())
+      .ok_or_else(|| crate::Error::Crypto("Peer presented no certificate".to_string()))?;
+    let peer = QuicPeer::from_certificate(&certificate.0)?;
+    let protocol_version = connection
+      .handshake_data()
+      .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+      .and_then(|data| data.protocol)
+      .and_then(|protocol| Self::alpn_version(&protocol))
+      .ok_or_else(|| crate::Error::Protocol("Peer negotiated no supported ALPN".to_string()))?;
+
+    info!(
+      "QUIC connection established with {} ({}, protocol v{})",
+      address, peer.device_id, protocol_version
+    );
+    Ok(Self {
+      connection,
+      address,
+      peer,
+      protocol_version,
+    })
+  }
+
+  /// 从 ALPN 协议标识中解析传输协议版本
+  fn alpn_version(protocol: &[u8]) -> Option<u32> {
+    let version = protocol.strip_prefix(ALPN_PREFIX)?;
+    std::str::from_utf8(version).ok()?.parse().ok()
+  }
+
+  /// 打开一个新的双向流
+  pub async fn open_stream(&self) -> Result<QuicStream> {
+    let (send, recv) = self
+      .connection
+      .open_bi()
+      .await
+      .map_err(|e| crate::Error::Network(format!("Open stream failed: {}", e)))?;
+    Ok(self.stream(send, recv))
+  }
+
+  /// 等待对端打开的下一个双向流
+  ///
+  /// 对端打开流后需要先发送数据，本端才能收到该流
+  pub async fn accept_stream(&self) -> Result<QuicStream> {
+    let (send, recv) = self
+      .connection
+      .accept_bi()
+      .await
+      .map_err(|e| crate::Error::Network(format!("Accept stream failed: {}", e)))?;
+    Ok(self.stream(send, recv))
   }
+
+  fn stream(&self, send: quinn::SendStream, recv: quinn::RecvStream) -> QuicStream {
+    QuicStream {
+      send,
+      recv,
+      address: self.address,
+      peer: self.peer.clone(),
+      protocol_version: self.protocol_version,
+      encryption: None,
+      limits: ConnectionLimits::default(),
+    }
+  }
+
+  /// 对端身份（来自已校验的证书）
+  pub fn peer(&self) -> &QuicPeer {
+    &self.peer
+  }
+
+  /// 获取远程地址
+  pub fn address(&self) -> &SocketAddr {
+    &self.address
+  }
+
+  /// TLS 握手中通过 ALPN 协商出的传输协议版本
+  pub fn protocol_version(&self) -> u32 {
+    self.protocol_version
+  }
+
+  /// 连接是否已关闭
+  pub fn is_closed(&self) -> bool {
+    self.connection.close_reason().is_some()
+  }
+
+  /// 关闭连接（所有流随之关闭）
+  pub fn close(&self) {
+    self.connection.close(0u32.into(), b"closed");
+    info!("QUIC connection to {} closed", self.address);
+  }
+}
+
+/// QUIC 双向流，帧格式与 TCP 连接相同（4 字节长度 + 数据）
+pub struct QuicStream {
+  send: quinn::SendStream,
+  recv: quinn::RecvStream,
+  address: SocketAddr,
+  peer: QuicPeer,
+  protocol_version: u32,
+  encryption: Option<Encryption>, // 握手完成后启用，之后所有帧都会加密
+  limits: ConnectionLimits,
 }
 
-impl crate::p2p::Connection for QuicConnection {
-  fn connect(&mut self) -> Result<()> {
-    // TODO: 实现 QUIC 连接
+impl QuicStream {
+  /// 发送数据
+  pub async fn send(&mut self, data: &[u8]) -> Result<()> {
+    let sealed;
+    let data = if let Some(ref mut encryption) = self.encryption {
+      sealed = encryption.encrypt(data)?;
+      sealed.as_slice()
+    } else {
+      data
+    };
+
+    let len = data.len() as u32;
+    self
+      .send
+      .write_all(&len.to_be_bytes())
+      .await
+      .map_err(|e| crate::Error::Network(format!("Write length failed: {}", e)))?;
+    self
+      .send
+      .write_all(data)
+      .await
+      .map_err(|e| crate::Error::Network(format!("Write data failed: {}", e)))?;
     Ok(())
   }
 
-  fn send(&mut self, _data: &[u8]) -> Result<()> {
-    // TODO: 实现 QUIC 发送
-    Ok(())
+  /// 设置接收限制
+  pub fn set_limits(&mut self, limits: ConnectionLimits) {
+    self.limits = limits;
+  }
+
+  /// 接收数据
+  pub async fn receive(&mut self) -> Result<Vec<u8>> {
+    let limits = self.limits;
+
+    // 连接本身有保活，这里限制的是流上没有新帧的时间
+    let mut len = [0u8; 4];
+    tokio::time::timeout(limits.idle_timeout, self.recv.read_exact(&mut len))
+      .await
+      .map_err(|_| {
+        crate::Error::Network(format!(
+          "Read timed out: no data for {:?}",
+          limits.idle_timeout
+        ))
+      })?
+      .map_err(|e| crate::Error::Network(format!("Read length failed: {}", e)))?;
+
+    // 分配缓冲区之前先校验长度
+    let len = u32::from_be_bytes(len) as usize;
+    limits.check_frame_size(len, self.encryption.is_some())?;
+
+    let mut data = vec![0u8; len];
+    tokio::time::timeout(limits.read_timeout, self.recv.read_exact(&mut data))
+      .await
+      .map_err(|_| {
+        crate::Error::Network(format!(
+          "Read data timed out after {:?}",
+          limits.read_timeout
+        ))
+      })?
+      .map_err(|e| crate::Error::Network(format!("Read data failed: {}", e)))?;
+
+    transport::open_frame(self.encryption.as_mut(), data)
+  }
+
+  /// 结束发送方向，等待对端确认收到全部数据
+  pub async fn finish(&mut self) -> Result<()> {
+    self
+      .send
+      .finish()
+      .await
+      .map_err(|e| crate::Error::Network(format!("Finish stream failed: {}", e)))
+  }
+
+  /// 获取远程地址
+  pub fn address(&self) -> &SocketAddr {
+    &self.address
+  }
+
+  /// 所属连接协商出的传输协议版本
+  pub fn protocol_version(&self) -> u32 {
+    self.protocol_version
   }
 
-  fn receive(&mut self) -> Result<Vec<u8>> {
-    // TODO: 实现 QUIC 接收
-    Ok(vec![])
+  /// 对端身份（来自所属连接已校验的证书）
+  pub fn peer(&self) -> &QuicPeer {
+    &self.peer
   }
+}
 
-  fn close(&mut self) -> Result<()> {
-    // TODO: 实现 QUIC 关闭
+impl FramedConnection for QuicStream {
+  async fn send_frame(&mut self, data: &[u8]) -> Result<()> {
+    self.send(data).await
+  }
+
+  async fn recv_frame(&mut self) -> Result<Vec<u8>> {
+    self.receive().await
+  }
+
+  async fn close(&mut self) -> Result<()> {
+    // 只结束本流，连接上的其他流不受影响。对端读完后可能先丢弃了自己的一端（STOP_SENDING），
+    // 此时对端已不再需要数据，视为正常关闭
+    match self.send.finish().await {
+      Ok(()) | Err(quinn::WriteError::Stopped(_)) => Ok(()),
+      Err(e) => Err(crate::Error::Network(format!(
+        "Finish stream failed: {}",
+        e
+      ))),
+    }
+  }
+
+  fn peer_addr(&self) -> SocketAddr {
+    self.address
+  }
+
+  fn set_encryption(&mut self, encryption: Encryption) {
+    self.encryption = Some(encryption);
+  }
+
+  /// 握手中声明的设备 ID 和长期公钥必须与证书中的一致
+  fn verify_peer(&self, peer: &PeerIdentity) -> Result<()> {
+    if peer.device_id != self.peer.device_id || peer.public_key != self.peer.static_public_key {
+      return Err(crate::Error::ProtocolViolation(format!(
+        "Handshake identity {} does not match QUIC certificate {}",
+        peer.device_id, self.peer.device_id
+      )));
+    }
     Ok(())
   }
 }
+
+/// QUIC 传输
+///
+/// 到同一对端的出站连接会被复用，每次 `connect` 只在连接上打开一个新的流；
+/// 入站连接上对端打开的每个流都作为一个独立的连接交给 `accept`。
+/// 入站连接在 TLS 握手前按来源限制器检查来源，被封禁或连接数已达上限的对端直接被丢弃
+pub struct QuicTransport {
+  endpoint: Arc<QuicEndpoint>,
+  connections: Mutex<HashMap<SocketAddr, QuicConnection>>,
+  incoming: tokio::sync::Mutex<mpsc::Receiver<QuicStream>>,
+  accept_task: JoinHandle<()>,
+  limits: ConnectionLimits,
+  /// 入站连接的来源限制器（与传输服务共享时，处理流时被封禁的对端也无法再建立连接）
+  limiter: Arc<Mutex<PeerLimiter>>,
+}
+
+impl QuicTransport {
+  /// 在指定 UDP 端口上创建传输
+  pub fn bind(port: u16, identity: &DeviceIdentity) -> Result<Self> {
+    Ok(Self::from_endpoint(QuicEndpoint::bind(port, identity)?))
+  }
+
+  /// 使用已创建的端点
+  pub fn from_endpoint(endpoint: QuicEndpoint) -> Self {
+    let endpoint = Arc::new(endpoint);
+    let (streams, incoming) = mpsc::channel(INCOMING_STREAM_CAPACITY);
+    let limiter = Arc::new(Mutex::new(PeerLimiter::default()));
+    let accept_task = tokio::spawn(Self::accept_loop(
+      endpoint.clone(),
+      streams,
+      limiter.clone(),
+    ));
+    Self {
+      endpoint,
+      connections: Mutex::new(HashMap::new()),
+      incoming: tokio::sync::Mutex::new(incoming),
+      accept_task,
+      limits: ConnectionLimits::default(),
+      limiter,
+    }
+  }
+
+  /// 设置之后建立的流的接收限制
+  pub fn set_limits(&mut self, limits: ConnectionLimits) {
+    self.limits = limits;
+  }
+
+  /// 接受入站连接，并把每个连接上的流转交给 `accept`
+  ///
+  /// 每个连接在独立的任务中完成 TLS 握手，握手缓慢的对端不会阻塞其他连接
+  async fn accept_loop(
+    endpoint: Arc<QuicEndpoint>,
+    streams: mpsc::Sender<QuicStream>,
+    limiter: Arc<Mutex<PeerLimiter>>,
+  ) {
+    while let Some(connecting) = endpoint.endpoint.accept().await {
+      let limiter = limiter.lock().unwrap().clone();
+      // 被拒绝的连接在握手前直接丢弃（关闭）
+      let Some(slot) = limiter.admit_connection(connecting.remote_address().ip()) else {
+        continue;
+      };
+      let streams = streams.clone();
+      tokio::spawn(async move {
+        match QuicEndpoint::establish(connecting).await {
+          Ok(connection) => Self::accept_streams(connection, streams, slot).await,
+          Err(e) => warn!("Accept QUIC connection error: {}", e),
+        }
+      });
+    }
+  }
+
+  /// 接受一个连接上的所有流，直到连接关闭或来源被封禁
+  async fn accept_streams(
+    connection: QuicConnection,
+    streams: mpsc::Sender<QuicStream>,
+    slot: ConnectionSlot,
+  ) {
+    while let Ok(stream) = connection.accept_stream().await {
+      if slot.is_blocked() {
+        warn!("Closing QUIC connection from blocked peer {}", slot.ip());
+        connection.close();
+        break;
+      }
+      if streams.send(stream).await.is_err() {
+        break;
+      }
+    }
+  }
+
+  /// 获取到对端的可用连接（已关闭或设备 ID 不符的连接会被移除）
+  fn cached_connection(
+    &self,
+    addr: &SocketAddr,
+    expected_device_id: Option<&str>,
+  ) -> Option<QuicConnection> {
+    let mut connections = self.connections.lock().unwrap();
+    let usable = connections.get(addr).is_some_and(|connection| {
+      !connection.is_closed()
+        && expected_device_id.is_none_or(|expected| expected == connection.peer.device_id)
+    });
+    if usable {
+      connections.get(addr).cloned()
+    } else {
+      connections.remove(addr);
+      None
+    }
+  }
+
+  /// 打开到对端的流
+  ///
+  /// 指定 `expected_device_id` 时，对端证书对应的设备 ID 必须一致，否则返回 `Error::Crypto`
+  pub async fn connect_to(
+    &self,
+    address: &str,
+    port: u16,
+    expected_device_id: Option<&str>,
+  ) -> Result<QuicStream> {
+    let addr = addr::parse_socket_addr(address, port)?;
+
+    if let Some(connection) = self.cached_connection(&addr, expected_device_id)
+      && let Ok(mut stream) = connection.open_stream().await
+    {
+      stream.set_limits(self.limits);
+      return Ok(stream);
+    }
+
+    let connection = self
+      .endpoint
+      .connect(address, port, expected_device_id)
+      .await?;
+    let mut stream = connection.open_stream().await?;
+    stream.set_limits(self.limits);
+    self.connections.lock().unwrap().insert(addr, connection);
+    Ok(stream)
+  }
+}
+
+impl Transport for QuicTransport {
+  type Connection = QuicStream;
+
+  async fn connect(&self, address: &str, port: u16) -> Result<QuicStream> {
+    self.connect_to(address, port, None).await
+  }
+
+  async fn accept(&self) -> Result<QuicStream> {
+    let mut stream = self
+      .incoming
+      .lock()
+      .await
+      .recv()
+      .await
+      .ok_or_else(|| crate::Error::Network("QUIC endpoint closed".to_string()))?;
+    stream.set_limits(self.limits);
+    Ok(stream)
+  }
+
+  fn local_addr(&self) -> Result<SocketAddr> {
+    self.endpoint.local_addr()
+  }
+
+  fn set_peer_limiter(&self, limiter: PeerLimiter) {
+    *self.limiter.lock().unwrap() = limiter;
+  }
+}
+
+impl Drop for QuicTransport {
+  fn drop(&mut self) {
+    self.accept_task.abort();
+    self.endpoint.endpoint.close(0u32.into(), b"closed");
+  }
+}
@@ -1,6 +1,9 @@
 //! TCP 协议实现
 
 use crate::Result;
+use crate::crypto::Encryption;
+use crate::p2p::addr;
+use crate::p2p::transport::{self, ConnectionLimits, FramedConnection, Transport};
 use std::net::SocketAddr;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
@@ -10,25 +13,15 @@ use tracing::info;
 pub struct TcpConnection {
   stream: Option<TcpStream>,
   address: SocketAddr,
+  encryption: Option<Encryption>, // 握手完成后启用，之后所有帧都会加密
+  limits: ConnectionLimits,
 }
 
 impl TcpConnection {
   /// 创建新的 TCP 连接（客户端）
   pub async fn connect(address: &str, port: u16) -> Result<Self> {
-    // 验证地址不为空
-    let address = address.trim();
-    if address.is_empty() {
-      return Err(crate::Error::Network(
-        "Invalid address: address cannot be empty".to_string(),
-      ));
-    }
-
-    // 尝试解析为 SocketAddr
-    let addr = format!("{}:{}", address, port)
-      .parse::<SocketAddr>()
-      .map_err(|e| {
-        crate::Error::Network(format!("Invalid address: '{}:{}' - {}", address, port, e))
-      })?;
+    // 解析地址（支持 IPv6 及链路本地地址的作用域）
+    let addr = addr::parse_socket_addr(address, port)?;
 
     info!("Connecting to {}", addr);
     let stream = TcpStream::connect(addr)
@@ -40,19 +33,19 @@ impl TcpConnection {
     Ok(Self {
       stream: Some(stream),
       address: addr,
+      encryption: None,
+      limits: ConnectionLimits::default(),
     })
   }
 
-  /// 创建 TCP 服务器
+  /// 创建 TCP 服务器（同时接受 IPv4 和 IPv6 连接）
   pub async fn listen(port: u16) -> Result<TcpListener> {
-    let addr = format!("0.0.0.0:{}", port)
-      .parse::<SocketAddr>()
-      .map_err(|e| crate::Error::Network(format!("Invalid address: {}", e)))?;
-
-    let listener = TcpListener::bind(addr)
-      .await
+    let listener = TcpListener::from_std(addr::bind_tcp(port)?)
       .map_err(|e| crate::Error::Network(format!("Bind failed: {}", e)))?;
 
+    let addr = listener
+      .local_addr()
+      .map_err(|e| crate::Error::Network(format!("Get local address failed: {}", e)))?;
     info!("TCP server listening on {}", addr);
     Ok(listener)
   }
@@ -63,17 +56,45 @@ impl TcpConnection {
       .accept()
       .await
       .map_err(|e| crate::Error::Network(format!("Accept failed: {}", e)))?;
+    let addr = addr::canonical(addr);
 
     info!("Accepted connection from {}", addr);
 
     Ok(Self {
       stream: Some(stream),
       address: addr,
+      encryption: None,
+      limits: ConnectionLimits::default(),
     })
   }
 
+  /// 启用帧加密
+  ///
+  /// 之后 `send` 发送的每一帧都会被加密，`receive` 收到的每一帧都会被解密校验
+  pub fn set_encryption(&mut self, encryption: Encryption) {
+    self.encryption = Some(encryption);
+  }
+
+  /// 设置接收限制
+  pub fn set_limits(&mut self, limits: ConnectionLimits) {
+    self.limits = limits;
+  }
+
+  /// 是否已启用帧加密
+  pub fn is_encrypted(&self) -> bool {
+    self.encryption.is_some()
+  }
+
   /// 发送数据
   pub async fn send(&mut self, data: &[u8]) -> Result<()> {
+    let sealed;
+    let data = if let Some(ref mut encryption) = self.encryption {
+      sealed = encryption.encrypt(data)?;
+      sealed.as_slice()
+    } else {
+      data
+    };
+
     if let Some(ref mut stream) = self.stream {
       // 先发送数据长度（4 字节）
       let len = data.len() as u32;
@@ -104,20 +125,36 @@ impl TcpConnection {
   /// 接收数据
   pub async fn receive(&mut self) -> Result<Vec<u8>> {
     if let Some(ref mut stream) = self.stream {
-      // 先读取数据长度
-      let len = stream
-        .read_u32()
+      let limits = self.limits;
+
+      // 先读取数据长度，超过空闲时间仍未收到下一帧时断开
+      let len = tokio::time::timeout(limits.idle_timeout, stream.read_u32())
         .await
-        .map_err(|e| crate::Error::Network(format!("Read length failed: {}", e)))?;
+        .map_err(|_| {
+          crate::Error::Network(format!(
+            "Read timed out: no data for {:?}",
+            limits.idle_timeout
+          ))
+        })?
+        .map_err(|e| crate::Error::Network(format!("Read length failed: {}", e)))?
+        as usize;
+
+      // 分配缓冲区之前先校验长度，避免对端用帧头让本端分配超大内存
+      limits.check_frame_size(len, self.encryption.is_some())?;
 
       // 读取数据
-      let mut data = vec![0u8; len as usize];
-      stream
-        .read_exact(&mut data)
+      let mut data = vec![0u8; len];
+      tokio::time::timeout(limits.read_timeout, stream.read_exact(&mut data))
         .await
+        .map_err(|_| {
+          crate::Error::Network(format!(
+            "Read data timed out after {:?}",
+            limits.read_timeout
+          ))
+        })?
         .map_err(|e| crate::Error::Network(format!("Read data failed: {}", e)))?;
 
-      Ok(data)
+      transport::open_frame(self.encryption.as_mut(), data)
     } else {
       Err(crate::Error::Network(
         "Connection not established".to_string(),
@@ -140,29 +177,128 @@ impl TcpConnection {
   }
 }
 
-impl crate::p2p::Connection for TcpConnection {
-  fn connect(&mut self) -> Result<()> {
-    // 异步方法，需要在 async 上下文中调用
-    Err(crate::Error::Network(
-      "Use TcpConnection::connect() instead".to_string(),
-    ))
+impl FramedConnection for TcpConnection {
+  async fn send_frame(&mut self, data: &[u8]) -> Result<()> {
+    self.send(data).await
+  }
+
+  async fn recv_frame(&mut self) -> Result<Vec<u8>> {
+    self.receive().await
+  }
+
+  async fn close(&mut self) -> Result<()> {
+    TcpConnection::close(self)
   }
 
-  fn send(&mut self, _data: &[u8]) -> Result<()> {
-    // 异步方法，需要在 async 上下文中调用
-    Err(crate::Error::Network(
-      "Use TcpConnection::send() instead".to_string(),
-    ))
+  fn peer_addr(&self) -> SocketAddr {
+    self.address
+  }
+
+  fn set_encryption(&mut self, encryption: Encryption) {
+    TcpConnection::set_encryption(self, encryption);
+  }
+}
+
+/// TCP 传输（未监听时只能建立出站连接）
+pub struct TcpTransport {
+  listener: Option<TcpListener>,
+  limits: ConnectionLimits,
+}
+
+impl TcpTransport {
+  /// 创建只用于出站连接的传输
+  pub fn new() -> Self {
+    Self {
+      listener: None,
+      limits: ConnectionLimits::default(),
+    }
+  }
+
+  /// 在指定端口上监听
+  pub async fn bind(port: u16) -> Result<Self> {
+    Ok(Self::from_listener(TcpConnection::listen(port).await?))
+  }
+
+  /// 使用已绑定的监听器
+  pub fn from_listener(listener: TcpListener) -> Self {
+    Self {
+      listener: Some(listener),
+      limits: ConnectionLimits::default(),
+    }
+  }
+
+  /// 设置之后建立的连接的接收限制
+  pub fn set_limits(&mut self, limits: ConnectionLimits) {
+    self.limits = limits;
+  }
+}
+
+impl Default for TcpTransport {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Transport for TcpTransport {
+  type Connection = TcpConnection;
+
+  async fn connect(&self, address: &str, port: u16) -> Result<TcpConnection> {
+    let mut connection = TcpConnection::connect(address, port).await?;
+    connection.set_limits(self.limits);
+    Ok(connection)
+  }
+
+  async fn accept(&self) -> Result<TcpConnection> {
+    let mut connection = match self.listener {
+      Some(ref listener) => TcpConnection::accept(listener).await?,
+      None => {
+        return Err(crate::Error::Network(
+          "TCP transport is not listening".to_string(),
+        ));
+      }
+    };
+    connection.set_limits(self.limits);
+    Ok(connection)
+  }
+
+  fn local_addr(&self) -> Result<SocketAddr> {
+    self
+      .listener
+      .as_ref()
+      .ok_or_else(|| crate::Error::Network("TCP transport is not listening".to_string()))?
+      .local_addr()
+      .map_err(|e| crate::Error::Network(format!("Get local address failed: {}", e)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  async fn connection_pair() -> (TcpConnection, TcpConnection) {
+    let listener = TcpConnection::listen(0).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (client, server) = tokio::join!(
+      TcpConnection::connect("127.0.0.1", port),
+      TcpConnection::accept(&listener),
+    );
+    (client.unwrap(), server.unwrap())
   }
 
-  fn receive(&mut self) -> Result<Vec<u8>> {
-    // 异步方法，需要在 async 上下文中调用
-    Err(crate::Error::Network(
-      "Use TcpConnection::receive() instead".to_string(),
-    ))
+  #[tokio::test]
+  async fn frames_round_trip() {
+    let (mut client, mut server) = connection_pair().await;
+    client.send(b"hello").await.unwrap();
+    client.send(b"").await.unwrap();
+    assert_eq!(server.receive().await.unwrap(), b"hello");
+    assert_eq!(server.receive().await.unwrap(), b"");
   }
 
-  fn close(&mut self) -> Result<()> {
-    self.close()
+  #[tokio::test]
+  async fn rejects_oversized_frame_before_handshake() {
+    let (mut client, mut server) = connection_pair().await;
+    let frame = vec![0u8; crate::p2p::transport::DEFAULT_MAX_HANDSHAKE_FRAME_SIZE + 1];
+    let (_, received) = tokio::join!(client.send(&frame), server.receive());
+    assert!(matches!(received, Err(crate::Error::ProtocolViolation(_))));
   }
 }
@@ -0,0 +1,223 @@
+//! 传输抽象
+//!
+//! `FramedConnection` 是一条按帧收发的双向连接，`Transport` 负责建立和接受这种连接。
+//! 文件传输和屏幕投影只依赖这两个 trait，因此可以运行在 TCP、QUIC 或内存回环之上
+
+use crate::Result;
+use crate::crypto::{Encryption, PeerIdentity};
+use crate::p2p::limiter::PeerLimiter;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 默认的单帧最大长度（加密后的长度，需大于文件分片和屏幕投影帧）
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// 默认的握手阶段单帧最大长度（会话握手完成之前对端尚未认证，只需容纳握手消息）
+pub const DEFAULT_MAX_HANDSHAKE_FRAME_SIZE: usize = 64 * 1024;
+
+/// 默认的帧读取超时：收到帧长度后，必须在该时间内收完整帧
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 默认的空闲超时：等待下一帧开始的最长时间
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// 连接的接收限制，防止对端用超大帧耗尽内存或长期占用连接
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLimits {
+  /// 单帧最大长度（字节），超过时视为违反协议
+  pub max_frame_size: usize,
+  /// 启用帧加密之前的单帧最大长度（字节）
+  pub max_handshake_frame_size: usize,
+  /// 帧读取超时
+  pub read_timeout: Duration,
+  /// 空闲超时
+  pub idle_timeout: Duration,
+}
+
+impl Default for ConnectionLimits {
+  fn default() -> Self {
+    Self {
+      max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+      max_handshake_frame_size: DEFAULT_MAX_HANDSHAKE_FRAME_SIZE,
+      read_timeout: DEFAULT_READ_TIMEOUT,
+      idle_timeout: DEFAULT_IDLE_TIMEOUT,
+    }
+  }
+}
+
+impl ConnectionLimits {
+  /// 校验对端声明的帧长度
+  ///
+  /// `encrypted` 为连接是否已启用帧加密，此前对端尚未认证，只接受握手消息大小的帧
+  pub fn check_frame_size(&self, len: usize, encrypted: bool) -> Result<()> {
+    let max = if encrypted {
+      self.max_frame_size
+    } else {
+      self.max_handshake_frame_size.min(self.max_frame_size)
+    };
+    if len > max {
+      return Err(crate::Error::ProtocolViolation(format!(
+        "Frame too large: {} bytes (max {})",
+        len, max
+      )));
+    }
+    Ok(())
+  }
+}
+
+/// 按帧收发的双向连接
+pub trait FramedConnection: Send + Sync + 'static {
+  /// 发送一帧
+  fn send_frame(&mut self, data: &[u8]) -> impl Future<Output = Result<()>> + Send;
+
+  /// 接收一帧
+  fn recv_frame(&mut self) -> impl Future<Output = Result<Vec<u8>>> + Send;
+
+  /// 关闭连接
+  fn close(&mut self) -> impl Future<Output = Result<()>> + Send;
+
+  /// 对端地址
+  fn peer_addr(&self) -> SocketAddr;
+
+  /// 启用帧加密
+  ///
+  /// 会话握手完成后调用，之后发送的每一帧都会被加密，收到的每一帧都会被解密校验
+  fn set_encryption(&mut self, encryption: Encryption);
+
+  /// 校验握手中对端声明的身份与传输层已认证的身份一致
+  ///
+  /// 传输层本身不认证对端时（如 TCP）不做检查
+  fn verify_peer(&self, _peer: &PeerIdentity) -> Result<()> {
+    Ok(())
+  }
+}
+
+/// 解密收到的帧（未启用加密时原样返回）
+///
+/// 连接上认证失败的帧说明对端篡改、重放或乱序发送，按违反协议处理，传输服务据此封禁对端
+pub(crate) fn open_frame(encryption: Option<&mut Encryption>, data: Vec<u8>) -> Result<Vec<u8>> {
+  match encryption {
+    Some(encryption) => encryption.decrypt(&data).map_err(|e| match e {
+      crate::Error::Crypto(reason) => crate::Error::ProtocolViolation(reason),
+      e => e,
+    }),
+    None => Ok(data),
+  }
+}
+
+/// 传输层：建立出站连接、接受入站连接
+pub trait Transport: Send + Sync + 'static {
+  type Connection: FramedConnection;
+
+  /// 连接到目标设备
+  fn connect(
+    &self,
+    address: &str,
+    port: u16,
+  ) -> impl Future<Output = Result<Self::Connection>> + Send;
+
+  /// 接受下一个入站连接（未监听时返回错误）
+  fn accept(&self) -> impl Future<Output = Result<Self::Connection>> + Send;
+
+  /// 本地监听地址
+  fn local_addr(&self) -> Result<SocketAddr>;
+
+  /// 设置入站连接的来源限制器
+  ///
+  /// 传输服务为每个交给处理函数的连接占用名额；自己接受底层连接的传输（例如 QUIC）
+  /// 应在接受底层连接时也按该限制器检查来源
+  fn set_peer_limiter(&self, _limiter: PeerLimiter) {}
+}
+
+/// 共享的传输层（例如同一个端点既用于服务端接受连接，又用于发起连接）
+impl<T: Transport> Transport for Arc<T> {
+  type Connection = T::Connection;
+
+  fn connect(
+    &self,
+    address: &str,
+    port: u16,
+  ) -> impl Future<Output = Result<Self::Connection>> + Send {
+    (**self).connect(address, port)
+  }
+
+  fn accept(&self) -> impl Future<Output = Result<Self::Connection>> + Send {
+    (**self).accept()
+  }
+
+  fn local_addr(&self) -> Result<SocketAddr> {
+    (**self).local_addr()
+  }
+
+  fn set_peer_limiter(&self, limiter: PeerLimiter) {
+    (**self).set_peer_limiter(limiter)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn handshake_frames_use_smaller_limit() {
+    let limits = ConnectionLimits::default();
+    assert!(
+      limits
+        .check_frame_size(DEFAULT_MAX_HANDSHAKE_FRAME_SIZE, false)
+        .is_ok()
+    );
+    assert!(matches!(
+      limits.check_frame_size(DEFAULT_MAX_HANDSHAKE_FRAME_SIZE + 1, false),
+      Err(crate::Error::ProtocolViolation(_))
+    ));
+    assert!(
+      limits
+        .check_frame_size(DEFAULT_MAX_HANDSHAKE_FRAME_SIZE + 1, true)
+        .is_ok()
+    );
+  }
+
+  #[test]
+  fn encrypted_frames_use_max_frame_size() {
+    let limits = ConnectionLimits::default();
+    assert!(
+      limits
+        .check_frame_size(DEFAULT_MAX_FRAME_SIZE, true)
+        .is_ok()
+    );
+    assert!(matches!(
+      limits.check_frame_size(DEFAULT_MAX_FRAME_SIZE + 1, true),
+      Err(crate::Error::ProtocolViolation(_))
+    ));
+    assert!(matches!(
+      limits.check_frame_size(u32::MAX as usize, true),
+      Err(crate::Error::ProtocolViolation(_))
+    ));
+  }
+
+  #[test]
+  fn handshake_limit_never_exceeds_max_frame_size() {
+    let limits = ConnectionLimits {
+      max_frame_size: 1024,
+      ..Default::default()
+    };
+    assert!(limits.check_frame_size(1024, false).is_ok());
+    assert!(limits.check_frame_size(1025, false).is_err());
+  }
+
+  #[test]
+  fn unauthenticated_frames_are_protocol_violations() {
+    let (a, b) = ([1u8; 32], [2u8; 32]);
+    let mut sender = Encryption::new(&a, &b).unwrap();
+    let mut receiver = Encryption::new(&b, &a).unwrap();
+    let mut frame = sender.encrypt(b"frame").unwrap();
+    frame[0] ^= 1;
+    assert!(matches!(
+      open_frame(Some(&mut receiver), frame),
+      Err(crate::Error::ProtocolViolation(_))
+    ));
+    assert_eq!(open_frame(None, b"plain".to_vec()).unwrap(), b"plain");
+  }
+}
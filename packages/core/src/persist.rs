@@ -0,0 +1,203 @@
+//! 本地持久化
+//!
+//! 设备身份、受信任设备、断点续传检查点和传输历史都保存为 JSON 文件。
+//! 写入时先写临时文件再重命名，写到一半时崩溃也不会损坏原文件
+
+use crate::Result;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 读取 JSON 文件，文件不存在时返回 `None`
+///
+/// `what` 为文件内容的名称，用于错误信息
+pub(crate) fn load_json<T: DeserializeOwned>(path: &Path, what: &str) -> Result<Option<T>> {
+  let data = match std::fs::read(path) {
+    Ok(data) => data,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => return Err(crate::Error::File(format!("Read {} failed: {}", what, e))),
+  };
+  let value = serde_json::from_slice(&data)
+    .map_err(|e| crate::Error::File(format!("Parse {} failed: {}", what, e)))?;
+  Ok(Some(value))
+}
+
+/// 以原子方式写入 JSON 文件（在阻塞线程池中执行）
+pub(crate) async fn save_json_atomic<T: Serialize + ?Sized>(
+  path: &Path,
+  value: &T,
+  what: &str,
+) -> Result<()> {
+  let data = to_json(value, what)?;
+  let path = path.to_path_buf();
+  let label = what.to_string();
+  tokio::task::spawn_blocking(move || write_atomic(&path, &data, &label, false))
+    .await
+    .map_err(|e| crate::Error::File(format!("Write {} failed: {}", what, e)))?
+}
+
+/// 以原子方式同步写入只允许当前用户读写的 JSON 文件（用于保存私钥）
+pub(crate) fn save_private_json_atomic<T: Serialize + ?Sized>(
+  path: &Path,
+  value: &T,
+  what: &str,
+) -> Result<()> {
+  write_atomic(path, &to_json(value, what)?, what, true)
+}
+
+/// 创建只允许当前用户读写的 JSON 文件，文件已存在时不覆盖并返回 `false`
+///
+/// 内容先完整写入临时文件再以硬链接放到目标路径，其他进程不会读到写了一半的文件，
+/// 同时创建时只有一方成功
+pub(crate) fn create_private_json<T: Serialize + ?Sized>(
+  path: &Path,
+  value: &T,
+  what: &str,
+) -> Result<bool> {
+  let to_error = |e: std::io::Error| crate::Error::File(format!("Write {} failed: {}", what, e));
+  let tmp_path = write_temp(path, &to_json(value, what)?, true).map_err(to_error)?;
+  let result = std::fs::hard_link(&tmp_path, path);
+  let _ = std::fs::remove_file(&tmp_path);
+  match result {
+    Ok(()) => Ok(true),
+    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+    Err(e) => Err(to_error(e)),
+  }
+}
+
+/// 字节转十六进制字符串
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn to_json<T: Serialize + ?Sized>(value: &T, what: &str) -> Result<Vec<u8>> {
+  serde_json::to_vec_pretty(value)
+    .map_err(|e| crate::Error::File(format!("Serialize {} failed: {}", what, e)))
+}
+
+fn write_atomic(path: &Path, data: &[u8], what: &str, private: bool) -> Result<()> {
+  let to_error = |e: std::io::Error| crate::Error::File(format!("Write {} failed: {}", what, e));
+  let tmp_path = write_temp(path, data, private).map_err(to_error)?;
+  std::fs::rename(&tmp_path, path).map_err(|e| {
+    let _ = std::fs::remove_file(&tmp_path);
+    to_error(e)
+  })
+}
+
+/// 把内容写入目标文件旁的新临时文件并刷到磁盘，返回临时文件路径
+///
+/// 临时文件名带有进程号和序号，并发写入同一文件时互不干扰；
+/// `private` 为 true 时文件在创建时就只允许当前用户读写
+fn write_temp(path: &Path, data: &[u8], private: bool) -> std::io::Result<PathBuf> {
+  static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+  tmp_name.push(format!(
+    ".{}.{}.tmp",
+    std::process::id(),
+    NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+  ));
+  let tmp_path = path.with_file_name(tmp_name);
+
+  let mut options = OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  if private {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+  }
+  #[cfg(not(unix))]
+  let _ = private;
+
+  let result = options.open(&tmp_path).and_then(|mut file| {
+    file.write_all(data)?;
+    file.sync_all()
+  });
+  match result {
+    Ok(()) => Ok(tmp_path),
+    Err(e) => {
+      let _ = std::fs::remove_file(&tmp_path);
+      Err(e)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+      "stationuli-persist-{}-{}",
+      name,
+      std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  #[test]
+  fn concurrent_writers_do_not_collide() {
+    let dir = test_dir("concurrent");
+    let path = dir.join("data.json");
+    std::thread::scope(|scope| {
+      for value in 0..8u32 {
+        let path = &path;
+        scope.spawn(move || write_atomic(path, &to_json(&value, "data").unwrap(), "data", false));
+      }
+    });
+
+    let value: u32 = load_json(&path, "data").unwrap().unwrap();
+    assert!(value < 8);
+    // 只剩下目标文件，没有残留的临时文件
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn create_private_json_does_not_overwrite() {
+    let dir = test_dir("create");
+    let path = dir.join("identity.json");
+    assert!(create_private_json(&path, &1u32, "data").unwrap());
+    assert!(!create_private_json(&path, &2u32, "data").unwrap());
+    assert_eq!(load_json::<u32>(&path, "data").unwrap(), Some(1));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+      assert_eq!(mode & 0o777, 0o600);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn private_files_are_never_readable_by_others() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = test_dir("private");
+    let path = dir.join("identity.json");
+    let tmp_path = write_temp(&path, b"{}", true).unwrap();
+    let mode = std::fs::metadata(&tmp_path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn missing_file_loads_as_none() {
+    let dir = test_dir("missing");
+    assert_eq!(
+      load_json::<u32>(&dir.join("none.json"), "data").unwrap(),
+      None
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}
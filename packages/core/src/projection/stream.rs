@@ -1,32 +1,93 @@
 //! 投影流传输模块
+//!
+//! 投影流可以运行在任意传输层之上；使用 QUIC 时与文件传输各占一个流，互不阻塞
 
 use crate::Result;
-use crate::p2p::tcp::TcpConnection;
+use crate::crypto::{Encryption, Handshake, LocalPeer, SessionKeys, TrustStore};
+use crate::p2p::tcp::TcpTransport;
+use crate::p2p::transport::{FramedConnection, Transport};
 use crate::projection::{ProjectionConfig, ProjectionFrame};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tokio::time::{Duration, interval};
 
 /// 投影流
-pub struct ProjectionStream {
-  connection: Arc<RwLock<Option<TcpConnection>>>,
+pub struct ProjectionStream<T: Transport = TcpTransport> {
+  transport: T,
+  connection: Arc<RwLock<Option<T::Connection>>>,
   config: ProjectionConfig,
   is_streaming: Arc<RwLock<bool>>,
+  local_peer: LocalPeer,
+  trust_store: Arc<TrustStore>,
 }
 
 impl ProjectionStream {
-  /// 创建新的投影流
+  /// 创建新的投影流（通过 TCP 连接）
   pub fn new(config: ProjectionConfig) -> Self {
+    Self::with_transport(TcpTransport::new(), config)
+  }
+}
+
+impl<T: Transport> ProjectionStream<T> {
+  /// 使用指定的传输层创建投影流
+  pub fn with_transport(transport: T, config: ProjectionConfig) -> Self {
     Self {
+      transport,
       connection: Arc::new(RwLock::new(None)),
       config,
       is_streaming: Arc::new(RwLock::new(false)),
+      local_peer: LocalPeer::generate(
+        "unknown".to_string(),
+        "stationuli-device".to_string(),
+        "unknown".to_string(),
+      ),
+      trust_store: Arc::new(TrustStore::in_memory()),
     }
   }
 
-  /// 连接到目标设备
+  /// 设置本端身份（握手时使用）
+  pub fn set_local_peer(&mut self, local_peer: LocalPeer) {
+    self.local_peer = local_peer;
+  }
+
+  /// 设置受信任设备存储（只与已配对的设备建立投影流）
+  pub fn set_trust_store(&mut self, trust_store: Arc<TrustStore>) {
+    self.trust_store = trust_store;
+  }
+
+  /// 连接到已配对的目标设备
   pub async fn connect(&mut self, address: &str, port: u16) -> Result<()> {
-    let connection = TcpConnection::connect(address, port).await?;
+    let mut connection = self.transport.connect(address, port).await?;
+    let session = Handshake::new(&self.local_peer)
+      .initiate(&mut connection)
+      .await?;
+    self.establish(connection, &session).await
+  }
+
+  /// 接受已配对设备的连接（传输层需要处于监听状态）
+  pub async fn accept(&mut self) -> Result<()> {
+    let mut connection = self.transport.accept().await?;
+    let session = Handshake::new(&self.local_peer)
+      .respond(&mut connection)
+      .await?;
+    self.establish(connection, &session).await
+  }
+
+  /// 确认对端已配对后启用帧加密；对端未配对时关闭连接
+  async fn establish(
+    &mut self,
+    mut connection: T::Connection,
+    session: &SessionKeys,
+  ) -> Result<()> {
+    if !self.trust_store.is_trusted(&session.peer).await {
+      connection.close().await?;
+      return Err(crate::Error::Crypto(format!(
+        "Device not paired: {} ({})",
+        session.peer.device_name, session.peer.device_id
+      )));
+    }
+
+    connection.set_encryption(Encryption::from_session(session)?);
     *self.connection.write().await = Some(connection);
     Ok(())
   }
@@ -97,7 +158,7 @@ impl ProjectionStream {
         // 使用 write 锁来获取可变引用
         let mut conn_guard = connection.write().await;
         if let Some(ref mut conn) = *conn_guard {
-          if let Err(e) = conn.send(&frame_data).await {
+          if let Err(e) = conn.send_frame(&frame_data).await {
             tracing::warn!("Failed to send frame: {}", e);
             // 如果连接失败，停止流式传输
             break;
@@ -147,7 +208,7 @@ impl ProjectionStream {
         let mut conn_guard = connection.write().await;
         if let Some(ref mut conn) = *conn_guard {
           // 接收数据
-          let data = match conn.receive().await {
+          let data = match conn.recv_frame().await {
             Ok(data) => data,
             Err(e) => {
               tracing::warn!("Failed to receive data: {}", e);
@@ -230,7 +291,7 @@ impl ProjectionStream {
   pub async fn close(&mut self) -> Result<()> {
     *self.is_streaming.write().await = false;
     if let Some(ref mut conn) = *self.connection.write().await {
-      conn.close()?;
+      conn.close().await?;
     }
     *self.connection.write().await = None;
     Ok(())